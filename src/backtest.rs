@@ -0,0 +1,284 @@
+//! Running a strategy over a historical block range, filling its trades at the pool price instead
+//! of executing them.
+
+use std::sync::Arc;
+
+use alloy::{eips::BlockId, providers::Provider};
+use anyhow::Context;
+use num_traits::ToPrimitive;
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::{
+    pool,
+    position::Position,
+    price,
+    quote::Quoter,
+    strategy::{self, Strategy, Trade},
+};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    from_block: u64,
+
+    /// Inclusive.
+    to_block: u64,
+
+    /// Backtest every combination of these parameters' values, each with its own strategy.
+    #[serde(default)]
+    sweep: Vec<Sweep>,
+}
+
+/// A strategy parameter backtested at each of several values.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    /// Dot separated path to the parameter in the strategy config, e.g. `ema.carry`.
+    parameter: String,
+
+    values: Vec<serde_json::Value>,
+}
+
+/// Feeds each block from `from_block` to `to_block` to the strategy built from `strategy`, once per
+/// combination of swept parameters, filling every trade at that block's pool price without price
+/// impact or fees, then logs a summary of the results.
+#[allow(clippy::too_many_arguments)]
+pub async fn run<P: Provider + Clone>(
+    config: &Config,
+    chain_id: u64,
+    pool_address: Option<Address>,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &P,
+    strategy: &serde_json::Value,
+    quoter: Arc<Quoter>,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        config.from_block <= config.to_block,
+        "Backtest from_block must not be after to_block"
+    );
+    let mut runs = build(strategy, &config.sweep)?
+        .into_iter()
+        .map(|(label, strategy)| (label, strategy, Fills::default()))
+        .collect::<Vec<_>>();
+    log::info!(
+        "Backtesting blocks {} to {} with {} strategies",
+        config.from_block,
+        config.to_block,
+        runs.len()
+    );
+    let needs_swaps = runs.iter().any(|(_, s, _)| s.needs_swaps());
+
+    let mut last_price = None;
+    let mut last_liquidity = None;
+
+    for block in config.from_block..=config.to_block {
+        let block_id = BlockId::from(block);
+        let pool = pool::fetch_traded(pool_address, chain_id, base, quote, fee, provider, block_id)
+            .await?;
+        let price_lossy = price::of_quote(&pool, quote)?;
+        if !(price_lossy.is_finite() && price_lossy > 0.) {
+            log::warn!("Skipping block {block}, invalid price {price_lossy}");
+            continue;
+        }
+
+        let context = strategy::TradeContext {
+            block_number: block,
+            price_lossy,
+            fee_lossy: pool.fee as u32 as f64 / 1_000_000.,
+            liquidity: pool.liquidity,
+            liquidity_delta: last_liquidity
+                .map_or(0, |last: u128| pool.liquidity as i128 - last as i128),
+            quoter: quoter.clone(),
+            pool: Some(Arc::new(pool.clone())),
+            swaps: if needs_swaps {
+                pool::swaps(provider, &pool, quote, block).await?
+            } else {
+                Vec::new()
+            },
+            balances: None,
+        };
+        last_liquidity = Some(pool.liquidity);
+        last_price = Some(price_lossy);
+
+        for (_, strategy, fills) in &mut runs {
+            fills.fill(strategy.as_mut(), &context);
+        }
+    }
+
+    let symbol = |c: &Currency| c.symbol().map_or("???", |v| v).to_string();
+    let (base_symbol, quote_symbol) = (symbol(base), symbol(quote));
+    for (label, _, fills) in &runs {
+        let prefix = if label.is_empty() {
+            String::new()
+        } else {
+            format!("{label}: ")
+        };
+        let net = fills.position.net_lossy();
+        log::info!("{prefix}Backtest finished with {} trades", fills.trades);
+        log::info!("{prefix}Base change: {:.6} {base_symbol}", fills.base_delta);
+        log::info!("{prefix}Position: {net:.6} {quote_symbol}");
+        log::info!(
+            "{prefix}Realized PnL: {:.6} {base_symbol}",
+            fills.position.realized_pnl()
+        );
+        if let Some(price) = last_price {
+            log::info!(
+                "{prefix}Ending notional at {price}: {:.6} {base_symbol}",
+                fills.pnl(price)
+            );
+        }
+    }
+    let Some(price) = last_price else {
+        log::warn!("No valid prices in the backtested range");
+        return Ok(());
+    };
+
+    if !config.sweep.is_empty() {
+        let runs = runs
+            .into_iter()
+            .map(|(label, _, fills)| (label, fills))
+            .collect::<Vec<_>>();
+        log::info!("Sweep results by PnL at {price}:");
+        for (label, pnl) in ranked(&runs, price) {
+            log::info!("  {pnl:>16.6} {base_symbol}  {label}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Trades filled for one strategy over the backtested range.
+#[derive(Default)]
+struct Fills {
+    position: Position,
+    trades: usize,
+
+    /// Change in the base currency from fills, the quote currency's change is the position.
+    base_delta: f64,
+}
+
+impl Fills {
+    /// Fills each of `strategy`'s trades for `context` at its price.
+    fn fill(&mut self, strategy: &mut dyn Strategy, context: &strategy::TradeContext) {
+        let (block, price_lossy) = (context.block_number, context.price_lossy);
+        for trade in strategy.trade(context) {
+            let amount = trade.amount();
+            let amount_lossy = amount.numerator.to_f64().unwrap_or(f64::NAN)
+                / amount.denominator.to_f64().unwrap_or(f64::NAN);
+            match trade {
+                Trade::Buy { .. } => self.base_delta -= amount_lossy * price_lossy,
+                Trade::Sell { .. } => self.base_delta += amount_lossy * price_lossy,
+            }
+            log::info!("Block {block}: filled {trade:?} at {price_lossy}");
+            self.position.apply(&trade, block, price_lossy);
+            self.trades += 1;
+        }
+    }
+
+    /// Change in the base currency, valuing the position at `price`.
+    fn pnl(&self, price: f64) -> f64 {
+        self.base_delta + self.position.net_lossy() * price
+    }
+}
+
+/// A strategy built from `config` for each combination of `sweeps`' values, labelled with them.
+fn build(
+    config: &serde_json::Value,
+    sweeps: &[Sweep],
+) -> anyhow::Result<Vec<(String, Box<dyn Strategy>)>> {
+    let mut combinations = vec![(Vec::new(), config.clone())];
+    for sweep in sweeps {
+        anyhow::ensure!(
+            !sweep.values.is_empty(),
+            "Sweep of {} has no values",
+            sweep.parameter
+        );
+        let pointer = format!("/{}", sweep.parameter.replace('.', "/"));
+        let mut next = Vec::with_capacity(combinations.len() * sweep.values.len());
+        for (labels, config) in &combinations {
+            for value in &sweep.values {
+                let mut config = config.clone();
+                *config.pointer_mut(&pointer).with_context(|| {
+                    format!("No {} in the strategy config to sweep", sweep.parameter)
+                })? = value.clone();
+
+                let mut labels = labels.clone();
+                labels.push(format!("{}={value}", sweep.parameter));
+                next.push((labels, config));
+            }
+        }
+        combinations = next;
+    }
+
+    combinations
+        .into_iter()
+        .map(|(labels, config)| {
+            let label = labels.join(", ");
+            let strategy = serde_json::from_value::<strategy::Config>(config)
+                .map_err(anyhow::Error::from)
+                .and_then(strategy::Config::into_dyn)
+                .with_context(|| format!("Sweeping {label}"))?;
+            Ok((label, strategy))
+        })
+        .collect()
+}
+
+/// Each run's label and PnL valuing its position at `price`, most profitable first.
+fn ranked(runs: &[(String, Fills)], price: f64) -> Vec<(&str, f64)> {
+    let mut ranked = runs
+        .iter()
+        .map(|(label, fills)| (label.as_str(), fills.pnl(price)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::strategy::testing::{context, quoter};
+
+    #[test]
+    fn sweep_reports_one_row_per_value() {
+        let config = json!({ "threshold": { "buy": { "at": 100.0, "amount": 1 } } });
+        let sweep = Sweep {
+            parameter: "threshold.buy.at".to_string(),
+            values: vec![json!(90.0), json!(100.0), json!(110.0)],
+        };
+        let quoter = quoter();
+
+        let mut runs = Vec::new();
+        for (label, mut strategy) in build(&config, &[sweep]).unwrap() {
+            let mut fills = Fills::default();
+            for (block, price) in [(1, 105.), (2, 95.), (3, 120.)] {
+                fills.fill(strategy.as_mut(), &context(&quoter, block, price));
+            }
+            runs.push((label, fills));
+        }
+
+        // Buying higher only ever catches more of the rise to 120.
+        assert_eq!(
+            ranked(&runs, 120.),
+            [
+                ("threshold.buy.at=110.0", 40.),
+                ("threshold.buy.at=100.0", 25.),
+                ("threshold.buy.at=90.0", 0.),
+            ]
+        );
+    }
+
+    #[test]
+    fn sweeping_a_missing_parameter_fails() {
+        let config = json!({ "threshold": { "buy": { "at": 100.0, "amount": 1 } } });
+        let sweep = Sweep {
+            parameter: "threshold.sell.at".to_string(),
+            values: vec![json!(1.0)],
+        };
+        assert!(build(&config, &[sweep]).is_err());
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::ErrorKind,
+    path::PathBuf,
+    time::Duration,
+};
+
+use alloy::{
+    eips::{BlockId, BlockNumberOrTag},
+    primitives::B256,
+    providers::Provider,
+    pubsub::SubscriptionStream,
+    rpc::types::Header,
+};
+use anyhow::Context;
+use futures::StreamExt;
+
+use crate::retry;
+
+/// Which state pool reads are made against.
+#[derive(serde::Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StateBlock {
+    /// The block being processed.
+    #[default]
+    Latest,
+
+    /// The node's pending block, fresher but may change or never be mined.
+    Pending,
+}
+
+impl StateBlock {
+    pub fn block_id(self, block: u64) -> BlockId {
+        match self {
+            StateBlock::Latest => BlockId::from(block),
+            StateBlock::Pending => BlockId::pending(),
+        }
+    }
+}
+
+/// How new blocks are noticed.
+pub enum NewBlocks {
+    /// Asking for the block number, waiting this long between asks.
+    Poll(Duration),
+
+    /// Headers pushed over a WebSocket subscription.
+    Subscribe(SubscriptionStream<Header>),
+}
+
+impl NewBlocks {
+    /// Subscribes for `ws://` and `wss://` URLs, polling every `poll_interval` otherwise.
+    pub async fn for_url(
+        rpc_url: &str,
+        provider: &impl Provider,
+        poll_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        if rpc_url.starts_with("ws://") || rpc_url.starts_with("wss://") {
+            log::info!("Subscribing to new blocks");
+            Ok(NewBlocks::Subscribe(
+                provider.subscribe_blocks().await?.into_stream(),
+            ))
+        } else {
+            Ok(NewBlocks::Poll(poll_interval))
+        }
+    }
+
+    /// The first block number that isn't `last`.
+    async fn next(
+        &mut self,
+        provider: &impl Provider,
+        last: Option<u64>,
+        retry: &retry::Config,
+    ) -> anyhow::Result<u64> {
+        match self {
+            NewBlocks::Poll(interval) => loop {
+                let n = retry
+                    .retry("block number", || provider.get_block_number())
+                    .await?;
+                if Some(n) != last {
+                    return Ok(n);
+                }
+                tokio::time::sleep(*interval).await;
+            },
+            NewBlocks::Subscribe(headers) => loop {
+                let header = headers.next().await.context("Block subscription closed")?;
+                if Some(header.number) != last {
+                    return Ok(header.number);
+                }
+            },
+        }
+    }
+
+    /// Waits before looking again after a block was rejected.
+    async fn back_off(&self) {
+        if let NewBlocks::Poll(interval) = self {
+            tokio::time::sleep(*interval).await;
+        }
+    }
+}
+
+/// How many recent block hashes are kept to confirm reorgs against.
+const REMEMBERED_HASHES: usize = 128;
+
+/// Tracks new blocks, ignoring a block number going backwards (e.g. a load balanced RPC hitting a
+/// lagging node) unless the chain actually reorged.
+pub struct BlockTracker {
+    new_blocks: NewBlocks,
+    retry: retry::Config,
+
+    last: Option<u64>,
+    highest: Option<u64>,
+
+    /// Hashes of recently accepted blocks.
+    hashes: BTreeMap<u64, B256>,
+
+    /// Where the last fully processed block is persisted.
+    last_block_file: Option<PathBuf>,
+
+    /// Last block processed before a restart, to catch up from on the first call to `next`.
+    resume_after: Option<u64>,
+
+    /// Missed blocks still to be caught up on, in order.
+    catch_up: VecDeque<u64>,
+}
+
+impl BlockTracker {
+    /// Persisting processed blocks to `last_block_file` if set, and when `catch_up` replaying the
+    /// blocks missed since the one it holds.
+    pub fn new(
+        new_blocks: NewBlocks,
+        retry: retry::Config,
+        last_block_file: Option<PathBuf>,
+        catch_up: bool,
+    ) -> anyhow::Result<Self> {
+        let resume_after = match (&last_block_file, catch_up) {
+            (Some(file), true) => match std::fs::read_to_string(file) {
+                Ok(s) => Some(
+                    s.trim()
+                        .parse()
+                        .context(format!("Invalid last block in {file:?}"))?,
+                ),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e).context(format!("Reading {file:?}")),
+            },
+            _ => None,
+        };
+
+        Ok(BlockTracker {
+            new_blocks,
+            retry,
+            last: None,
+            highest: None,
+            hashes: BTreeMap::new(),
+            last_block_file,
+            resume_after,
+            catch_up: VecDeque::new(),
+        })
+    }
+
+    /// The next block to process, the block previously returned has been fully processed.
+    pub async fn next(&mut self, provider: &impl Provider) -> anyhow::Result<u64> {
+        self.finish()?;
+
+        if let Some(after) = self.resume_after.take() {
+            let head = self
+                .retry
+                .retry("block number", || provider.get_block_number())
+                .await?;
+            self.catch_up = (after + 1..head).collect();
+            if !self.catch_up.is_empty() {
+                log::info!(
+                    "Catching up on {} blocks after {after}",
+                    self.catch_up.len()
+                );
+            }
+        }
+        while let Some(n) = self.catch_up.pop_front() {
+            if self.accept(provider, n).await? {
+                self.last = Some(n);
+                return Ok(n);
+            }
+        }
+
+        loop {
+            let n = self
+                .new_blocks
+                .next(provider, self.last, &self.retry)
+                .await?;
+            if self.accept(provider, n).await? {
+                self.last = Some(n);
+                return Ok(n);
+            }
+            self.new_blocks.back_off().await;
+        }
+    }
+
+    /// Persists the block last returned by `next` as fully processed.
+    pub fn finish(&self) -> anyhow::Result<()> {
+        if let (Some(file), Some(n)) = (&self.last_block_file, self.last) {
+            std::fs::write(file, n.to_string()).context(format!("Writing {file:?}"))?;
+        }
+        Ok(())
+    }
+
+    async fn accept(&mut self, provider: &impl Provider, n: u64) -> anyhow::Result<bool> {
+        let hash = self
+            .retry
+            .retry("block hash", || block_hash(provider, n))
+            .await?;
+        let Some(hash) = hash else {
+            log::warn!("Provider reported block {n} but doesn't have it, ignoring");
+            return Ok(false);
+        };
+        Ok(self.accept_hash(n, hash))
+    }
+
+    /// Whether block `n` with `hash` is new, or replaces a block the chain reorged away.
+    fn accept_hash(&mut self, n: u64, hash: B256) -> bool {
+        let highest = self.highest.unwrap_or(0);
+        if self.highest.is_some() && n < highest {
+            match self.hashes.get(&n) {
+                Some(seen) if *seen != hash => {
+                    log::warn!("Reorg confirmed at block {n}, was {seen} now {hash}");
+                    self.hashes.split_off(&n);
+                }
+                _ => {
+                    log::warn!("Ignoring stale block {n}, already seen up to {highest}");
+                    return false;
+                }
+            }
+        }
+
+        self.highest = Some(n);
+        self.hashes.insert(n, hash);
+        while self.hashes.len() > REMEMBERED_HASHES {
+            self.hashes.pop_first();
+        }
+        true
+    }
+}
+
+async fn block_hash(provider: &impl Provider, n: u64) -> anyhow::Result<Option<B256>> {
+    let block = provider
+        .get_block_by_number(BlockNumberOrTag::Number(n))
+        .await?;
+    Ok(block.map(|b| b.header.hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{keccak256, Address, Bytes, I256, U256},
+        providers::ProviderBuilder,
+        sol_types::SolValue,
+    };
+    use serde_json::json;
+    use uniswap_sdk_core::prelude::{Currency, Ether};
+    use uniswap_v3_sdk::prelude::FeeAmount;
+
+    use super::*;
+    use crate::{
+        mock_rpc::{self, MockRpc},
+        pool,
+        strategy::testing::usdc,
+    };
+
+    fn tracker() -> BlockTracker {
+        let new_blocks = NewBlocks::Poll(Duration::from_secs(1));
+        BlockTracker::new(new_blocks, retry::Config::default(), None, false).unwrap()
+    }
+
+    #[test]
+    fn decreasing_blocks_are_ignored() {
+        let mut tracker = tracker();
+        let hash = |n: u64| B256::with_last_byte(n as u8);
+
+        let accepted: Vec<_> = [10, 12, 11, 9, 13]
+            .into_iter()
+            .filter(|&n| tracker.accept_hash(n, hash(n)))
+            .collect();
+        assert_eq!(accepted, [10, 12, 13]);
+    }
+
+    #[test]
+    fn older_block_with_a_new_hash_is_a_reorg() {
+        let mut tracker = tracker();
+        for n in 10..=12 {
+            assert!(tracker.accept_hash(n, B256::with_last_byte(n as u8)));
+        }
+
+        assert!(tracker.accept_hash(11, B256::repeat_byte(0xff)));
+        // Blocks after the reorg are forgotten, so the old 12 is no longer stale.
+        assert!(tracker.accept_hash(12, B256::with_last_byte(12)));
+        assert!(!tracker.accept_hash(11, B256::repeat_byte(0xff)));
+    }
+
+    #[tokio::test]
+    async fn missed_blocks_are_caught_up_in_order() {
+        let rpc = MockRpc::serve(|method, params| match method {
+            "eth_blockNumber" => Ok(json!("0xe")),
+            "eth_getBlockByNumber" => {
+                let n = params[0].as_str().unwrap().trim_start_matches("0x");
+                Ok(mock_rpc::block(u64::from_str_radix(n, 16).unwrap()))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+
+        let file = std::env::temp_dir().join(format!("last_block_{}", std::process::id()));
+        std::fs::write(&file, "10").unwrap();
+        let new_blocks = NewBlocks::Poll(Duration::from_millis(1));
+        let mut tracker = BlockTracker::new(
+            new_blocks,
+            retry::Config::default(),
+            Some(file.clone()),
+            true,
+        )
+        .unwrap();
+
+        let mut processed = Vec::new();
+        for _ in 0..4 {
+            processed.push(tracker.next(&provider).await.unwrap());
+        }
+        // Blocks missed while down come first, then the head as usual.
+        assert_eq!(processed, [11, 12, 13, 14]);
+
+        tracker.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "14");
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pending_state_reads_use_the_pending_tag() {
+        let rpc = MockRpc::serve(|method, _| match method {
+            "eth_call" => {
+                let cumulatives = vec![I256::ZERO, I256::try_from(12_000).unwrap()];
+                let observed = (cumulatives, vec![U256::ZERO; 2]).abi_encode_params();
+                Ok(json!(Bytes::from(observed)))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let twap = |state: StateBlock| {
+            pool::twap_tick(
+                Address::repeat_byte(0x01),
+                60,
+                &provider,
+                state.block_id(10),
+            )
+        };
+
+        assert_eq!(twap(StateBlock::Latest).await.unwrap(), 200);
+        assert_eq!(twap(StateBlock::Pending).await.unwrap(), 200);
+        let tags: Vec<_> = rpc
+            .params("eth_call")
+            .iter()
+            .map(|p| p[1].clone())
+            .collect();
+        assert_eq!(tags, [json!("0xa"), json!("pending")]);
+    }
+
+    #[tokio::test]
+    async fn pending_pool_and_balance_reads_use_the_pending_tag() {
+        let selector = |signature: &str| keccak256(signature)[..4].to_vec();
+        let (slot0, liquidity, balance_of) = (
+            selector("slot0()"),
+            selector("liquidity()"),
+            selector("balanceOf(address)"),
+        );
+        let rpc = MockRpc::serve(move |method, params| match method {
+            "eth_call" => {
+                let call = &params[0];
+                let input: Bytes = serde_json::from_value(call["input"].clone())
+                    .or_else(|_| serde_json::from_value(call["data"].clone()))
+                    .map_err(|e| e.to_string())?;
+                let output = if input.starts_with(&slot0) {
+                    // A price of 1, the rest of slot0 zero.
+                    (U256::from(1) << 96, [U256::ZERO; 6]).abi_encode_params()
+                } else if input.starts_with(&liquidity) {
+                    U256::from(1_000).abi_encode()
+                } else if input.starts_with(&balance_of) {
+                    U256::from(5_000_000).abi_encode()
+                } else {
+                    return Err("Tick data isn't served".to_string());
+                };
+                Ok(json!(Bytes::from(output)))
+            }
+            "eth_getBalance" => Ok(json!("0xde0b6b3a7640000")),
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let block_id = StateBlock::Pending.block_id(10);
+        let ether = Currency::NativeCurrency(Ether::on_chain(1));
+
+        // Fails fetching tick data, after reading the pool's state.
+        let pool = pool::fetch_traded(
+            Some(Address::repeat_byte(0x01)),
+            1,
+            &usdc(),
+            &ether,
+            FeeAmount::LOW,
+            &provider,
+            block_id,
+        )
+        .await;
+        assert!(pool.is_err());
+        let balances = crate::balances(
+            &provider,
+            Address::repeat_byte(0xaa),
+            &usdc(),
+            &ether,
+            block_id,
+        )
+        .await
+        .unwrap();
+        assert_eq!((balances.base, balances.quote), (5., 1.));
+
+        let calls = rpc.params("eth_call");
+        assert!(calls.len() >= 3, "Expected slot0, liquidity and balanceOf");
+        assert!(calls.iter().all(|p| p[1] == json!("pending")));
+        assert_eq!(rpc.params("eth_getBalance")[0][1], json!("pending"));
+    }
+}
@@ -0,0 +1,2445 @@
+use std::{
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use alloy::{
+    consensus::Transaction as _,
+    eips::{BlockId, BlockNumberOrTag},
+    network::{Ethereum, EthereumWallet},
+    node_bindings::{Anvil, AnvilInstance},
+    primitives::{aliases::U160, TxHash, U256},
+    providers::{PendingTransactionBuilder, Provider, ProviderBuilder},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+    signers::local::PrivateKeySigner,
+    sol_types::SolCall,
+};
+use anyhow::Context;
+use num_traits::ToPrimitive;
+use tracing::Instrument;
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::{
+    funding, pool, price, prompt, strategy, user_operation,
+    venue::{self, Venue},
+};
+
+/// Turns the trades produced by a Strategy into transactions against the swap router.
+pub struct Executor {
+    pub chain_id: u64,
+    pub rpc_url: String,
+    /// The chain's wrapped native token, from `wrapped_native`.
+    pub weth: Option<Address>,
+
+    pub base: Currency,
+    pub quote: Currency,
+
+    /// Accounts trades are sent from in turn, impersonated on the fork. The fork's first dev
+    /// account when empty.
+    pub signers: Vec<Address>,
+    /// Index into `signers`, or `live_signers` when live, of the account for the next trade.
+    pub next_signer: AtomicUsize,
+
+    /// When set, swaps are submitted as UserOperations so gas is paid by the paymaster.
+    pub paymaster: Option<user_operation::Config>,
+
+    /// How long to wait for a sent transaction to be mined.
+    pub confirmation_timeout: Duration,
+
+    /// Ask the operator on stdin to approve each swap before sending it, skipping if they don't
+    /// answer within this long.
+    pub approval_timeout: Option<Duration>,
+
+    /// Cancel a transaction pending this long if the strategy no longer wants its trade.
+    pub cancel_after: Option<Duration>,
+
+    /// Halt trading if any trade executes this much worse than quoted.
+    pub max_realized_slippage_bps: Option<f64>,
+
+    /// Skip sells quoted to receive less than this.
+    pub min_out: Option<MinOut>,
+
+    /// How much of an ERC20 input to approve the router for when its allowance is too low.
+    pub approval: Approval,
+
+    /// Fixed gas parameters for swaps, instead of the node's estimates.
+    pub gas: Option<Gas>,
+
+    /// Skip swaps whose estimated gas cost is more than this share of the trade's value, as a
+    /// strategy's edge on a small trade can be less than the gas to make it.
+    pub max_gas_cost_bps: Option<f64>,
+    /// Base units per unit of the chain's native currency, for valuing gas when neither side of
+    /// the pair is wrapped native.
+    pub gas_token_price: Option<f64>,
+
+    /// Tolerance for the swap executing worse than quoted, before it reverts.
+    pub slippage_bps: u64,
+    pub retry: RetryPolicy,
+
+    /// Fee taken from the swap output by the router and sent to a recipient.
+    pub fee: Option<FeeOptions>,
+
+    /// Receive native currency instead of WETH, by unwrapping in the router's multicall.
+    pub unwrap_output: bool,
+
+    pub precision_loss: PrecisionLoss,
+
+    /// Refuse to trade when the account's native balance for gas is below this, unless a paymaster
+    /// pays for gas.
+    pub min_gas_balance: Option<Fraction>,
+
+    /// Tops up the account's balance of the trade's input currency before swapping.
+    pub funding: Option<funding::Config>,
+
+    /// Sign and broadcast swaps to `rpc_url` when set, instead of simulating them on a fork. Trades
+    /// are sent from each in turn.
+    pub live_signers: Vec<PrivateKeySigner>,
+
+    /// Tried when the swap through Uniswap fails, if the trade is still wanted.
+    pub fallback: Option<venue::Deployment>,
+
+    /// Pools to swap through from the base currency instead of the traded pool, as the token each
+    /// reaches and its fee. The last reaches the quote currency.
+    pub route: Vec<(Currency, FeeAmount)>,
+
+    /// Forks started ahead of time by `speculate_next`.
+    pub speculations: Mutex<Vec<Speculation>>,
+}
+
+/// Amount to approve the router to spend, when its allowance doesn't cover a swap.
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Approval {
+    /// The most the swap can spend.
+    #[default]
+    Exact,
+
+    /// Unlimited, so later swaps don't need their own approval.
+    Max,
+}
+
+/// Least base currency a sell must be quoted to receive before it's sent.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum MinOut {
+    /// In human units of the base currency.
+    Absolute(strategy::FractionInput),
+
+    /// Of the output expected at the pool's mid price, e.g. 0.99.
+    FractionOfMid(f64),
+}
+
+impl MinOut {
+    /// The minimum for selling `amount` of the quote currency at `mid`, in base per quote.
+    fn minimum(&self, amount: &Fraction, mid: f64) -> anyhow::Result<f64> {
+        Ok(match self {
+            MinOut::Absolute(min) => Fraction::from(*min).to_significant(18, None)?.parse()?,
+            MinOut::FractionOfMid(fraction) => {
+                amount.to_significant(18, None)?.parse::<f64>()? * mid * fraction
+            }
+        })
+    }
+}
+
+/// EIP-1559 gas parameters for swap transactions, each left to the node when unset.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Gas {
+    /// In wei.
+    max_fee_per_gas: Option<u128>,
+    /// In wei.
+    max_priority_fee_per_gas: Option<u128>,
+    gas_limit: Option<u64>,
+}
+
+impl Gas {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let (Some(max), Some(priority)) = (self.max_fee_per_gas, self.max_priority_fee_per_gas) {
+            anyhow::ensure!(
+                priority <= max,
+                "gas.max_priority_fee_per_gas {priority} is above gas.max_fee_per_gas {max}"
+            );
+        }
+        Ok(())
+    }
+
+    fn apply(&self, mut tx: TransactionRequest) -> TransactionRequest {
+        if let Some(max) = self.max_fee_per_gas {
+            tx = tx.max_fee_per_gas(max);
+        }
+        if let Some(priority) = self.max_priority_fee_per_gas {
+            tx = tx.max_priority_fee_per_gas(priority);
+        }
+        if let Some(limit) = self.gas_limit {
+            tx = tx.gas_limit(limit);
+        }
+        tx
+    }
+}
+
+/// How swaps that revert for insufficient output are retried.
+#[derive(Default)]
+pub struct RetryPolicy {
+    pub slippage_escalation: Option<SlippageEscalation>,
+
+    /// Retry with half the size.
+    pub adaptive_retry: bool,
+    pub min_retry_size: Option<Fraction>,
+}
+
+/// One attempt at a swap, repeated by `RetryPolicy::swap`.
+#[async_trait::async_trait(?Send)]
+trait SwapAttempt {
+    async fn swap(
+        &mut self,
+        trade: &strategy::Trade,
+        slippage_bps: u64,
+    ) -> anyhow::Result<(TransactionReceipt, TradeType, BigInt)>;
+}
+
+impl RetryPolicy {
+    /// Swaps `trade` with `attempt`, retrying swaps that fail for insufficient output. Returns the
+    /// trade as executed, which may be smaller than requested.
+    async fn swap(
+        &self,
+        trade: &strategy::Trade,
+        mut slippage_bps: u64,
+        attempt: &mut dyn SwapAttempt,
+    ) -> anyhow::Result<(strategy::Trade, TransactionReceipt, TradeType, BigInt)> {
+        let mut trade = trade.clone();
+        let mut retries = 0;
+        loop {
+            let result = attempt.swap(&trade, slippage_bps).await;
+            let e = match result {
+                Ok((receipt, trade_type, quoted)) => {
+                    return Ok((trade, receipt, trade_type, quoted))
+                }
+                Err(e) => e,
+            };
+
+            if !is_insufficient_output(&e) {
+                return Err(e);
+            }
+
+            let fixed_slippage = trade.options().slippage_tolerance.is_some();
+            if let (Some(escalation), false) = (&self.slippage_escalation, fixed_slippage) {
+                if slippage_bps < escalation.max_bps {
+                    let escalated = (slippage_bps + escalation.step_bps).min(escalation.max_bps);
+                    log::warn!(
+                        "Swap failed for insufficient output ({e:#}), escalating slippage tolerance from {slippage_bps} to {escalated} bps"
+                    );
+                    slippage_bps = escalated;
+                    continue;
+                }
+            }
+
+            if !self.adaptive_retry || retries >= MAX_ADAPTIVE_RETRIES {
+                return Err(e);
+            }
+
+            let amount = trade.amount();
+            let smaller = Fraction::new(amount.numerator.clone(), amount.denominator.clone() * 2);
+            if let Some(min) = &self.min_retry_size {
+                if smaller.numerator.clone() * &min.denominator
+                    < min.numerator.clone() * &smaller.denominator
+                {
+                    return Err(e.context("Not retrying below min_retry_size"));
+                }
+            }
+
+            retries += 1;
+            log::warn!(
+                "Swap failed for insufficient output ({e:#}), retry {retries} with amount {}",
+                smaller.to_significant(6, None)?
+            );
+            trade = trade.with_amount(smaller);
+        }
+    }
+}
+
+/// A swap on `venue` from `account`.
+struct VenueSwap<'a, P> {
+    executor: &'a Executor,
+    provider: &'a P,
+    account: Address,
+    venue: &'a dyn Venue,
+    pool: Pool<EphemeralTickMapDataProvider>,
+    still_wanted: &'a mut dyn StillWanted,
+}
+
+#[async_trait::async_trait(?Send)]
+impl<P: Provider> SwapAttempt for VenueSwap<'_, P> {
+    async fn swap(
+        &mut self,
+        trade: &strategy::Trade,
+        slippage_bps: u64,
+    ) -> anyhow::Result<(TransactionReceipt, TradeType, BigInt)> {
+        self.executor
+            .swap(
+                self.provider,
+                self.account,
+                self.venue,
+                trade,
+                self.pool.clone(),
+                slippage_bps,
+                &mut *self.still_wanted,
+            )
+            .await
+    }
+}
+
+/// The signer whose turn it is, advancing `next` to the one after. None without signers.
+fn round_robin<T: Clone>(signers: &[T], next: &AtomicUsize) -> Option<T> {
+    if signers.is_empty() {
+        return None;
+    }
+    let i = next.fetch_add(1, Ordering::Relaxed) % signers.len();
+    Some(signers[i].clone())
+}
+
+/// A provider signing with only `signer`'s key, so it fills in that account's own nonce.
+async fn live_provider(rpc_url: &str, signer: PrivateKeySigner) -> anyhow::Result<impl Provider> {
+    Ok(ProviderBuilder::new()
+        .wallet(EthereumWallet::from(signer))
+        .on_builtin(rpc_url)
+        .await?)
+}
+
+/// Swapping on one venue after another, for `swap_with_fallback`.
+#[async_trait::async_trait(?Send)]
+trait VenueAttempt {
+    type Swapped;
+
+    async fn swap_on(&mut self, venue: &dyn Venue) -> anyhow::Result<Self::Swapped>;
+
+    async fn still_wanted(&mut self) -> anyhow::Result<bool>;
+}
+
+/// Swaps with `attempt` on `primary`, then on `fallback` if that fails and the trade is still
+/// wanted. Returns the name of the venue that executed it.
+async fn swap_with_fallback<A: VenueAttempt>(
+    attempt: &mut A,
+    primary: &dyn Venue,
+    fallback: Option<&dyn Venue>,
+) -> anyhow::Result<(String, A::Swapped)> {
+    let e = match attempt.swap_on(primary).await {
+        Ok(swapped) => return Ok((primary.name(), swapped)),
+        Err(e) => e,
+    };
+    let Some(fallback) = fallback else {
+        return Err(e);
+    };
+    if !attempt.still_wanted().await? {
+        return Err(e);
+    }
+
+    log::warn!(
+        "Swap on {} failed ({e:#}), trying {}",
+        primary.name(),
+        fallback.name()
+    );
+    let swapped = attempt
+        .swap_on(fallback)
+        .await
+        .context(format!("Fallback to {} failed", fallback.name()))?;
+    Ok((fallback.name(), swapped))
+}
+
+/// A trade from `account` on whichever venue, with retries.
+struct VenueFallback<'a, P> {
+    executor: &'a Executor,
+    provider: &'a P,
+    account: Address,
+    trade: &'a strategy::Trade,
+
+    /// The primary venue's pool, already fetched. Other venues' pools are fetched as needed.
+    pool: Option<Pool<EphemeralTickMapDataProvider>>,
+    fee: FeeAmount,
+    block: u64,
+
+    still_wanted: &'a mut dyn StillWanted,
+}
+
+#[async_trait::async_trait(?Send)]
+impl<P: Provider> VenueAttempt for VenueFallback<'_, P> {
+    type Swapped = (strategy::Trade, TransactionReceipt, TradeType, BigInt);
+
+    async fn swap_on(&mut self, venue: &dyn Venue) -> anyhow::Result<Self::Swapped> {
+        let executor = self.executor;
+        let pool = match self.pool.take() {
+            Some(pool) => pool,
+            None => venue
+                .pool(
+                    self.provider.root(),
+                    &executor.base,
+                    &executor.quote,
+                    self.fee,
+                    self.block,
+                )
+                .await
+                .context(format!("Fetching pool on {}", venue.name()))?,
+        };
+        executor
+            .swap_with_retries(
+                self.provider,
+                self.account,
+                venue,
+                self.trade,
+                pool,
+                &mut *self.still_wanted,
+            )
+            .await
+    }
+
+    async fn still_wanted(&mut self) -> anyhow::Result<bool> {
+        self.still_wanted.still_wanted(self.trade).await
+    }
+}
+
+/// Retry swaps that revert for insufficient output with a higher slippage tolerance.
+pub struct SlippageEscalation {
+    pub step_bps: u64,
+    pub max_bps: u64,
+}
+
+/// A fork of whichever block follows `after`, started before the bot has seen that block.
+pub struct Speculation<F = AnvilInstance> {
+    after: u64,
+    handle: tokio::task::JoinHandle<anyhow::Result<(u64, F)>>,
+}
+
+alloy::sol! {
+    #[sol(rpc)]
+    interface ERC20 {
+        function balanceOf(address target) returns (uint256);
+        function transferFrom(address from, address to, uint256 amount) returns (bool);
+        function allowance(address owner, address spender) returns (uint256);
+        function approve(address spender, uint256 amount) returns (bool);
+    }
+
+    /// Calls the SDK's swap calldata is extended with.
+    interface SwapRouter {
+        function multicall(bytes[] data) returns (bytes[] results);
+        function unwrapWETH9(uint256 amountMinimum, address recipient);
+        function unwrapWETH9WithFee(
+            uint256 amountMinimum,
+            address recipient,
+            uint256 feeBips,
+            address feeRecipient
+        );
+    }
+
+    interface UniswapV3Pool {
+        event Swap(
+            address indexed sender,
+            address indexed recipient,
+            int256 amount0,
+            int256 amount1,
+            uint160 sqrtPriceX96,
+            uint128 liquidity,
+            int24 tick
+        );
+    }
+}
+
+/// Decides whether a trade whose transaction is stuck pending should still go through.
+#[async_trait::async_trait(?Send)]
+pub trait StillWanted {
+    async fn still_wanted(&mut self, trade: &strategy::Trade) -> anyhow::Result<bool>;
+}
+
+/// For trades that must go through regardless, like flattening the position.
+pub struct AlwaysWanted;
+
+#[async_trait::async_trait(?Send)]
+impl StillWanted for AlwaysWanted {
+    async fn still_wanted(&mut self, _: &strategy::Trade) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// How often a speculation polls for the block it will fork.
+const SPECULATION_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Percent increase of fees for a cancelling transaction, nodes require at least 10% to replace.
+const CANCEL_FEE_BUMP_PERCENT: u128 = 50;
+
+/// Retries with a smaller size stop after this many attempts, even above `min_retry_size`.
+const MAX_ADAPTIVE_RETRIES: usize = 5;
+
+pub struct Execution {
+    /// The trade as executed, which may be smaller than requested.
+    pub trade: strategy::Trade,
+
+    /// Account holding the funds traded, the paymaster's smart account if there is one.
+    pub account: Address,
+
+    /// Paid into the pool, in the currency spent.
+    pub spent: CurrencyAmount<Currency>,
+    /// Paid out of the pool, in the currency received.
+    pub received: CurrencyAmount<Currency>,
+
+    /// Gas paid by the account for the swap, in wei of the chain's native currency.
+    pub gas_cost_wei: u128,
+
+    pub tx_hash: TxHash,
+
+    /// The account's balances immediately before and after the swap.
+    pub balances_before: strategy::Balances,
+    pub balances_after: strategy::Balances,
+
+    pub after_trade: ControlFlow<String>,
+}
+
+impl Execution {
+    fn amount_lossy(amount: &CurrencyAmount<Currency>) -> anyhow::Result<f64> {
+        Ok(amount.to_exact().parse()?)
+    }
+
+    /// Base units paid or received per quote unit.
+    pub fn price_lossy(&self) -> anyhow::Result<f64> {
+        let (spent, received) = (
+            Self::amount_lossy(&self.spent)?,
+            Self::amount_lossy(&self.received)?,
+        );
+        Ok(match self.trade {
+            strategy::Trade::Buy { .. } => spent / received,
+            strategy::Trade::Sell { .. } => received / spent,
+        })
+    }
+
+    pub fn summary(&self) -> anyhow::Result<String> {
+        let symbol = |a: &CurrencyAmount<Currency>| {
+            a.meta().currency.symbol().map_or("???", |v| v).to_string()
+        };
+        Ok(format!(
+            "{} spent {} {} → received {} {} (price {})",
+            self.account,
+            self.spent.to_exact(),
+            symbol(&self.spent),
+            self.received.to_exact(),
+            symbol(&self.received),
+            self.price_lossy()?,
+        ))
+    }
+}
+
+impl Executor {
+    pub async fn execute(
+        &self,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+        block: u64,
+        still_wanted: &mut dyn StillWanted,
+    ) -> anyhow::Result<Execution> {
+        if let Some(signer) = round_robin(&self.live_signers, &self.next_signer) {
+            let account = signer.address();
+            let provider = live_provider(&self.rpc_url, signer).await?;
+            return self
+                .execute_on(&provider, account, trade, pool, block, still_wanted)
+                .await;
+        }
+
+        let anvil = self
+            .fork(block)
+            .instrument(tracing::info_span!("fork", block))
+            .await?;
+        let provider = ProviderBuilder::new().on_http(anvil.endpoint_url());
+
+        // The provider's nonce filler tracks each account's nonce separately.
+        let account = match round_robin(&self.signers, &self.next_signer) {
+            None => provider.get_accounts().await?[0],
+            Some(signer) => {
+                provider
+                    .raw_request::<_, ()>("anvil_impersonateAccount".into(), (signer,))
+                    .await?;
+                signer
+            }
+        };
+        self.execute_on(&provider, account, trade, pool, block, still_wanted)
+            .await
+    }
+
+    /// The account the next live swap is sent from.
+    pub fn live_account(&self) -> Option<Address> {
+        if self.live_signers.is_empty() {
+            return None;
+        }
+        let i = self.next_signer.load(Ordering::Relaxed) % self.live_signers.len();
+        Some(self.live_signers[i].address())
+    }
+
+    /// The account holding the funds the next live swap trades.
+    pub fn live_holder(&self) -> Option<Address> {
+        self.live_account().map(|account| self.holder(account))
+    }
+
+    /// Every account live swaps are sent from.
+    pub fn live_accounts(&self) -> Vec<Address> {
+        self.live_signers.iter().map(|s| s.address()).collect()
+    }
+
+    /// The account holding the funds traded by swaps `account` sends, the paymaster's smart
+    /// account if there is one.
+    pub fn holder(&self, account: Address) -> Address {
+        self.paymaster
+            .as_ref()
+            .map_or(account, user_operation::Config::sender)
+    }
+
+    async fn execute_on(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+        block: u64,
+        still_wanted: &mut dyn StillWanted,
+    ) -> anyhow::Result<Execution> {
+        let chain_id = self.chain_id;
+        let (base, quote) = (&self.base, &self.quote);
+        let fee = pool.fee;
+
+        // Live trades continue on the next block, as does a fresh fork for each trade.
+        let mut after_trade = ControlFlow::Continue(());
+        log::info!("Trading from {account}");
+
+        if let (Some(min), None) = (&self.min_gas_balance, &self.paymaster) {
+            ensure_gas_balance(provider, account, chain_id, min, self.precision_loss).await?;
+        }
+
+        if let Some(funding) = &self.funding {
+            let (input, is_base) = match trade {
+                strategy::Trade::Buy { .. } => (base, true),
+                strategy::Trade::Sell { .. } => (quote, false),
+            };
+            funding
+                .top_up(
+                    provider,
+                    account,
+                    input,
+                    is_base,
+                    self.confirmation_timeout,
+                    self.precision_loss,
+                )
+                .await?;
+        }
+
+        if self.unwrap_output && !self.unwraps(trade) {
+            log::warn!(
+                "Ignoring unwrap_output, output {} isn't WETH",
+                self.output_token(trade).symbol().map_or("???", |v| v)
+            );
+        }
+
+        let holder = self.holder(account);
+        let balances_before = log_balances("before trade", holder, base, quote, provider).await?;
+
+        let primary = venue::Uniswap { chain_id };
+        let fallback = self.fallback.as_ref().map(|v| v as &dyn Venue);
+        let mut attempt = VenueFallback {
+            executor: self,
+            provider,
+            account,
+            trade,
+            pool: Some(pool),
+            fee,
+            block,
+            still_wanted,
+        };
+        let (venue, (trade, receipt, trade_type, quoted)) =
+            swap_with_fallback(&mut attempt, &primary, fallback).await?;
+        log::info!(
+            "Successfully executed transaction {} on {venue}",
+            receipt.transaction_hash
+        );
+
+        let (spent, received) = swap_amounts(&receipt)?;
+        let slippage_bps = realized_slippage_bps(trade_type, &quoted, &spent, &received)?;
+        log::info!("Realized slippage {slippage_bps:.2} bps versus the pre-trade quote");
+        if let Some(halt) = slippage_halt(slippage_bps, self.max_realized_slippage_bps) {
+            after_trade = ControlFlow::Break(halt);
+        }
+
+        let balances_after = log_balances("after trade", holder, base, quote, provider).await?;
+
+        let spent_currency = match trade {
+            strategy::Trade::Buy { .. } => base,
+            strategy::Trade::Sell { .. } => quote,
+        };
+        let gas_cost_wei = match self.paymaster {
+            Some(_) => 0,
+            None => receipt.gas_used as u128 * receipt.effective_gas_price,
+        };
+
+        Ok(Execution {
+            account: holder,
+            gas_cost_wei,
+            tx_hash: receipt.transaction_hash,
+            balances_before,
+            balances_after,
+            spent: CurrencyAmount::from_raw_amount(spent_currency.clone(), spent)?,
+            received: CurrencyAmount::from_raw_amount(self.output_currency(&trade), received)?,
+            trade,
+            after_trade,
+        })
+    }
+
+    /// The pools of `route` on `venue`, from the base currency.
+    async fn route_pools(
+        &self,
+        provider: &impl Provider,
+        venue: &dyn Venue,
+    ) -> anyhow::Result<Vec<Pool<EphemeralTickMapDataProvider>>> {
+        let block = provider.get_block_number().await?;
+        let mut from = &self.base;
+        let mut pools = Vec::with_capacity(self.route.len());
+        for (to, fee) in &self.route {
+            pools.push(venue.pool(provider.root(), from, to, *fee, block).await?);
+            from = to;
+        }
+        Ok(pools)
+    }
+
+    /// Approves `router` to spend `amount` from `account` if its allowance is lower, unless it's
+    /// native currency sent as value.
+    async fn ensure_allowance(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        router: Address,
+        amount: &CurrencyAmount<Currency>,
+    ) -> anyhow::Result<()> {
+        let Some(approve) = self
+            .approval_call(provider, account, router, amount)
+            .await?
+        else {
+            return Ok(());
+        };
+        let tx = TransactionRequest::default()
+            .from(account)
+            .to(approve.to)
+            .input(approve.input.into());
+        let pending = provider.send_transaction(tx).await?;
+        let receipt = confirm(provider, pending, self.confirmation_timeout).await?;
+        anyhow::ensure!(
+            receipt.status(),
+            "Approval transaction {} reverted",
+            receipt.transaction_hash
+        );
+        Ok(())
+    }
+
+    /// The call approving `router` to spend `amount` from `account`, None if its allowance is
+    /// already enough or it's native currency sent as value.
+    async fn approval_call(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        router: Address,
+        amount: &CurrencyAmount<Currency>,
+    ) -> anyhow::Result<Option<user_operation::Call>> {
+        let Currency::Token(token) = &amount.meta().currency else {
+            return Ok(None);
+        };
+        let needed = amount.quotient().to_string().parse::<U256>()?;
+
+        let erc20 = ERC20::new(token.address(), provider);
+        let allowance = erc20.allowance(account, router).call().await?._0;
+        if allowance >= needed {
+            return Ok(None);
+        }
+
+        let approve = match self.approval {
+            Approval::Exact => needed,
+            Approval::Max => U256::MAX,
+        };
+        log::info!(
+            "Approving {router} to spend {approve} {} from {account}, allowance is {allowance}",
+            token.symbol().map_or("???", |v| v)
+        );
+        Ok(Some(user_operation::Call {
+            to: token.address(),
+            value: U256::ZERO,
+            input: ERC20::approveCall {
+                spender: router,
+                amount: approve,
+            }
+            .abi_encode()
+            .into(),
+        }))
+    }
+
+    /// Errors if sending `tx` is estimated to cost more than `max_bps` of `notional`, the trade's
+    /// value in the base currency.
+    async fn check_gas_cost(
+        &self,
+        provider: &impl Provider,
+        tx: &TransactionRequest,
+        trade: &strategy::Trade,
+        notional: f64,
+        max_bps: f64,
+    ) -> anyhow::Result<()> {
+        let gas = provider
+            .estimate_gas(tx.clone())
+            .await
+            .context("Estimating gas")?;
+        let gas_price = provider.get_gas_price().await?;
+
+        let amount = trade.amount().to_significant(18, None)?.parse::<f64>()?;
+        let price = notional / amount;
+        let cost = gas_in_base(
+            gas as u128 * gas_price,
+            self.weth,
+            &self.base,
+            &self.quote,
+            price,
+            self.gas_token_price,
+        )
+        .context("Can't value gas in the base currency, set gas_token_price")?;
+
+        let cost_bps = cost / notional * 10_000.;
+        anyhow::ensure!(
+            cost_bps <= max_bps,
+            "Estimated gas cost {cost:.6} is {cost_bps:.2} bps of the trade, above maximum {max_bps} bps"
+        );
+        log::info!("Estimated gas cost {cost:.6}, {cost_bps:.2} bps of the trade");
+        Ok(())
+    }
+
+    /// Swaps `trade` on `venue`, retrying swaps that fail for insufficient output as configured.
+    /// Returns the trade as executed, which may be smaller than requested.
+    async fn swap_with_retries(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        venue: &dyn Venue,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+        still_wanted: &mut dyn StillWanted,
+    ) -> anyhow::Result<(strategy::Trade, TransactionReceipt, TradeType, BigInt)> {
+        let mut attempt = VenueSwap {
+            executor: self,
+            provider,
+            account,
+            venue,
+            pool,
+            still_wanted,
+        };
+        self.retry
+            .swap(trade, self.slippage_bps, &mut attempt)
+            .await
+    }
+
+    /// Starts forking the block after `block` as soon as it's produced, so a trade on it doesn't
+    /// wait for anvil to start. Called after trading on `block`, as trades tend to follow trades.
+    pub fn speculate_next(&self, block: u64) {
+        let rpc_url = self.rpc_url.clone();
+        speculate(&self.speculations, block, || {
+            tokio::spawn(async move {
+                let provider = ProviderBuilder::new().on_builtin(&rpc_url).await?;
+                let next = loop {
+                    let n = provider.get_block_number().await?;
+                    if n > block {
+                        break n;
+                    }
+                    tokio::time::sleep(SPECULATION_POLL_INTERVAL).await;
+                };
+                let anvil =
+                    tokio::task::spawn_blocking(move || spawn_fork(rpc_url, next)).await??;
+                Ok((next, anvil))
+            })
+        });
+    }
+
+    /// Shuts down speculative forks of blocks before `block`, which went without a trade.
+    pub fn discard_speculations(&self, block: u64) {
+        discard_stale(&mut self.speculations.lock().unwrap(), block);
+    }
+
+    /// A fork at `block`, from a matching speculation if there is one.
+    async fn fork(&self, block: u64) -> anyhow::Result<AnvilInstance> {
+        if let Some(anvil) = speculative_fork(&self.speculations, block).await {
+            return Ok(anvil);
+        }
+
+        log::info!("Forking chain {} at {block}", self.chain_id);
+        let rpc_url = self.rpc_url.clone();
+        tokio::task::spawn_blocking(move || spawn_fork(rpc_url, block)).await?
+    }
+
+    /// The SDK trade swapping `trade` through `pool`, or `route` on `venue` if set, with the
+    /// `sqrtPriceLimitX96` for its limit price.
+    async fn build_swap(
+        &self,
+        provider: &impl Provider,
+        venue: &dyn Venue,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+    ) -> anyhow::Result<(
+        Trade<Currency, Currency, EphemeralTickMapDataProvider>,
+        TradeType,
+        Option<U160>,
+    )> {
+        let sqrt_price_limit_x96 = trade
+            .limit_price()
+            .map(|price| pool::sqrt_price_limit(&pool, &self.quote, price))
+            .transpose()?;
+
+        let pools = if self.route.is_empty() {
+            vec![pool]
+        } else {
+            anyhow::ensure!(
+                sqrt_price_limit_x96.is_none(),
+                "Limit prices aren't supported on multi-hop routes"
+            );
+            self.route_pools(provider, venue).await?
+        };
+
+        let (swap, trade_type) =
+            swap_through(trade, pools, (&self.base, &self.quote), self.precision_loss)?;
+
+        Ok((swap, trade_type, sqrt_price_limit_x96))
+    }
+
+    async fn swap_options(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        trade: &strategy::Trade,
+        sqrt_price_limit_x96: Option<U160>,
+        slippage_bps: u64,
+    ) -> anyhow::Result<SwapOptions> {
+        let mut swap_options = router_options(
+            account,
+            trade,
+            sqrt_price_limit_x96,
+            slippage_bps,
+            self.fee.clone(),
+        );
+        if let Some(secs) = trade.options().deadline_secs {
+            let latest = provider
+                .get_block_by_number(BlockNumberOrTag::Latest)
+                .await?
+                .context("No latest block to set the deadline from")?;
+            swap_options.deadline = U256::from(latest.header.timestamp + secs);
+        }
+        Ok(swap_options)
+    }
+
+    /// Logs the transaction that `account` would send to swap `trade` through `pool`, without
+    /// forking or sending it. Without an account, from the first fork signer if there is one.
+    pub async fn dry_run(
+        &self,
+        provider: &impl Provider,
+        account: Option<Address>,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+    ) -> anyhow::Result<()> {
+        let venue = venue::Uniswap {
+            chain_id: self.chain_id,
+        };
+        let router = venue.router()?;
+        let account = account
+            .or(self.signers.first().copied())
+            .unwrap_or(Address::ZERO);
+        let (swap, _, sqrt_price_limit_x96) =
+            self.build_swap(provider, &venue, trade, pool).await?;
+        let swap_options = self
+            .swap_options(
+                provider,
+                self.holder(account),
+                trade,
+                sqrt_price_limit_x96,
+                self.slippage_bps,
+            )
+            .await?;
+        let params = swap_parameters(swap, swap_options, self.unwraps(trade))?;
+
+        log::info!("Dry run of {trade:?}, not sending");
+        log::info!("  to: {router}");
+        log::info!("  from: {account}");
+        log::info!("  value: {}", params.value);
+        log::info!("  calldata: {}", params.calldata);
+        Ok(())
+    }
+
+    /// Sends a single swap for `trade`, returning its receipt along with the pre-trade quote of
+    /// the side that isn't fixed, and so is subject to slippage.
+    async fn swap(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        venue: &dyn Venue,
+        trade: &strategy::Trade,
+        pool: Pool<EphemeralTickMapDataProvider>,
+        slippage_bps: u64,
+        still_wanted: &mut dyn StillWanted,
+    ) -> anyhow::Result<(TransactionReceipt, TradeType, BigInt)> {
+        let router = venue.router()?;
+        let mid = price::of_quote(&pool, &self.quote)?;
+        let (swap, trade_type, sqrt_price_limit_x96) =
+            self.build_swap(provider, venue, trade, pool).await?;
+
+        let quoted = match trade_type {
+            TradeType::ExactInput => swap.output_amount()?,
+            TradeType::ExactOutput => swap.input_amount()?,
+        };
+
+        if let Some(timeout) = self.approval_timeout {
+            let proposal = format!(
+                "Proposed {trade:?} on {}, quoted {} {}",
+                venue.name(),
+                quoted.to_exact(),
+                quoted.meta().currency.symbol().map_or("???", |v| v)
+            );
+            anyhow::ensure!(
+                prompt::approve_on_stdin(&proposal, timeout).await?,
+                "Rejected by operator"
+            );
+        }
+        let notional = Execution::amount_lossy(&quoted)?;
+        if let (Some(min_out), TradeType::ExactInput) = (&self.min_out, trade_type) {
+            let min = min_out.minimum(trade.amount(), mid)?;
+            log::info!("Quoted output {notional}, min_out {min}");
+            anyhow::ensure!(
+                notional >= min,
+                "Skipping, quoted output {notional} is below min_out {min}"
+            );
+        }
+        let quoted = quoted.quotient();
+
+        // With a paymaster the smart account holds the funds, so it approves and receives.
+        let holder = self.holder(account);
+        let swap_options = self
+            .swap_options(provider, holder, trade, sqrt_price_limit_x96, slippage_bps)
+            .await?;
+        let max_in = swap.maximum_amount_in(swap_options.slippage_tolerance.clone(), None)?;
+        let approval = match self.paymaster {
+            Some(_) => {
+                self.approval_call(provider, holder, router, &max_in)
+                    .await?
+            }
+            None => {
+                self.ensure_allowance(provider, account, router, &max_in)
+                    .await?;
+                None
+            }
+        };
+        let params = swap_parameters(swap, swap_options, self.unwraps(trade))?;
+        // Batched after its approval, if it needs one.
+        let calls = approval
+            .into_iter()
+            .chain([user_operation::Call {
+                to: router,
+                value: params.value,
+                input: params.calldata.clone(),
+            }])
+            .collect();
+
+        let owner = self.live_signers.iter().find(|s| s.address() == account);
+        let receipt = match (&self.paymaster, owner) {
+            (Some(paymaster), Some(owner)) => {
+                paymaster
+                    .execute(provider, owner, calls, self.confirmation_timeout)
+                    .await?
+            }
+            (Some(paymaster), None) => {
+                paymaster
+                    .execute_on_fork(provider, account, calls, self.confirmation_timeout)
+                    .await?
+            }
+            (None, _) => {
+                let mut tx = TransactionRequest::default()
+                    .from(account)
+                    .to(router)
+                    .input(params.calldata.into())
+                    .value(params.value);
+                if let Some(gas) = &self.gas {
+                    tx = gas.apply(tx);
+                }
+                if let Some(max_bps) = self.max_gas_cost_bps {
+                    self.check_gas_cost(provider, &tx, trade, notional, max_bps)
+                        .await?;
+                }
+                let pending = provider.send_transaction(tx).await?;
+                confirm_or_cancel(
+                    provider,
+                    account,
+                    pending,
+                    trade,
+                    self.cancel_after,
+                    self.confirmation_timeout,
+                    still_wanted,
+                )
+                .await?
+            }
+        };
+        anyhow::ensure!(
+            receipt.status(),
+            "Transaction {} reverted",
+            receipt.transaction_hash
+        );
+
+        Ok((receipt, trade_type, quoted))
+    }
+
+    /// Currency the pool pays out for `trade`.
+    fn output_token(&self, trade: &strategy::Trade) -> &Currency {
+        match trade {
+            strategy::Trade::Buy { .. } => &self.quote,
+            strategy::Trade::Sell { .. } => &self.base,
+        }
+    }
+
+    /// Whether the WETH output of the swap for `trade` is unwrapped to native currency.
+    fn unwraps(&self, trade: &strategy::Trade) -> bool {
+        self.unwrap_output
+            && unwrapped(self.output_token(trade), self.chain_id, self.weth).is_some()
+    }
+
+    /// Currency the account receives for `trade`, native rather than WETH when unwrapping.
+    fn output_currency(&self, trade: &strategy::Trade) -> Currency {
+        let output = self.output_token(trade);
+        match unwrapped(output, self.chain_id, self.weth) {
+            Some(native) if self.unwrap_output => native,
+            _ => output.clone(),
+        }
+    }
+}
+
+/// Address of the chain's wrapped native token, `configured` or else the SDK's default.
+pub fn wrapped_native(chain_id: u64, configured: Option<Address>) -> Option<Address> {
+    configured.or_else(|| WETH9::on_chain(chain_id).map(|w| w.address()))
+}
+
+/// The native currency of `chain_id`, if `currency` is its wrapped native token `weth`.
+fn unwrapped(currency: &Currency, chain_id: u64, weth: Option<Address>) -> Option<Currency> {
+    match currency {
+        Currency::Token(t) if Some(t.address()) == weth => {
+            Some(Currency::NativeCurrency(Ether::on_chain(chain_id)))
+        }
+        _ => None,
+    }
+}
+
+/// The fork speculated for `block`, if it forked that block rather than a later one.
+async fn speculative_fork<F>(speculations: &Mutex<Vec<Speculation<F>>>, block: u64) -> Option<F> {
+    let speculation = {
+        let mut speculations = speculations.lock().unwrap();
+        discard_stale(&mut speculations, block);
+        speculations
+            .iter()
+            .position(|s| s.after + 1 == block)
+            .map(|i| speculations.remove(i))
+    }?;
+
+    match speculation.handle.await {
+        Ok(Ok((forked, fork))) if forked == block => {
+            log::info!("Using speculative fork of block {block}");
+            return Some(fork);
+        }
+        Ok(Ok((forked, _))) => {
+            log::info!("Discarding speculative fork of {forked}, trading on {block}")
+        }
+        Ok(Err(e)) => log::warn!("Speculative fork failed: {e:#}"),
+        Err(e) => log::warn!("Speculative fork failed: {e}"),
+    }
+    None
+}
+
+/// Starts a speculation after `block` with `spawn`, unless one was already started.
+fn speculate<F>(
+    speculations: &Mutex<Vec<Speculation<F>>>,
+    block: u64,
+    spawn: impl FnOnce() -> tokio::task::JoinHandle<anyhow::Result<(u64, F)>>,
+) {
+    let mut speculations = speculations.lock().unwrap();
+    discard_stale(&mut speculations, block);
+    if speculations.iter().all(|s| s.after != block) {
+        speculations.push(Speculation {
+            after: block,
+            handle: spawn(),
+        });
+    }
+}
+
+/// Drops speculations for blocks before `block`, shutting down their forks.
+fn discard_stale<F>(speculations: &mut Vec<Speculation<F>>, block: u64) {
+    speculations.retain(|s| {
+        let stale = s.after + 1 < block;
+        if stale {
+            s.handle.abort();
+        }
+        !stale
+    });
+}
+
+/// Where swaps are sent.
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Swap on a fork of the chain at each trade's block, nothing is broadcast.
+    #[default]
+    Simulate,
+
+    /// Broadcast swaps to the chain, signed with the hex private key in the `private_key_env`
+    /// environment variable.
+    Live {
+        private_key_env: String,
+
+        /// Environment variables with the keys of further accounts, trades are sent from each
+        /// account in turn.
+        #[serde(default)]
+        signer_key_envs: Vec<String>,
+    },
+}
+
+impl Mode {
+    /// The signers of live swaps, in the order trades rotate through them, or none to simulate.
+    pub fn signers(&self) -> anyhow::Result<Vec<PrivateKeySigner>> {
+        let Mode::Live {
+            private_key_env,
+            signer_key_envs,
+        } = self
+        else {
+            return Ok(Vec::new());
+        };
+
+        let mut signers = Vec::with_capacity(1 + signer_key_envs.len());
+        for env in std::iter::once(private_key_env).chain(signer_key_envs) {
+            let key = std::env::var(env).context(format!("Reading the private key from ${env}"))?;
+            let signer: PrivateKeySigner = key
+                .trim()
+                .parse()
+                .context(format!("Invalid private key in ${env}"))?;
+            log::warn!("Trading LIVE from {}", signer.address());
+            signers.push(signer);
+        }
+        Ok(signers)
+    }
+}
+
+/// Checks the anvil binary used for forks can be run, so a missing install fails at startup with a
+/// hint rather than at the first trade.
+pub fn ensure_anvil() -> anyhow::Result<()> {
+    ensure_anvil_at("anvil")
+}
+
+/// `ensure_anvil`, running anvil as `program`.
+fn ensure_anvil_at(program: &str) -> anyhow::Result<()> {
+    let install_hint = "anvil is installed with Foundry, see https://book.getfoundry.sh/getting-started/installation";
+    let path = std::env::var("PATH").unwrap_or_default();
+    match std::process::Command::new(program).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            log::debug!(
+                "Using {}",
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+            Ok(())
+        }
+        Ok(output) => anyhow::bail!(
+            "`{program} --version` failed with {}: {}\n{install_hint}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => anyhow::bail!(
+            "{program} not found on PATH ({path}), it's needed to fork the chain for trades\n{install_hint}"
+        ),
+        Err(e) => anyhow::bail!("Failed to run {program} from PATH ({path}): {e}\n{install_hint}"),
+    }
+}
+
+fn spawn_fork(rpc_url: String, block: u64) -> anyhow::Result<AnvilInstance> {
+    Ok(Anvil::new()
+        .fork(rpc_url)
+        .fork_block_number(block)
+        .try_spawn()?)
+}
+
+/// Whether the swap router rejected a swap because the price moved against it since quoting.
+fn is_insufficient_output(e: &anyhow::Error) -> bool {
+    let message = format!("{e:#}");
+    message.contains("Too little received") || message.contains("Too much requested")
+}
+
+/// Raw amounts spent and received by the swap in `receipt`, from the pool's Swap event.
+fn swap_amounts(receipt: &TransactionReceipt) -> anyhow::Result<(BigInt, BigInt)> {
+    // One per hop, in the order swapped.
+    let swaps: Vec<_> = receipt
+        .inner
+        .logs()
+        .iter()
+        .filter_map(|log| log.log_decode::<UniswapV3Pool::Swap>().ok())
+        .map(|log| log.inner.data)
+        .collect();
+    let (first, last) = swaps
+        .first()
+        .zip(swaps.last())
+        .context("No Swap event in receipt")?;
+
+    // Positive amounts are paid into the pool, negative are paid out of it.
+    let spent = first.amount0.max(first.amount1);
+    let received = -last.amount0.min(last.amount1);
+    Ok((spent.to_string().parse()?, received.to_string().parse()?))
+}
+
+/// How much worse, in basis points, the executed swap was than `quoted`. Negative if it was better.
+fn realized_slippage_bps(
+    trade_type: TradeType,
+    quoted: &BigInt,
+    spent: &BigInt,
+    received: &BigInt,
+) -> anyhow::Result<f64> {
+    let lossy = |v: &BigInt| v.to_f64().context("Amount does not fit in f64");
+    let (quoted, spent, received) = (lossy(quoted)?, lossy(spent)?, lossy(received)?);
+
+    let slippage = match trade_type {
+        TradeType::ExactInput => (quoted - received) / quoted,
+        TradeType::ExactOutput => (spent - quoted) / quoted,
+    };
+    Ok(slippage * 10_000.)
+}
+
+/// Why to halt after a trade with `slippage_bps` realized slippage, if it exceeds `max`.
+fn slippage_halt(slippage_bps: f64, max: Option<f64>) -> Option<String> {
+    let max = max?;
+    (slippage_bps > max)
+        .then(|| format!("Realized slippage {slippage_bps:.2} bps exceeds maximum {max} bps"))
+}
+
+/// Router options for swapping `trade` to `recipient`, taking `fee` from the output if set.
+fn router_options(
+    recipient: Address,
+    trade: &strategy::Trade,
+    sqrt_price_limit_x96: Option<U160>,
+    slippage_bps: u64,
+    fee: Option<FeeOptions>,
+) -> SwapOptions {
+    SwapOptions {
+        recipient,
+        slippage_tolerance: trade
+            .options()
+            .slippage_tolerance
+            .clone()
+            .unwrap_or_else(|| Percent::new(slippage_bps, 10_000)),
+        sqrt_price_limit_x96,
+        fee,
+        ..Default::default()
+    }
+}
+
+/// Waits for `pending` to be mined, cancelling it once it's been pending for `cancel_after` if
+/// `trade` is no longer wanted by then.
+async fn confirm_or_cancel(
+    provider: &impl Provider,
+    account: Address,
+    pending: PendingTransactionBuilder<Ethereum>,
+    trade: &strategy::Trade,
+    cancel_after: Option<Duration>,
+    timeout: Duration,
+    still_wanted: &mut dyn StillWanted,
+) -> anyhow::Result<TransactionReceipt> {
+    let Some(cancel_after) = cancel_after else {
+        return confirm(provider, pending, timeout).await;
+    };
+    let hash = *pending.tx_hash();
+    let deadline = || tokio::time::Instant::now() + timeout;
+
+    let confirmed = confirm(provider, pending, timeout);
+    match tokio::time::timeout(cancel_after, confirmed).await {
+        Ok(receipt) => receipt,
+        Err(_) if still_wanted.still_wanted(trade).await? => {
+            log::info!("{hash} pending over {cancel_after:?}, still wanted");
+            poll_receipt(provider, hash, deadline()).await
+        }
+        Err(_) => match cancel(provider, account, hash, timeout).await {
+            Ok(()) => anyhow::bail!(
+                "Cancelled {hash}, pending over {cancel_after:?} and no longer wanted"
+            ),
+            Err(e) => {
+                log::warn!("Cancelling {hash} failed ({e:#}), it may have been mined");
+                poll_receipt(provider, hash, deadline()).await
+            }
+        },
+    }
+}
+
+/// Replaces pending transaction `hash` with a 0-value transfer to self at the same nonce.
+async fn cancel(
+    provider: &impl Provider,
+    account: Address,
+    hash: TxHash,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let tx = provider
+        .get_transaction_by_hash(hash)
+        .await?
+        .context(format!("Pending transaction {hash} not found"))?;
+    let bump = |fee: u128| fee * (100 + CANCEL_FEE_BUMP_PERCENT) / 100;
+
+    let replacement = TransactionRequest::default()
+        .from(account)
+        .to(account)
+        .value(U256::ZERO)
+        .nonce(tx.nonce())
+        .max_fee_per_gas(bump(tx.max_fee_per_gas()))
+        .max_priority_fee_per_gas(bump(tx.max_priority_fee_per_gas().unwrap_or_default()));
+    let pending = provider.send_transaction(replacement).await?;
+    log::warn!("Cancelling {hash} with {}", pending.tx_hash());
+
+    confirm(provider, pending, timeout).await?;
+    Ok(())
+}
+
+/// Waits for `pending` to be mined, falling back to polling for the receipt if the watch drops.
+pub async fn confirm(
+    provider: &impl Provider,
+    pending: PendingTransactionBuilder<Ethereum>,
+    timeout: Duration,
+) -> anyhow::Result<TransactionReceipt> {
+    let hash = *pending.tx_hash();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    match pending.with_timeout(Some(timeout)).get_receipt().await {
+        Ok(receipt) => return Ok(receipt),
+        Err(e) => log::warn!("Watching {hash} failed ({e}), polling for its receipt instead"),
+    }
+
+    poll_receipt(provider, hash, deadline).await
+}
+
+async fn poll_receipt(
+    provider: &impl Provider,
+    hash: TxHash,
+    deadline: tokio::time::Instant,
+) -> anyhow::Result<TransactionReceipt> {
+    loop {
+        if let Some(receipt) = provider.get_transaction_receipt(hash).await? {
+            return Ok(receipt);
+        }
+        anyhow::ensure!(
+            tokio::time::Instant::now() < deadline,
+            "Transaction {hash} not confirmed in time"
+        );
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Errors if `account` holds less than `min` of the chain's native currency to pay gas with.
+async fn ensure_gas_balance(
+    provider: &impl Provider,
+    account: Address,
+    chain_id: u64,
+    min: &Fraction,
+    precision_loss: PrecisionLoss,
+) -> anyhow::Result<()> {
+    let native = Currency::NativeCurrency(Ether::on_chain(chain_id));
+    let min = from_human_amount(min.clone(), &native, precision_loss)?;
+    let balance = provider.get_balance(account).await?.to_big_int();
+    anyhow::ensure!(
+        balance >= min.quotient(),
+        "Out of gas funds: {account} has {} {}, below min_gas_balance {}",
+        CurrencyAmount::from_raw_amount(native.clone(), balance)?.to_exact(),
+        native.symbol().map_or("???", |v| v),
+        min.to_exact(),
+    );
+    Ok(())
+}
+
+/// Values `wei` of the chain's gas token in the base currency, given `price` in base per quote.
+/// `weth` is the chain's wrapped native token.
+pub fn gas_in_base(
+    wei: u128,
+    weth: Option<Address>,
+    base: &Currency,
+    quote: &Currency,
+    price: f64,
+    gas_token_price: Option<f64>,
+) -> Option<f64> {
+    let native = wei as f64 / 1e18;
+    let is_gas_token = |c: &Currency| Some(c.wrapped().address()) == weth;
+
+    if is_gas_token(base) {
+        Some(native)
+    } else if is_gas_token(quote) {
+        Some(native * price)
+    } else {
+        gas_token_price.map(|p| native * p)
+    }
+}
+
+/// Logs `account`'s balance of `currency`, returning it in human units.
+pub async fn log_balance(
+    suffix: &str,
+    account: Address,
+    currency: &Currency,
+    provider: &impl alloy::providers::Provider,
+) -> anyhow::Result<f64> {
+    let exact = exact_balance(account, currency, provider).await?;
+    log_exact_balance(suffix, account, currency, &exact);
+    Ok(exact.parse()?)
+}
+
+/// Fetches both balances at once, then logs them base first.
+async fn log_balances(
+    when: &str,
+    account: Address,
+    base: &Currency,
+    quote: &Currency,
+    provider: &impl alloy::providers::Provider,
+) -> anyhow::Result<strategy::Balances> {
+    let (base_exact, quote_exact) = futures::try_join!(
+        exact_balance(account, base, provider),
+        exact_balance(account, quote, provider),
+    )?;
+    log_exact_balance(&format!("(base) {when}"), account, base, &base_exact);
+    log_exact_balance(&format!("(quot) {when}"), account, quote, &quote_exact);
+
+    Ok(strategy::Balances {
+        base: base_exact.parse()?,
+        quote: quote_exact.parse()?,
+    })
+}
+
+/// Balance of `currency` held by `account`, exactly in human units.
+async fn exact_balance(
+    account: Address,
+    currency: &Currency,
+    provider: &impl alloy::providers::Provider,
+) -> anyhow::Result<String> {
+    let balance = balance(provider, account, currency, BlockId::latest()).await?;
+    Ok(CurrencyAmount::from_raw_amount(currency, balance.to_big_int())?.to_exact())
+}
+
+fn log_exact_balance(suffix: &str, account: Address, currency: &Currency, exact: &str) {
+    log::info!(
+        "{account} has {exact} {} {suffix}",
+        currency.symbol().map_or("???", |v| v)
+    );
+}
+
+/// Raw balance of `currency` held by `account` at `block_id`.
+pub async fn balance(
+    provider: &impl Provider,
+    account: Address,
+    currency: &Currency,
+    block_id: BlockId,
+) -> anyhow::Result<U256> {
+    Ok(match currency {
+        Currency::NativeCurrency(_) => provider.get_balance(account).block_id(block_id).await?,
+        Currency::Token(t) => {
+            let erc20 = ERC20::new(t.address(), provider);
+            erc20.balanceOf(account).block(block_id).call().await?._0
+        }
+    })
+}
+
+/// The SDK trade swapping `trade` through `pools`, in path order from `base` to `quote`.
+fn swap_through<TP: TickDataProvider>(
+    trade: &strategy::Trade,
+    mut pools: Vec<Pool<TP>>,
+    (base, quote): (&Currency, &Currency),
+    precision_loss: PrecisionLoss,
+) -> anyhow::Result<(Trade<Currency, Currency, TP>, TradeType)> {
+    let route = match trade {
+        strategy::Trade::Buy { .. } => Route::new(pools, base.clone(), quote.clone()),
+        strategy::Trade::Sell { .. } => {
+            pools.reverse();
+            Route::new(pools, quote.clone(), base.clone())
+        }
+    };
+    let (amount, trade_type) = exact_side(trade, quote, precision_loss)?;
+    Ok((Trade::from_route(route, amount, trade_type)?, trade_type))
+}
+
+/// Router calldata for `swap`. When unwrapping, the router keeps the swap's WETH output and
+/// unwraps it to the recipient in the same multicall, so native currency is received.
+fn swap_parameters<TP: TickDataProvider>(
+    swap: Trade<Currency, Currency, TP>,
+    mut options: SwapOptions,
+    unwrap: bool,
+) -> anyhow::Result<MethodParameters> {
+    if !unwrap {
+        return Ok(swap_call_parameters(&mut [swap], options)?);
+    }
+
+    let minimum = swap.minimum_amount_out(options.slippage_tolerance.clone(), None)?;
+    let amount_minimum = minimum.quotient().to_string().parse::<U256>()?;
+    let recipient = options.recipient;
+    let unwrap_call = match options.fee.take() {
+        None => SwapRouter::unwrapWETH9Call {
+            amountMinimum: amount_minimum,
+            recipient,
+        }
+        .abi_encode(),
+        Some(fee) => SwapRouter::unwrapWETH9WithFeeCall {
+            amountMinimum: amount_minimum,
+            recipient,
+            feeBips: (fee.fee.numerator.clone() * BigInt::from(10_000)
+                / fee.fee.denominator.clone())
+            .to_string()
+            .parse()?,
+            feeRecipient: fee.recipient,
+        }
+        .abi_encode(),
+    };
+
+    // As the SDK does for native output, the zero address has the router keep the output.
+    options.recipient = Address::ZERO;
+    let params = swap_call_parameters(&mut [swap], options)?;
+    let mut calls = match SwapRouter::multicallCall::abi_decode(&params.calldata, true) {
+        Ok(multicall) => multicall.data,
+        Err(_) => vec![params.calldata],
+    };
+    calls.push(unwrap_call.into());
+
+    Ok(MethodParameters {
+        calldata: SwapRouter::multicallCall { data: calls }
+            .abi_encode()
+            .into(),
+        value: params.value,
+    })
+}
+
+/// The exact amount of a swap for `trade` and which side it fixes. The quote currency is a buy's
+/// output and a sell's input, so `trade`'s amount is exact either way.
+fn exact_side(
+    trade: &strategy::Trade,
+    quote: &Currency,
+    precision_loss: PrecisionLoss,
+) -> anyhow::Result<(CurrencyAmount<Currency>, TradeType)> {
+    let trade_type = match trade {
+        strategy::Trade::Buy { .. } => TradeType::ExactOutput,
+        strategy::Trade::Sell { .. } => TradeType::ExactInput,
+    };
+    let amount = from_human_amount(trade.amount().clone(), quote, precision_loss)?;
+    Ok((amount, trade_type))
+}
+
+/// What to do when a human amount can't be represented exactly in the currency's decimals.
+#[derive(serde::Deserialize, Default, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PrecisionLoss {
+    /// Log a warning and use the amount rounded down to the currency's decimals.
+    #[default]
+    Warn,
+
+    Error,
+}
+
+/// `amount` in human units (e.g. 1.5 USDC) as a `CurrencyAmount`, whose fraction is raw units
+/// (1_500_000). Raw units that aren't whole are handled by `precision_loss`.
+pub fn from_human_amount(
+    amount: Fraction,
+    currency: &Currency,
+    precision_loss: PrecisionLoss,
+) -> anyhow::Result<CurrencyAmount<Currency>> {
+    let amount = CurrencyAmount::from_fractional_amount(
+        currency.clone(),
+        amount.numerator,
+        amount.denominator,
+    )?;
+    // `from_fractional_amount` takes raw units, scaling by 10^decimals converts from human units.
+    let amount = amount.multiply(&Fraction::new(amount.meta().decimal_scale.clone(), 1))?;
+
+    let remainder = amount.numerator.clone() % &amount.denominator;
+    if remainder != BigInt::from(0) {
+        let symbol = currency.symbol().map_or("???", |v| v);
+        let requested = amount.to_significant(18, None)?;
+        let representable = amount.quotient();
+        let message = format!(
+            "{requested} raw {symbol} can't be represented exactly, rounds to {representable} raw"
+        );
+        match precision_loss {
+            PrecisionLoss::Warn => log::warn!("{message}"),
+            PrecisionLoss::Error => anyhow::bail!(message),
+        }
+    }
+
+    Ok(amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use alloy::{
+        consensus::{Transaction as _, TxEnvelope},
+        eips::eip2718::Decodable2718,
+        primitives::{address, aliases::I24, Bytes, B256, I256},
+        providers::RootProvider,
+        sol_types::SolEvent,
+    };
+    use serde_json::json;
+    use uniswap_sdk_core::token;
+
+    use super::*;
+    use crate::{
+        mock_rpc::{self, MockRpc},
+        strategy::testing::{amount_lossy, buy, sell, usdc, weth},
+    };
+
+    /// Reverts for insufficient output unless the amount is at most `fills_up_to` and the slippage
+    /// tolerance at least `needs_slippage_bps`, recording each attempt's amount and tolerance.
+    struct Market {
+        fills_up_to: f64,
+        needs_slippage_bps: u64,
+        attempts: Vec<(f64, u64)>,
+    }
+
+    impl Market {
+        fn new(fills_up_to: f64, needs_slippage_bps: u64) -> Self {
+            Market {
+                fills_up_to,
+                needs_slippage_bps,
+                attempts: Vec::new(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl SwapAttempt for Market {
+        async fn swap(
+            &mut self,
+            trade: &strategy::Trade,
+            slippage_bps: u64,
+        ) -> anyhow::Result<(TransactionReceipt, TradeType, BigInt)> {
+            let amount = amount_lossy(trade.amount());
+            self.attempts.push((amount, slippage_bps));
+            anyhow::ensure!(
+                amount <= self.fills_up_to && slippage_bps >= self.needs_slippage_bps,
+                "execution reverted: Too little received"
+            );
+            let receipt = mock_rpc::receipt(B256::ZERO, Vec::new());
+            Ok((
+                serde_json::from_value(receipt)?,
+                TradeType::ExactOutput,
+                BigInt::from(0),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_watch_recovers_by_polling() {
+        let hash = B256::repeat_byte(0xab);
+        // The node drops the first receipt request and anything the watch subscribes with.
+        let mut receipt_requests = 0;
+        let rpc = MockRpc::serve(move |method, _| match method {
+            "eth_getTransactionReceipt" => {
+                receipt_requests += 1;
+                match receipt_requests {
+                    1 => Err("connection dropped".to_string()),
+                    _ => Ok(mock_rpc::receipt(hash, Vec::new())),
+                }
+            }
+            _ => Err("connection dropped".to_string()),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+
+        let pending = PendingTransactionBuilder::new(provider.root().clone(), hash);
+        let receipt = confirm(&provider, pending, Duration::from_secs(3))
+            .await
+            .unwrap();
+        assert_eq!(receipt.transaction_hash, hash);
+        assert!(rpc.params("eth_getTransactionReceipt").len() >= 2);
+        assert_eq!(rpc.params("eth_getTransactionReceipt")[0], json!([hash]));
+    }
+
+    #[test]
+    fn excessive_realized_slippage_halts() {
+        let quoted = BigInt::from(1_000);
+        let spent = BigInt::from(500);
+
+        // Received 2% less than quoted.
+        let slippage =
+            realized_slippage_bps(TradeType::ExactInput, &quoted, &spent, &BigInt::from(980))
+                .unwrap();
+        assert!((slippage - 200.).abs() < 1e-9);
+        assert_eq!(
+            slippage_halt(slippage, Some(100.)).as_deref(),
+            Some("Realized slippage 200.00 bps exceeds maximum 100 bps")
+        );
+        assert_eq!(slippage_halt(slippage, Some(250.)), None);
+        assert_eq!(slippage_halt(slippage, None), None);
+
+        // Spent 0.5% more than quoted for an exact output.
+        let slippage = realized_slippage_bps(
+            TradeType::ExactOutput,
+            &BigInt::from(500),
+            &BigInt::from(502),
+            &quoted,
+        )
+        .unwrap();
+        assert!((slippage - 40.).abs() < 1e-9);
+        assert_eq!(slippage_halt(slippage, Some(100.)), None);
+    }
+
+    #[tokio::test]
+    async fn adaptive_retry_succeeds_with_a_smaller_size() {
+        let retry = RetryPolicy {
+            adaptive_retry: true,
+            ..Default::default()
+        };
+        let mut market = Market::new(4., 0);
+        let (executed, ..) = retry.swap(&buy(8), 50, &mut market).await.unwrap();
+        assert_eq!(amount_lossy(executed.amount()), 4.);
+        assert_eq!(market.attempts, [(8., 50), (4., 50)]);
+
+        let retry = RetryPolicy {
+            adaptive_retry: true,
+            min_retry_size: Some(Fraction::new(5, 1)),
+            ..Default::default()
+        };
+        let mut market = Market::new(4., 0);
+        let e = retry.swap(&buy(8), 50, &mut market).await.unwrap_err();
+        assert!(format!("{e:#}").contains("min_retry_size"));
+        assert_eq!(market.attempts, [(8., 50)]);
+
+        let mut market = Market::new(4., 0);
+        let not_adaptive = RetryPolicy::default();
+        assert!(not_adaptive.swap(&buy(8), 50, &mut market).await.is_err());
+        assert_eq!(market.attempts, [(8., 50)]);
+    }
+
+    #[tokio::test]
+    async fn slippage_escalates_up_to_the_cap() {
+        let retry = RetryPolicy {
+            slippage_escalation: Some(SlippageEscalation {
+                step_bps: 20,
+                max_bps: 100,
+            }),
+            ..Default::default()
+        };
+
+        // Fills once the tolerance reaches 90 bps.
+        let mut market = Market::new(8., 90);
+        let (executed, ..) = retry.swap(&buy(8), 50, &mut market).await.unwrap();
+        assert_eq!(amount_lossy(executed.amount()), 8.);
+        assert_eq!(market.attempts, [(8., 50), (8., 70), (8., 90)]);
+
+        // Needs more than the cap, so the last attempt is at the cap.
+        let mut market = Market::new(8., 150);
+        assert!(retry.swap(&buy(8), 50, &mut market).await.is_err());
+        assert_eq!(market.attempts, [(8., 50), (8., 70), (8., 90), (8., 100)]);
+    }
+
+    /// A venue known only by name, with no pools.
+    struct Named(&'static str);
+
+    #[async_trait::async_trait(?Send)]
+    impl Venue for Named {
+        fn name(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn router(&self) -> anyhow::Result<Address> {
+            Ok(Address::ZERO)
+        }
+
+        async fn pool(
+            &self,
+            _: &RootProvider,
+            _: &Currency,
+            _: &Currency,
+            _: FeeAmount,
+            _: u64,
+        ) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+            anyhow::bail!("No pools on {}", self.0)
+        }
+    }
+
+    /// Reverts on the venues named in `reverts`, recording each venue tried.
+    struct Venues {
+        reverts: Vec<&'static str>,
+        wanted: bool,
+        tried: Vec<String>,
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl VenueAttempt for Venues {
+        type Swapped = ();
+
+        async fn swap_on(&mut self, venue: &dyn Venue) -> anyhow::Result<()> {
+            self.tried.push(venue.name());
+            anyhow::ensure!(
+                !self.reverts.contains(&venue.name().as_str()),
+                "execution reverted"
+            );
+            Ok(())
+        }
+
+        async fn still_wanted(&mut self) -> anyhow::Result<bool> {
+            Ok(self.wanted)
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_venue_executes_when_the_primary_reverts() {
+        let (primary, fallback) = (Named("primary"), Named("fallback"));
+        let venues = |reverts: &[&'static str], wanted| Venues {
+            reverts: reverts.to_vec(),
+            wanted,
+            tried: Vec::new(),
+        };
+
+        let mut attempt = venues(&["primary"], true);
+        let (venue, ()) = swap_with_fallback(&mut attempt, &primary, Some(&fallback))
+            .await
+            .unwrap();
+        assert_eq!(venue, "fallback");
+        assert_eq!(attempt.tried, ["primary", "fallback"]);
+
+        // The primary working, or the trade no longer wanted, leaves the fallback untried.
+        let mut attempt = venues(&[], true);
+        let (venue, ()) = swap_with_fallback(&mut attempt, &primary, Some(&fallback))
+            .await
+            .unwrap();
+        assert_eq!(venue, "primary");
+        assert_eq!(attempt.tried, ["primary"]);
+
+        let mut attempt = venues(&["primary"], false);
+        assert!(swap_with_fallback(&mut attempt, &primary, Some(&fallback))
+            .await
+            .is_err());
+        assert_eq!(attempt.tried, ["primary"]);
+
+        let mut attempt = venues(&["primary", "fallback"], true);
+        let e = swap_with_fallback(&mut attempt, &primary, Some(&fallback))
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("Fallback to fallback failed"));
+    }
+
+    /// The signed transaction in an `eth_sendRawTransaction` request's params.
+    fn raw_transaction(raw: &serde_json::Value) -> TxEnvelope {
+        let raw: Bytes = serde_json::from_value(raw.clone()).unwrap();
+        TxEnvelope::decode_2718(&mut raw.as_ref()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn live_trades_alternate_accounts_each_with_its_own_nonce() {
+        let signers = [PrivateKeySigner::random(), PrivateKeySigner::random()];
+        let (a, b) = (signers[0].address(), signers[1].address());
+        // Each account's next nonce, as the node counts them.
+        let mut nonces = HashMap::from([(a, 5u64), (b, 9)]);
+        let rpc = MockRpc::serve(move |method, params| {
+            Ok(match method {
+                "eth_chainId" => json!("0x1"),
+                "eth_estimateGas" => json!("0x5208"),
+                "eth_gasPrice" | "eth_maxPriorityFeePerGas" => json!("0x3b9aca00"),
+                "eth_feeHistory" => json!({
+                    "oldestBlock": "0x1",
+                    "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00"],
+                    "gasUsedRatio": [0.5],
+                    "reward": [["0x5f5e100"]],
+                }),
+                "eth_getTransactionCount" => {
+                    let account: Address = serde_json::from_value(params[0].clone()).unwrap();
+                    json!(format!("{:#x}", nonces[&account]))
+                }
+                "eth_sendRawTransaction" => {
+                    let tx = raw_transaction(&params[0]);
+                    *nonces.get_mut(&tx.recover_signer().unwrap()).unwrap() += 1;
+                    json!(tx.tx_hash())
+                }
+                method => return Err(format!("Unexpected {method}")),
+            })
+        })
+        .await;
+
+        let next = AtomicUsize::new(0);
+        for _ in 0..3 {
+            let signer = round_robin(&signers, &next).unwrap();
+            let account = signer.address();
+            let provider = live_provider(&rpc.url, signer).await.unwrap();
+            let tx = TransactionRequest::default()
+                .from(account)
+                .to(Address::repeat_byte(0x70))
+                .value(U256::from(1));
+            provider.send_transaction(tx).await.unwrap();
+        }
+
+        let sent: Vec<_> = rpc
+            .params("eth_sendRawTransaction")
+            .iter()
+            .map(|params| {
+                let tx = raw_transaction(&params[0]);
+                (tx.recover_signer().unwrap(), tx.nonce())
+            })
+            .collect();
+        assert_eq!(sent, [(a, 5), (b, 9), (a, 6)]);
+    }
+
+    #[test]
+    fn sequential_trades_alternate_signers() {
+        let (a, b) = (Address::repeat_byte(0xa), Address::repeat_byte(0xb));
+        let next = AtomicUsize::new(0);
+        let turns: Vec<_> = (0..5).map(|_| round_robin(&[a, b], &next)).collect();
+        assert_eq!(turns, [Some(a), Some(b), Some(a), Some(b), Some(a)]);
+
+        assert_eq!(round_robin(&[], &next), None);
+    }
+
+    #[test]
+    fn missing_anvil_explains_how_to_install_it() {
+        let e = ensure_anvil_at("anvil-not-installed")
+            .unwrap_err()
+            .to_string();
+        let path = std::env::var("PATH").unwrap_or_default();
+        assert!(e.starts_with(&format!("anvil-not-installed not found on PATH ({path})")));
+        assert!(e.contains("installed with Foundry"));
+    }
+
+    /// A receipt with a Swap event for each of `hops`, as the amounts into and out of each pool.
+    fn swaps_receipt(hops: &[(i128, i128)]) -> TransactionReceipt {
+        let logs = hops
+            .iter()
+            .map(|&(amount0, amount1)| {
+                let swap = UniswapV3Pool::Swap {
+                    sender: Address::repeat_byte(0xee),
+                    recipient: Address::repeat_byte(0xaa),
+                    amount0: I256::try_from(amount0).unwrap(),
+                    amount1: I256::try_from(amount1).unwrap(),
+                    sqrtPriceX96: U160::ZERO,
+                    liquidity: 0,
+                    tick: Default::default(),
+                };
+                let topics = [
+                    UniswapV3Pool::Swap::SIGNATURE_HASH,
+                    swap.sender.into_word(),
+                    swap.recipient.into_word(),
+                ];
+                mock_rpc::log(Address::repeat_byte(0x88), &topics, &swap.encode_data())
+            })
+            .collect();
+        serde_json::from_value(mock_rpc::receipt(B256::ZERO, logs)).unwrap()
+    }
+
+    #[test]
+    fn swap_amounts_span_every_hop() {
+        // 3000 USDC for 1.5 WETH, token1 of the USDC/WETH pool.
+        let one_hop = swaps_receipt(&[(3_000_000_000, -1_500_000_000_000_000_000)]);
+        assert_eq!(
+            swap_amounts(&one_hop).unwrap(),
+            (
+                BigInt::from(3_000_000_000u64),
+                BigInt::from(1_500_000_000_000_000_000u64)
+            )
+        );
+
+        // Then the 1.5 WETH for 500 of a token it's token0 to.
+        let two_hops = swaps_receipt(&[
+            (3_000_000_000, -1_500_000_000_000_000_000),
+            (-500, 1_500_000_000_000_000_000),
+        ]);
+        assert_eq!(
+            swap_amounts(&two_hops).unwrap(),
+            (BigInt::from(3_000_000_000u64), BigInt::from(500))
+        );
+
+        assert!(swap_amounts(&swaps_receipt(&[])).is_err());
+    }
+
+    fn execution(
+        trade: strategy::Trade,
+        spent: (Currency, u64),
+        received: (Currency, u64),
+    ) -> Execution {
+        let amount = |(currency, raw): (Currency, u64)| {
+            CurrencyAmount::from_raw_amount(currency, BigInt::from(raw)).unwrap()
+        };
+        let balances = strategy::Balances {
+            base: 0.,
+            quote: 0.,
+        };
+        Execution {
+            trade,
+            account: Address::repeat_byte(0xaa),
+            spent: amount(spent),
+            received: amount(received),
+            gas_cost_wei: 0,
+            tx_hash: TxHash::ZERO,
+            balances_before: balances,
+            balances_after: balances,
+            after_trade: ControlFlow::Continue(()),
+        }
+    }
+
+    #[test]
+    fn summary_names_both_sides_of_a_buy_and_a_sell() {
+        let account = Address::repeat_byte(0xaa);
+        let usdc_3000 = (usdc(), 3_000_000_000);
+        let weth_1_5 = (weth(), 1_500_000_000_000_000_000);
+
+        let bought = execution(buy(1), usdc_3000.clone(), weth_1_5.clone());
+        assert_eq!(
+            bought.summary().unwrap(),
+            format!(
+                "{account} spent {} USDC → received {} WETH (price 2000)",
+                bought.spent.to_exact(),
+                bought.received.to_exact(),
+            )
+        );
+
+        let sold = execution(sell(1), weth_1_5, usdc_3000);
+        assert_eq!(
+            sold.summary().unwrap(),
+            format!(
+                "{account} spent {} WETH → received {} USDC (price 2000)",
+                sold.spent.to_exact(),
+                sold.received.to_exact(),
+            )
+        );
+    }
+
+    #[test]
+    fn only_weth_output_is_unwrapped() {
+        let native = unwrapped(&weth(), 1, wrapped_native(1, None)).unwrap();
+        assert!(native.is_native());
+        assert_eq!(native.symbol().map_or("???", |v| v), "ETH");
+
+        assert!(unwrapped(&usdc(), 1, wrapped_native(1, None)).is_none());
+        // Mainnet WETH isn't the wrapped native currency elsewhere.
+        assert!(unwrapped(&weth(), 10, wrapped_native(10, None)).is_none());
+
+        // A configured wrapped native token replaces the SDK's.
+        let custom_chain = 424_242;
+        let custom = address!("4200000000000000000000000000000000000006");
+        let custom_weth = Currency::Token(token!(custom_chain, custom, 18, "WETH"));
+        let weth = wrapped_native(custom_chain, Some(custom));
+        assert!(unwrapped(&custom_weth, custom_chain, weth).is_some());
+        assert!(unwrapped(&weth(), 1, wrapped_native(1, Some(custom))).is_none());
+    }
+
+    /// A USDC/WETH pool at 1:1 in raw units, with liquidity across every usable tick.
+    fn full_range_pool() -> Pool<TickListDataProvider<I24>> {
+        let (Currency::Token(usdc_token), Currency::Token(weth_token)) = (usdc(), weth()) else {
+            unreachable!()
+        };
+        let liquidity = 10u128.pow(24);
+        // The usable ticks nearest the bounds for the 0.3% tier's spacing of 60.
+        let (lowest, highest) = (
+            I24::try_from(-887_220).unwrap(),
+            I24::try_from(887_220).unwrap(),
+        );
+        let ticks = vec![
+            Tick::new(lowest, liquidity, liquidity as i128),
+            Tick::new(highest, liquidity, -(liquidity as i128)),
+        ];
+        Pool::new_with_tick_data_provider(
+            usdc_token,
+            weth_token,
+            FeeAmount::MEDIUM,
+            encode_sqrt_ratio_x96(1, 1),
+            liquidity,
+            TickListDataProvider::new(ticks, FeeAmount::MEDIUM.tick_spacing()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn weth_output_is_unwrapped_in_the_router_multicall() {
+        let recipient = Address::repeat_byte(0xaa);
+        let slippage = Percent::new(50, 10_000);
+        let contains =
+            |calldata: &[u8], needle: &[u8]| calldata.windows(needle.len()).any(|w| w == needle);
+
+        // Buying WETH as the quote currency, and selling for it as the base currency.
+        for (trade, base, quote) in [(buy(1), usdc(), weth()), (sell(1), weth(), usdc())] {
+            let (swap, trade_type) = swap_through(
+                &trade,
+                vec![full_range_pool()],
+                (&base, &quote),
+                PrecisionLoss::Error,
+            )
+            .unwrap();
+            let minimum = swap
+                .minimum_amount_out(slippage.clone(), None)
+                .unwrap()
+                .quotient();
+            let options = || router_options(recipient, &trade, None, 50, None);
+
+            let plain = swap_parameters(swap.clone(), options(), false).unwrap();
+            assert!(contains(&plain.calldata, recipient.as_slice()));
+            assert!(!contains(
+                &plain.calldata,
+                &SwapRouter::unwrapWETH9Call::SELECTOR
+            ));
+
+            let unwrapping = swap_parameters(swap, options(), true).unwrap();
+            let calls = SwapRouter::multicallCall::abi_decode(&unwrapping.calldata, true)
+                .unwrap()
+                .data;
+            let [swap_call, unwrap_call] = &calls[..] else {
+                panic!("Expected the swap then the unwrap, got {calls:?}");
+            };
+            // The router keeps the WETH to unwrap it, then pays out at least the swap's minimum.
+            assert!(!contains(swap_call, recipient.as_slice()));
+            let unwrap = SwapRouter::unwrapWETH9Call::abi_decode(unwrap_call, true).unwrap();
+            assert_eq!(unwrap.recipient, recipient);
+            assert_eq!(unwrap.amountMinimum.to_big_int(), minimum);
+            assert_eq!(unwrapping.value, plain.value);
+
+            if matches!(trade_type, TradeType::ExactOutput) {
+                assert_eq!(minimum, BigInt::from(10u64.pow(18)));
+            }
+        }
+    }
+
+    /// The strategy moved on from every trade.
+    struct Unwanted;
+
+    #[async_trait::async_trait(?Send)]
+    impl StillWanted for Unwanted {
+        async fn still_wanted(&mut self, _: &strategy::Trade) -> anyhow::Result<bool> {
+            Ok(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn stuck_unwanted_transaction_is_cancelled() {
+        let account = Address::repeat_byte(0xaa);
+        let (stuck, cancellation) = (B256::repeat_byte(0x01), B256::repeat_byte(0x02));
+        // The stuck transaction is never mined, its replacement is.
+        let rpc = MockRpc::serve(move |method, params| match method {
+            "eth_getTransactionReceipt" if params[0] == json!(cancellation) => {
+                Ok(mock_rpc::receipt(cancellation, Vec::new()))
+            }
+            "eth_getTransactionReceipt" => Ok(json!(null)),
+            "eth_getTransactionByHash" => Ok(mock_rpc::pending_transaction(stuck, account, 7)),
+            "eth_sendTransaction" => Ok(json!(cancellation)),
+            "eth_chainId" => Ok(json!("0x1")),
+            "eth_estimateGas" => Ok(json!("0x5208")),
+            "eth_getTransactionCount" => Ok(json!("0x7")),
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+
+        let pending = PendingTransactionBuilder::new(provider.root().clone(), stuck);
+        let e = confirm_or_cancel(
+            &provider,
+            account,
+            pending,
+            &buy(1),
+            Some(Duration::from_secs(1)),
+            Duration::from_secs(2),
+            &mut Unwanted,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{e:#}").starts_with(&format!("Cancelled {stuck}")));
+
+        let sent = rpc.params("eth_sendTransaction");
+        assert_eq!(sent.len(), 1);
+        let replacement = &sent[0][0];
+        assert_eq!(replacement["from"], json!(account));
+        assert_eq!(replacement["to"], json!(account));
+        assert_eq!(replacement["value"], json!("0x0"));
+        assert_eq!(replacement["nonce"], json!("0x7"));
+        // Both fees bumped by half.
+        assert_eq!(
+            replacement["maxFeePerGas"],
+            json!(format!("{:#x}", 1_500_000_000u64))
+        );
+        assert_eq!(
+            replacement["maxPriorityFeePerGas"],
+            json!(format!("{:#x}", 150_000_000u64))
+        );
+    }
+
+    /// Calldata for selling 1 WETH for 2000 USDC through a single pool, with `fee` taken from the
+    /// output.
+    fn sell_calldata(fee: Option<FeeOptions>) -> Vec<u8> {
+        let (Currency::Token(usdc_token), Currency::Token(weth_token)) = (usdc(), weth()) else {
+            unreachable!()
+        };
+        let pool = Pool::new(
+            usdc_token,
+            weth_token,
+            FeeAmount::MEDIUM,
+            encode_sqrt_ratio_x96(1, 1),
+            1_000_000,
+        )
+        .unwrap();
+        let route = Route::new(vec![pool], weth(), usdc());
+        let swap = Trade::create_unchecked_trade(
+            route,
+            CurrencyAmount::from_raw_amount(weth(), BigInt::from(10u64.pow(18))).unwrap(),
+            CurrencyAmount::from_raw_amount(usdc(), BigInt::from(2_000_000_000u64)).unwrap(),
+            TradeType::ExactInput,
+        )
+        .unwrap();
+
+        let options = router_options(Address::repeat_byte(0xaa), &sell(1), None, 50, fee);
+        swap_call_parameters(&mut [swap], options)
+            .unwrap()
+            .calldata
+            .to_vec()
+    }
+
+    #[test]
+    fn fee_recipient_is_in_the_calldata() {
+        let recipient = Address::repeat_byte(0xfe);
+        let contains =
+            |calldata: &[u8], needle: &[u8]| calldata.windows(needle.len()).any(|w| w == needle);
+
+        let with_fee = sell_calldata(Some(FeeOptions {
+            fee: Percent::new(25, 10_000),
+            recipient,
+        }));
+        assert!(contains(&with_fee, recipient.as_slice()));
+        // The fee is encoded in bips.
+        assert!(contains(&with_fee, &U256::from(25).to_be_bytes::<32>()));
+
+        assert!(!contains(&sell_calldata(None), recipient.as_slice()));
+    }
+
+    /// A speculation after block `after` that forked block `forked`.
+    fn speculation(after: u64, forked: u64) -> Speculation<u64> {
+        Speculation {
+            after,
+            handle: tokio::spawn(async move { Ok((forked, forked)) }),
+        }
+    }
+
+    #[tokio::test]
+    async fn speculative_fork_is_used_only_for_the_block_it_forked() {
+        let speculations = Mutex::new(vec![speculation(10, 11)]);
+        assert_eq!(speculative_fork(&speculations, 11).await, Some(11));
+        assert!(speculations.lock().unwrap().is_empty());
+
+        // Block 12 was out before the speculation saw 11, so trading on 11 needs a new fork.
+        let speculations = Mutex::new(vec![speculation(10, 12)]);
+        assert_eq!(speculative_fork(&speculations, 11).await, None);
+
+        // Speculations for earlier blocks are dropped, later ones kept.
+        let speculations = Mutex::new(vec![
+            speculation(8, 9),
+            speculation(10, 11),
+            speculation(11, 12),
+        ]);
+        assert_eq!(speculative_fork(&speculations, 11).await, Some(11));
+        let remaining = speculations.lock().unwrap();
+        assert_eq!(remaining.iter().map(|s| s.after).collect::<Vec<_>>(), [11]);
+    }
+
+    #[tokio::test]
+    async fn speculation_starts_once_per_traded_block() {
+        let speculations = Mutex::new(vec![speculation(9, 10)]);
+        let spawned = std::cell::Cell::new(0);
+        let spawn = || {
+            spawned.set(spawned.get() + 1);
+            speculation(0, 11).handle
+        };
+
+        // A second trade on block 10 reuses the speculation started by the first.
+        speculate(&speculations, 10, spawn);
+        speculate(&speculations, 10, spawn);
+        assert_eq!(spawned.get(), 1);
+        let after = |s: &Mutex<Vec<Speculation<u64>>>| {
+            s.lock()
+                .unwrap()
+                .iter()
+                .map(|s| s.after)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(after(&speculations), [9, 10]);
+
+        // Blocks without trades leave nothing running.
+        discard_stale(&mut speculations.lock().unwrap(), 13);
+        assert_eq!(after(&speculations), []);
+    }
+
+    #[test]
+    fn buy_outputs_exactly_its_amount_of_the_quote_currency() {
+        let (amount, trade_type) = exact_side(&buy(2), &weth(), PrecisionLoss::Error).unwrap();
+        assert!(matches!(trade_type, TradeType::ExactOutput));
+        assert_eq!(amount.meta().currency.symbol(), weth().symbol());
+        assert_eq!(amount.to_exact(), "2");
+        assert_eq!(amount.quotient(), BigInt::from(2 * 10u64.pow(18)));
+
+        let (amount, trade_type) = exact_side(&sell(3), &usdc(), PrecisionLoss::Error).unwrap();
+        assert!(matches!(trade_type, TradeType::ExactInput));
+        assert_eq!(amount.meta().currency.symbol(), usdc().symbol());
+        assert_eq!(amount.quotient(), BigInt::from(3_000_000));
+    }
+
+    #[test]
+    fn precision_loss_warns_or_errors_as_configured() {
+        // 0.1 raw units of an 18 decimal token.
+        let tiny = || Fraction::new(1, 10u64.pow(19));
+        let rounded = from_human_amount(tiny(), &weth(), PrecisionLoss::Warn).unwrap();
+        assert_eq!(rounded.quotient(), BigInt::from(0));
+        let e = from_human_amount(tiny(), &weth(), PrecisionLoss::Error).unwrap_err();
+        assert!(e.to_string().contains("can't be represented exactly"));
+
+        // 1234567.8 raw units of a 6 decimal token.
+        let digits = || Fraction::new(12_345_678, 10_000_000);
+        let rounded = from_human_amount(digits(), &usdc(), PrecisionLoss::Warn).unwrap();
+        assert_eq!(rounded.quotient(), BigInt::from(1_234_567));
+        assert!(from_human_amount(digits(), &usdc(), PrecisionLoss::Error).is_err());
+    }
+
+    #[test]
+    fn human_amounts_convert_at_each_currencys_decimals() {
+        let one_and_a_half = || Fraction::new(3, 2);
+        for (currency, raw) in [(usdc(), 1_500_000u64), (weth(), 15 * 10u64.pow(17))] {
+            let amount =
+                from_human_amount(one_and_a_half(), &currency, PrecisionLoss::Error).unwrap();
+            assert_eq!(amount.quotient(), BigInt::from(raw));
+            assert_eq!(amount.to_exact(), "1.5");
+        }
+
+        let indivisible = Currency::Token(token!(
+            1,
+            address!("0000000000000000000000000000000000000001"),
+            0,
+            "WHOLE"
+        ));
+        let whole =
+            from_human_amount(Fraction::new(2, 1), &indivisible, PrecisionLoss::Error).unwrap();
+        assert_eq!(whole.quotient(), BigInt::from(2));
+        assert_eq!(whole.to_exact(), "2");
+
+        assert!(from_human_amount(one_and_a_half(), &indivisible, PrecisionLoss::Error).is_err());
+        let rounded =
+            from_human_amount(one_and_a_half(), &indivisible, PrecisionLoss::Warn).unwrap();
+        assert_eq!(rounded.quotient(), BigInt::from(1));
+    }
+
+    #[tokio::test]
+    async fn low_gas_balance_blocks_the_trade() {
+        // 0.01 ETH.
+        let rpc = MockRpc::serve(|method, _| match method {
+            "eth_getBalance" => Ok(json!("0x2386f26fc10000")),
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let signer = Address::repeat_byte(0x5e);
+        let check = |numerator| {
+            let provider = provider.clone();
+            async move {
+                let min = Fraction::new(numerator, 100);
+                ensure_gas_balance(&provider, signer, 1, &min, PrecisionLoss::Error).await
+            }
+        };
+
+        check(1).await.unwrap();
+        let e = check(5).await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("Out of gas funds: {signer} has 0.01 ETH, below min_gas_balance 0.05")
+        );
+        // The signer's balance is the one checked.
+        assert_eq!(rpc.params("eth_getBalance")[0][0], json!(signer));
+    }
+}
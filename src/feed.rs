@@ -0,0 +1,212 @@
+//! Recording the contexts fed to the strategy, so they can be replayed offline to reproduce its
+//! decisions.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use uniswap_sdk_core::prelude::*;
+
+use crate::{
+    quote::Quoter,
+    strategy::{Balances, PoolSwap, TradeContext},
+};
+
+/// First line of a feed, the rest are one `Record` per line.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    chain_id: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    block_number: u64,
+    price_lossy: f64,
+    fee_lossy: f64,
+    liquidity: u128,
+    liquidity_delta: i128,
+    swaps: Vec<RecordedSwap>,
+    #[serde(default)]
+    balances: Option<Balances>,
+
+    /// The strategy's trade for this context was discarded rather than executed, e.g. for an
+    /// interpolated block.
+    pub discarded: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedSwap {
+    sender: Address,
+    recipient: Address,
+    numerator: String,
+    denominator: String,
+}
+
+impl Record {
+    /// The recorded context, with `quoter` standing in for the live one.
+    pub fn context(&self, quoter: Arc<Quoter>) -> anyhow::Result<TradeContext> {
+        Ok(TradeContext {
+            block_number: self.block_number,
+            price_lossy: self.price_lossy,
+            fee_lossy: self.fee_lossy,
+            liquidity: self.liquidity,
+            liquidity_delta: self.liquidity_delta,
+            quoter,
+            pool: None,
+            balances: self.balances,
+            swaps: self
+                .swaps
+                .iter()
+                .map(|s| {
+                    Ok(PoolSwap {
+                        sender: s.sender,
+                        recipient: s.recipient,
+                        quote_bought: Fraction::new(
+                            s.numerator.parse::<BigInt>()?,
+                            s.denominator.parse::<BigInt>()?,
+                        ),
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+pub struct Recorder {
+    file: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, chain_id: u64) -> anyhow::Result<Self> {
+        let file = File::create(path).context(format!("Creating feed {path:?}"))?;
+        let mut recorder = Recorder {
+            file: BufWriter::new(file),
+        };
+        recorder.write(&Header { chain_id })?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, context: &TradeContext, discarded: bool) -> anyhow::Result<()> {
+        self.write(&Record {
+            block_number: context.block_number,
+            price_lossy: context.price_lossy,
+            fee_lossy: context.fee_lossy,
+            liquidity: context.liquidity,
+            liquidity_delta: context.liquidity_delta,
+            swaps: context
+                .swaps
+                .iter()
+                .map(|s| RecordedSwap {
+                    sender: s.sender,
+                    recipient: s.recipient,
+                    numerator: s.quote_bought.numerator.to_string(),
+                    denominator: s.quote_bought.denominator.to_string(),
+                })
+                .collect(),
+            balances: context.balances,
+            discarded,
+        })
+    }
+
+    /// Writes a line, flushed so the feed is complete up to the last block if the bot dies.
+    fn write(&mut self, line: &impl Serialize) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.file, line)?;
+        self.file.write_all(b"\n")?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// The chain id and records of a feed written by `Recorder`.
+pub fn read(path: &Path) -> anyhow::Result<(u64, Vec<Record>)> {
+    let file = File::open(path).context(format!("Opening feed {path:?}"))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines.next().context(format!("Feed {path:?} is empty"))??;
+    let Header { chain_id } = serde_json::from_str(&header)?;
+
+    let records = lines
+        .enumerate()
+        .map(|(i, line)| {
+            serde_json::from_str(&line?).context(format!("Invalid record on line {}", i + 2))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok((chain_id, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{quote::Direction, strategy::testing::*};
+
+    #[test]
+    fn replaying_a_recorded_feed_reproduces_the_decisions() {
+        let quoter = quoter();
+        let whale = Address::repeat_byte(0x11);
+        let contexts: Vec<_> = [(100., 0), (95., 3), (90., 0), (105., -2), (111., 0)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, (price, bought))| TradeContext {
+                swaps: vec![PoolSwap {
+                    sender: whale,
+                    recipient: whale,
+                    quote_bought: Fraction::new(bought, 3),
+                }],
+                balances: Some(Balances {
+                    base: 1000.,
+                    quote: 1.5,
+                }),
+                ..context(&quoter, i as u64 + 1, price)
+            })
+            .collect();
+        let configs = [
+            json!({ "grid": { "lower": 90.0, "upper": 110.0, "levels": 2, "amount": 1 } }),
+            json!({ "mirror": { "target_address": whale, "scale": 0.5 } }),
+        ];
+
+        let path = std::env::temp_dir().join(format!("feed_{}", std::process::id()));
+        let mut recorder = Recorder::create(&path, 1).unwrap();
+        let mut live: Vec<_> = configs.iter().map(|c| strategy(c.clone())).collect();
+        let mut decided = Vec::new();
+        for (i, context) in contexts.iter().enumerate() {
+            // The third block is discarded, as for an interpolated block.
+            let discarded = i == 2;
+            recorder.record(context, discarded).unwrap();
+            for strategy in &mut live {
+                let trades = strategy.trade(context);
+                if discarded {
+                    trades.iter().for_each(|t| strategy.on_execution_failed(t));
+                }
+                decided.push(signals(&trades));
+            }
+        }
+        drop(recorder);
+
+        let (chain_id, records) = read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(chain_id, 1);
+        let mut replayed: Vec<_> = configs.iter().map(|c| strategy(c.clone())).collect();
+        let mut replay_decided = Vec::new();
+        for record in &records {
+            let context = record.context(quoter.clone()).unwrap();
+            for strategy in &mut replayed {
+                let trades = strategy.trade(&context);
+                if record.discarded {
+                    trades.iter().for_each(|t| strategy.on_execution_failed(t));
+                }
+                replay_decided.push(signals(&trades));
+            }
+        }
+
+        assert_eq!(replay_decided, decided);
+        assert!(decided.iter().flatten().any(|(d, _)| *d == Direction::Buy));
+        assert!(decided.iter().flatten().any(|(d, _)| *d == Direction::Sell));
+    }
+}
@@ -0,0 +1,183 @@
+//! Topping up the trading account from a treasury before trading.
+
+use std::time::Duration;
+
+use alloy::{primitives::U256, providers::Provider};
+use uniswap_sdk_core::prelude::*;
+
+use crate::{
+    execution::{confirm, from_human_amount, PrecisionLoss, ERC20},
+    strategy::FractionInput,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Treasury to pull from with `transferFrom`, it must have approved the trading account.
+    source: Address,
+
+    base: Option<Threshold>,
+    quote: Option<Threshold>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Threshold {
+    /// Top up when the balance is below this, in human units.
+    min_balance: FractionInput,
+
+    /// Most to pull in a single top up, in human units.
+    max_topup: FractionInput,
+}
+
+impl Config {
+    /// Ensures `account` holds at least the configured minimum of `currency`, the side of the
+    /// pair given by `is_base`, pulling the shortfall from the treasury.
+    pub async fn top_up(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        currency: &Currency,
+        is_base: bool,
+        timeout: Duration,
+        precision_loss: PrecisionLoss,
+    ) -> anyhow::Result<()> {
+        let threshold = match (is_base, &self.base, &self.quote) {
+            (true, Some(t), _) | (false, _, Some(t)) => t,
+            _ => return Ok(()),
+        };
+        let symbol = currency.symbol().map_or("???", |v| v);
+
+        let token = match currency {
+            Currency::Token(t) => t,
+            Currency::NativeCurrency(_) => {
+                log::warn!("Can't top up native {symbol} with transferFrom, skipping");
+                return Ok(());
+            }
+        };
+
+        let erc20 = ERC20::new(token.address(), provider);
+        let balance = erc20.balanceOf(account).call().await?._0.to_big_int();
+        let min_balance =
+            from_human_amount(threshold.min_balance.into(), currency, precision_loss)?.quotient();
+        if balance >= min_balance {
+            return Ok(());
+        }
+
+        let max_topup =
+            from_human_amount(threshold.max_topup.into(), currency, precision_loss)?.quotient();
+        let amount = (min_balance - balance).min(max_topup);
+        log::info!(
+            "Topping up {account} with {} {symbol} from {}",
+            CurrencyAmount::from_raw_amount(currency.clone(), amount.clone())?.to_exact(),
+            self.source
+        );
+
+        let pending = erc20
+            .transferFrom(self.source, account, amount.to_string().parse::<U256>()?)
+            .from(account)
+            .send()
+            .await?;
+        let receipt = confirm(provider, pending, timeout).await?;
+        anyhow::ensure!(
+            receipt.status(),
+            "Top up transaction {} reverted",
+            receipt.transaction_hash
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{Bytes, B256},
+        providers::ProviderBuilder,
+        sol_types::SolCall,
+    };
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{
+        mock_rpc::{self, MockRpc},
+        strategy::testing::usdc,
+    };
+
+    /// A chain where the trading account holds `balance` raw units of any token.
+    async fn chain(balance: u64) -> MockRpc {
+        MockRpc::serve(move |method, _: &Value| {
+            Ok(match method {
+                "eth_call" => json!(B256::from(U256::from(balance))),
+                "eth_chainId" => json!("0x1"),
+                "eth_getTransactionCount" => json!("0x0"),
+                "eth_estimateGas" => json!("0x186a0"),
+                "eth_gasPrice" | "eth_maxPriorityFeePerGas" => json!("0x3b9aca00"),
+                "eth_feeHistory" => json!({
+                    "oldestBlock": "0x1",
+                    "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00"],
+                    "gasUsedRatio": [0.5],
+                    "reward": [["0x5f5e100"]],
+                }),
+                "eth_sendTransaction" => json!(B256::repeat_byte(0x70)),
+                "eth_getTransactionReceipt" => {
+                    mock_rpc::receipt(B256::repeat_byte(0x70), Vec::new())
+                }
+                method => return Err(format!("Unexpected {method}")),
+            })
+        })
+        .await
+    }
+
+    /// `transferFrom` amounts sent after topping up from a balance of `balance` USDC.
+    async fn top_ups(balance: u64) -> Vec<U256> {
+        let chain = chain(balance * 1_000_000).await;
+        let provider = ProviderBuilder::new().on_http(chain.url.parse().unwrap());
+        let funding: Config = serde_json::from_value(json!({
+            "source": Address::repeat_byte(0x7e),
+            "quote": { "min_balance": 100, "max_topup": 30 },
+        }))
+        .unwrap();
+
+        let account = Address::repeat_byte(0xac);
+        funding
+            .top_up(
+                &provider,
+                account,
+                &usdc(),
+                false,
+                Duration::from_secs(5),
+                PrecisionLoss::Error,
+            )
+            .await
+            .unwrap();
+
+        chain
+            .params("eth_sendTransaction")
+            .iter()
+            .map(|params| {
+                let tx = &params[0];
+                let input = tx.get("input").or(tx.get("data")).unwrap();
+                let input: Bytes = serde_json::from_value(input.clone()).unwrap();
+                let call = ERC20::transferFromCall::abi_decode(&input, true).unwrap();
+                assert_eq!((call.from, call.to), (funding.source, account));
+                call.amount
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn low_balance_is_topped_up_to_the_minimum() {
+        assert_eq!(top_ups(80).await, [U256::from(20_000_000)]);
+    }
+
+    #[tokio::test]
+    async fn top_up_is_capped() {
+        assert_eq!(top_ups(50).await, [U256::from(30_000_000)]);
+    }
+
+    #[tokio::test]
+    async fn sufficient_balance_is_not_topped_up() {
+        assert_eq!(top_ups(150).await, []);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    future::Future,
+    io::ErrorKind,
+    ops::ControlFlow,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+
+use crate::{position::Position, strategy::Trade};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// File the operator's monitoring touches to signal it is still alive.
+    file: PathBuf,
+
+    timeout_secs: u64,
+}
+
+/// Dead-man's switch, expects the heartbeat file to be touched at least every `timeout_secs`.
+pub struct Heartbeat {
+    config: Config,
+    started: SystemTime,
+}
+
+impl Heartbeat {
+    pub fn new(config: Config) -> Self {
+        Heartbeat {
+            config,
+            started: SystemTime::now(),
+        }
+    }
+
+    /// How long it has been since the last heartbeat, if that exceeds the timeout.
+    pub fn missed(&self) -> anyhow::Result<Option<Duration>> {
+        let last = match std::fs::metadata(&self.config.file) {
+            Ok(meta) => meta.modified()?.max(self.started),
+            Err(e) if e.kind() == ErrorKind::NotFound => self.started,
+            Err(e) => {
+                return Err(e).context(format!("Reading heartbeat {:?}", self.config.file));
+            }
+        };
+
+        let silence = SystemTime::now()
+            .duration_since(last)
+            .unwrap_or(Duration::ZERO);
+        Ok((silence > Duration::from_secs(self.config.timeout_secs)).then_some(silence))
+    }
+
+    /// If the heartbeat was missed, flattens `position` with `execute` and breaks to halt.
+    pub async fn check<F, Fut>(
+        &self,
+        position: &Position,
+        execute: F,
+    ) -> anyhow::Result<ControlFlow<()>>
+    where
+        F: FnOnce(Trade) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let Some(silence) = self.missed()? else {
+            return Ok(ControlFlow::Continue(()));
+        };
+        log::error!("!!! DEAD MAN'S SWITCH: no heartbeat for {silence:?} !!!");
+        log::error!("!!! Flattening position and halting !!!");
+        if let Some(trade) = position.flatten() {
+            log::error!("Flattening with {trade:?}");
+            execute(trade).await?;
+        }
+        Ok(ControlFlow::Break(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        quote::Direction,
+        strategy::testing::{buy, signals},
+    };
+
+    fn heartbeat(file: &str, timeout_secs: u64, started_ago: Duration) -> Heartbeat {
+        let file = std::env::temp_dir().join(format!("{file}_{}", std::process::id()));
+        Heartbeat {
+            config: Config { file, timeout_secs },
+            started: SystemTime::now() - started_ago,
+        }
+    }
+
+    /// Runs `check` on a position of `held`, returning whether it halted and what it executed.
+    async fn check(heartbeat: &Heartbeat, held: u64) -> (bool, Vec<Trade>) {
+        let mut position = Position::default();
+        if held > 0 {
+            position.apply(&buy(held), 1, 100.);
+        }
+        let mut executed = Vec::new();
+        let flow = heartbeat
+            .check(&position, |trade| {
+                executed.push(trade);
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        (flow.is_break(), executed)
+    }
+
+    #[tokio::test]
+    async fn missed_heartbeat_flattens_the_position_and_halts() {
+        let heartbeat = heartbeat("lhava_never_touched", 5, Duration::from_secs(10));
+        let silence = heartbeat.missed().unwrap().expect("heartbeat was missed");
+        assert!(silence >= Duration::from_secs(10));
+
+        let (halted, executed) = check(&heartbeat, 3).await;
+        assert!(halted);
+        assert_eq!(signals(&executed), [(Direction::Sell, 3.)]);
+
+        // Already flat, it halts without trading.
+        let (halted, executed) = check(&heartbeat, 0).await;
+        assert!(halted);
+        assert!(executed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn heartbeat_within_the_timeout_keeps_trading() {
+        let heartbeat = heartbeat("lhava_just_started", 5, Duration::ZERO);
+        let (halted, executed) = check(&heartbeat, 3).await;
+        assert!(!halted);
+        assert!(executed.is_empty());
+    }
+
+    #[test]
+    fn touched_heartbeat_is_not_missed() {
+        let heartbeat = heartbeat("lhava_touched", 5, Duration::from_secs(10));
+        std::fs::write(&heartbeat.config.file, "").unwrap();
+        let missed = heartbeat.missed().unwrap();
+        std::fs::remove_file(&heartbeat.config.file).unwrap();
+        assert_eq!(missed, None);
+    }
+
+    #[test]
+    fn no_heartbeat_is_missed_within_the_timeout_of_starting() {
+        let heartbeat = heartbeat("lhava_not_yet_touched", 5, Duration::ZERO);
+        assert_eq!(heartbeat.missed().unwrap(), None);
+    }
+}
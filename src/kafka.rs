@@ -0,0 +1,219 @@
+//! Publishing executed trades to a Kafka topic, for the wider trading platform.
+
+use std::time::Duration;
+
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+use uniswap_sdk_core::prelude::*;
+
+use crate::{execution::Execution, strategy};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Comma separated `host:port` bootstrap brokers.
+    brokers: String,
+    topic: String,
+
+    /// Events waiting to be published beyond this are dropped, e.g. while the brokers are down.
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+}
+
+fn default_queue_size() -> usize {
+    1024
+}
+
+/// How long the producer keeps retrying a message before giving up on it.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(serde::Serialize, Debug)]
+pub struct TradeEvent {
+    pub block: u64,
+    pub pair: String,
+    pub side: &'static str,
+
+    /// Amounts in human units, as exact decimal strings.
+    pub spent: String,
+    pub spent_symbol: String,
+    pub received: String,
+    pub received_symbol: String,
+
+    /// Base units paid or received per quote unit.
+    pub price: f64,
+}
+
+impl TradeEvent {
+    pub fn new(block: u64, pair: &str, execution: &Execution) -> anyhow::Result<Self> {
+        let symbol = |a: &CurrencyAmount<Currency>| {
+            a.meta().currency.symbol().map_or("???", |v| v).to_string()
+        };
+        Ok(TradeEvent {
+            block,
+            pair: pair.to_string(),
+            side: match execution.trade {
+                strategy::Trade::Buy { .. } => "buy",
+                strategy::Trade::Sell { .. } => "sell",
+            },
+            spent: execution.spent.to_exact(),
+            spent_symbol: symbol(&execution.spent),
+            received: execution.received.to_exact(),
+            received_symbol: symbol(&execution.received),
+            price: execution.price_lossy()?,
+        })
+    }
+}
+
+/// Where events are sent, a Kafka producer outside of tests.
+#[async_trait::async_trait]
+pub trait Producer: Send + Sync + 'static {
+    async fn send(&self, topic: &str, key: &str, payload: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait::async_trait]
+impl Producer for FutureProducer {
+    async fn send(&self, topic: &str, key: &str, payload: &str) -> anyhow::Result<()> {
+        let record = FutureRecord::to(topic).key(key).payload(payload);
+        FutureProducer::send(self, record, MESSAGE_TIMEOUT)
+            .await
+            .map_err(|(e, _)| e)?;
+        Ok(())
+    }
+}
+
+/// Publishes events from a background task, so a slow or unavailable broker doesn't hold up
+/// trading.
+pub struct Publisher {
+    sender: mpsc::Sender<TradeEvent>,
+    task: JoinHandle<()>,
+}
+
+impl Publisher {
+    pub fn spawn(config: Config) -> anyhow::Result<Self> {
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set(
+                "message.timeout.ms",
+                MESSAGE_TIMEOUT.as_millis().to_string(),
+            )
+            .create()?;
+        Ok(Publisher::with_producer(
+            producer,
+            config.topic,
+            config.queue_size,
+        ))
+    }
+
+    fn with_producer(producer: impl Producer, topic: String, queue_size: usize) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<TradeEvent>(queue_size);
+        let task = tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                let payload = match serde_json::to_string(&event) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        log::error!("Failed to serialize {event:?}: {e}");
+                        continue;
+                    }
+                };
+                let key = event.block.to_string();
+                if let Err(e) = producer.send(&topic, &key, &payload).await {
+                    log::error!(
+                        "Failed to publish trade from block {} to {topic}: {e:#}",
+                        event.block
+                    );
+                }
+            }
+        });
+
+        Publisher { sender, task }
+    }
+
+    /// Queues `event` for publishing, dropping it if the queue is full.
+    pub fn publish(&self, event: TradeEvent) {
+        match self.sender.try_send(event) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(event)) => {
+                log::warn!(
+                    "Kafka queue full, dropping trade from block {}",
+                    event.block
+                )
+            }
+            Err(mpsc::error::TrySendError::Closed(event)) => {
+                log::warn!(
+                    "Kafka publisher stopped, dropping trade from block {}",
+                    event.block
+                )
+            }
+        }
+    }
+
+    /// Waits for queued events to be published.
+    pub async fn close(self) -> anyhow::Result<()> {
+        drop(self.sender);
+        Ok(self.task.await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Records each message sent as its topic, key, and payload.
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<(String, String, serde_json::Value)>>>);
+
+    #[async_trait::async_trait]
+    impl Producer for Recorded {
+        async fn send(&self, topic: &str, key: &str, payload: &str) -> anyhow::Result<()> {
+            let payload = serde_json::from_str(payload)?;
+            self.0
+                .lock()
+                .unwrap()
+                .push((topic.to_string(), key.to_string(), payload));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn trade_events_are_published_as_json() {
+        let recorded = Recorded::default();
+        let publisher = Publisher::with_producer(recorded.clone(), "trades".to_string(), 8);
+        publisher.publish(TradeEvent {
+            block: 42,
+            pair: "USDC/WETH".to_string(),
+            side: "buy",
+            spent: "3000".to_string(),
+            spent_symbol: "USDC".to_string(),
+            received: "1.5".to_string(),
+            received_symbol: "WETH".to_string(),
+            price: 2000.,
+        });
+        publisher.close().await.unwrap();
+
+        let sent = recorded.0.lock().unwrap();
+        assert_eq!(
+            *sent,
+            [(
+                "trades".to_string(),
+                "42".to_string(),
+                json!({
+                    "block": 42,
+                    "pair": "USDC/WETH",
+                    "side": "buy",
+                    "spent": "3000",
+                    "spent_symbol": "USDC",
+                    "received": "1.5",
+                    "received_symbol": "WETH",
+                    "price": 2000.0,
+                })
+            )]
+        );
+    }
+}
@@ -1,23 +1,73 @@
-use std::{ops::ControlFlow, time::Duration};
+use std::{
+    io::Write,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use alloy::{
     eips::BlockId,
+    primitives::U256,
     providers::{Provider, ProviderBuilder},
-    rpc::types::TransactionRequest,
-    transports::http::reqwest::Url,
 };
 use anyhow::Context;
+use num_traits::ToPrimitive;
 use structopt::StructOpt;
+use tracing::Instrument;
 use uniswap_sdk_core::{prelude::*, token};
 use uniswap_v3_sdk::prelude::*;
 
+mod backtest;
+mod blocks;
+mod execution;
+mod feed;
+mod funding;
+mod heartbeat;
+mod kafka;
+mod metrics;
+#[cfg(test)]
+mod mock_rpc;
+mod netting;
+mod outlier;
+mod pool;
+mod position;
+mod price;
+mod prompt;
+mod quote;
+mod retry;
+mod rpc_budget;
+mod sizing;
 mod strategy;
+mod telemetry;
+mod trade_log;
+mod user_operation;
+mod venue;
+mod watermark;
 
 #[derive(StructOpt)]
 struct Options {
-    // TODO(shelbyd): Should be PathBuf.
-    #[structopt(default_value = "src/config/default.yaml")]
-    config_file: String,
+    /// Config file, .yaml, .toml, or .json.
+    #[structopt(parse(from_os_str))]
+    config_file: Option<PathBuf>,
+
+    /// Config file, instead of passing it positionally.
+    #[structopt(
+        short,
+        long = "config",
+        parse(from_os_str),
+        conflicts_with = "config-file"
+    )]
+    config: Option<PathBuf>,
+}
+
+impl Options {
+    fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .or_else(|| self.config_file.clone())
+            .unwrap_or_else(|| PathBuf::from("src/config/default.yaml"))
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -25,10 +75,409 @@ struct Options {
 struct Config {
     rpc_url: String,
 
+    /// The pair traded, unless `pairs` is set.
+    base: Option<ConfigToken>,
+    quote: Option<ConfigToken>,
+    /// A `strategy::Config`, kept as written so a backtest can sweep its parameters.
+    strategy: Option<serde_json::Value>,
+
+    /// Pairs traded concurrently, each with its own strategy, instead of a single one. Every
+    /// other option is shared.
+    #[serde(default)]
+    pairs: Vec<Pair>,
+
+    heartbeat: Option<heartbeat::Config>,
+
+    /// Publish each executed trade to a Kafka topic as JSON.
+    kafka: Option<kafka::Config>,
+
+    /// Serve Prometheus metrics for scraping.
+    metrics: Option<metrics::Config>,
+
+    /// Accounts to send trades from in turn, instead of the fork's first dev account.
+    #[serde(default)]
+    signers: Vec<Address>,
+
+    /// Account whose balances are given to strategies that need them.
+    account: Option<Address>,
+
+    /// Alert when an account's balances drop below these, checked every block.
+    low_watermark: Option<watermark::Config>,
+
+    paymaster: Option<user_operation::Config>,
+
+    /// Whether swaps are simulated on a fork or broadcast.
+    #[serde(default)]
+    mode: execution::Mode,
+
+    /// Log the transaction for each trade instead of forking and sending it.
+    #[serde(default)]
+    dry_run: bool,
+
+    /// Stop after the first executed trade, rather than trading every block.
+    #[serde(default)]
+    run_once: bool,
+
+    /// Feed linearly interpolated prices for skipped blocks to the strategy, without trading on them.
+    #[serde(default)]
+    interpolate_missed_blocks: bool,
+
+    #[serde(default = "default_confirmation_timeout_secs")]
+    confirmation_timeout_secs: u64,
+
+    /// Retries of RPC calls for new blocks and pool state that fail transiently.
+    #[serde(default)]
+    retry: retry::Config,
+
+    /// How often to ask for the block number when not subscribed to new blocks.
+    #[serde(default = "default_poll_interval_ms")]
+    poll_interval_ms: u64,
+
+    /// Ask for approval on stdin before sending each swap, skipping it without an answer in
+    /// `interactive_timeout_secs`.
+    #[serde(default)]
+    interactive: bool,
+    #[serde(default = "default_interactive_timeout_secs")]
+    interactive_timeout_secs: u64,
+
+    /// Cancel a transaction pending this long if the strategy no longer wants its trade.
+    cancel_after_secs: Option<u64>,
+
+    /// Halt trading if any trade's realized slippage versus its quote exceeds this.
+    max_realized_slippage_bps: Option<f64>,
+
+    /// Approve the router for exactly what each swap needs, or the max, when its allowance is low.
+    #[serde(default)]
+    approval: execution::Approval,
+
+    /// Skip sells quoted to receive less than this, absolute or relative to the mid price.
+    min_out: Option<execution::MinOut>,
+
+    /// EIP-1559 fees and gas limit for swaps, instead of the node's estimates.
+    gas: Option<execution::Gas>,
+
+    /// Skip a swap if its estimated gas cost is more than this share of the trade's value.
+    max_gas_cost_bps: Option<f64>,
+
+    /// Wrapped native token, overriding the SDK's default for the chain.
+    weth_address: Option<String>,
+
+    /// Evaluate the strategy on this cadence, using the latest block, instead of every block.
+    evaluation_cadence_secs: Option<u64>,
+
+    /// Guard against runaway strategies, trades beyond this in a single block are dropped.
+    max_trades_per_block: Option<usize>,
+
+    /// Accumulate the strategy's trades over windows of this many blocks, executing only their
+    /// net as a single trade when each window closes.
+    net_window_blocks: Option<u64>,
+
+    /// Tolerance for swaps executing worse than quoted before reverting.
+    #[serde(default)]
+    slippage_bps: u64,
+
+    /// Retry swaps that revert for insufficient output with the tolerance raised by this much each
+    /// time, up to `max_slippage_bps`.
+    slippage_step_bps: Option<u64>,
+    max_slippage_bps: Option<u64>,
+
+    /// Another Uniswap V3 deployment to swap through when the swap through Uniswap fails.
+    fallback_router: Option<venue::Config>,
+
+    /// Retry swaps that revert for insufficient output with half the size, down to `min_retry_size`.
+    #[serde(default)]
+    adaptive_retry: bool,
+    min_retry_size: Option<strategy::FractionInput>,
+
+    #[serde(default)]
+    price_source: price::PriceSource,
+
+    /// Skip blocks whose price is far from the recent median.
+    outlier_filter: Option<outlier::Config>,
+
+    /// Feed the first block to the strategy only to seed its state, never trading on it.
+    #[serde(default)]
+    skip_first_block: bool,
+
+    /// Resize trades from the strategy, e.g. to the largest within a slippage target.
+    sizing: Option<sizing::Sizing>,
+
+    /// Bounds on the net quote currency acquired since startup. Buys are reduced to stay under
+    /// `max_position`, sells that would go below `min_position` are skipped.
+    max_position: Option<strategy::FractionInput>,
+    min_position: Option<strategy::FractionInput>,
+
+    /// Don't sell within this many blocks of opening a position.
+    #[serde(default)]
+    min_hold_blocks: u64,
+
+    funding: Option<funding::Config>,
+
+    /// Whether trade amounts that can't be represented in the token's decimals warn or error.
+    #[serde(default)]
+    precision_loss: execution::PrecisionLoss,
+
+    /// Refuse to trade when the account's native balance is below this, in native units.
+    min_gas_balance: Option<strategy::FractionInput>,
+
+    /// Router fee taken from each swap's output and sent to a recipient.
+    fee_recipient: Option<FeeRecipient>,
+
+    /// Referral tag for routers that support one, Uniswap's routers don't so it's ignored.
+    referral_code: Option<String>,
+
+    /// When a swap outputs WETH, unwrap it so native currency is received.
+    #[serde(default)]
+    unwrap_output: bool,
+
+    /// Base units per unit of the chain's gas token, to value gas in PnL when neither base nor
+    /// quote is the gas token.
+    gas_token_price: Option<f64>,
+
+    /// Re-fetch the pool at the head before executing if the block being processed is more than
+    /// this many blocks behind it.
+    max_tick_data_lag: Option<u64>,
+
+    /// Block state pool reads are made against.
+    #[serde(default)]
+    state_block: blocks::StateBlock,
+
+    /// File the last fully processed block is persisted to.
+    last_block_file: Option<PathBuf>,
+
+    /// On startup, process the blocks missed since `last_block_file`'s before resuming live.
+    #[serde(default)]
+    catch_up: bool,
+
+    /// File the strategy's internal state (windows, averages, ...) and the position are persisted to
+    /// after every block and restored from on startup, so a restart doesn't need to warm up again.
+    state_file: Option<PathBuf>,
+
+    /// After a trade, fork the next block as soon as it's produced, in parallel with deciding whether
+    /// to trade on it.
+    #[serde(default)]
+    speculative_fork: bool,
+
+    /// OTLP (HTTP) collector to export per-block pipeline spans to.
+    otel_endpoint: Option<String>,
+
+    /// Bound RPC reads per block, skipping optional ones (aggregate prices, quotes) past it.
+    max_rpc_calls_per_block: Option<usize>,
+
+    /// Log a single line per block summarizing price, position, PnL, and the strategy's decision.
+    #[serde(default)]
+    status_line: bool,
+
+    /// QuoterV2 used for quotes requested by strategies, defaults to mainnet's.
+    quoter_address: Option<Address>,
+
+    /// Swap through these pools in order, starting from the base currency and ending at the quote
+    /// currency, instead of directly. Prices and quotes still come from the direct pool.
+    #[serde(default)]
+    route: Vec<Hop>,
+
+    /// Fee tier of the pool traded, and quoted and priced against.
+    #[serde(default)]
+    fee_tier: pool::FeeTier,
+
+    /// Address of the pool traded, instead of deriving it from the canonical factory, e.g. for a
+    /// deployment on a fork without the factory. Must be for the pair and `fee_tier`.
+    pool_address: Option<Address>,
+
+    /// Quote each trade on these fee tiers and execute it on the one with the best price, rather
+    /// than always on `fee_tier`.
+    #[serde(default)]
+    route_fee_tiers: Vec<pool::FeeTier>,
+
+    /// Rebuild the strategy from its config, dropping its state, whenever `route_fee_tiers` routes
+    /// through a different tier than the previous trade, as state like effective prices depends on
+    /// the fee.
+    #[serde(default)]
+    reseed_on_tier_change: bool,
+
+    /// Skip trades rather than route them through a `route_fee_tiers` pool with less in-range
+    /// liquidity than this.
+    min_auto_liquidity: Option<u128>,
+
+    /// How many blocks a quote is reused for, 0 disables caching.
+    #[serde(default = "default_quote_cache_ttl_blocks")]
+    quote_cache_ttl_blocks: u64,
+
+    /// Run the strategy over a historical block range, filling trades at the pool price instead of
+    /// executing them, then exit.
+    backtest: Option<backtest::Config>,
+
+    /// File every executed trade is appended to, as JSON lines or CSV by its extension.
+    trade_log: Option<PathBuf>,
+
+    /// File every context fed to the strategy is recorded to, for `replay_feed`.
+    record_feed: Option<PathBuf>,
+
+    /// Instead of trading, feed the strategy the contexts recorded in this file and log its
+    /// decisions, without any RPC calls.
+    replay_feed: Option<PathBuf>,
+}
+
+fn default_confirmation_timeout_secs() -> u64 {
+    120
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_interactive_timeout_secs() -> u64 {
+    60
+}
+
+fn default_quote_cache_ttl_blocks() -> u64 {
+    1
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeeRecipient {
+    recipient: Address,
+    fee_bps: u64,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pair {
     base: ConfigToken,
     quote: ConfigToken,
+    /// A `strategy::Config`, kept as written so a backtest can sweep its parameters.
+    strategy: serde_json::Value,
+}
+
+/// A pair's base and quote, and its strategy as configured and built.
+type PairStrategy = (
+    ConfigToken,
+    ConfigToken,
+    serde_json::Value,
+    Box<dyn strategy::Strategy>,
+);
 
-    strategy: strategy::Config,
+impl Config {
+    /// The pairs to trade, from either `pairs` or the top level `base`, `quote`, and `strategy`.
+    fn pairs(&mut self) -> anyhow::Result<Vec<Pair>> {
+        match (self.base.take(), self.quote.take(), self.strategy.take()) {
+            (Some(base), Some(quote), Some(strategy)) if self.pairs.is_empty() => Ok(vec![Pair {
+                base,
+                quote,
+                strategy,
+            }]),
+            (None, None, None) if !self.pairs.is_empty() => Ok(std::mem::take(&mut self.pairs)),
+            _ => anyhow::bail!("Set either `base`, `quote`, and `strategy`, or `pairs`"),
+        }
+    }
+
+    /// Checks everything that can be without RPC calls, listing every problem found, and builds
+    /// each pair's strategy.
+    fn validate(&mut self) -> anyhow::Result<Vec<PairStrategy>> {
+        let pairs = self.pairs()?;
+        let mut problems = Vec::new();
+
+        let mut check_token = |what: &str, token: &ConfigToken| {
+            if let ConfigToken::Erc20 {
+                address, decimals, ..
+            } = token
+            {
+                if address.parse::<Address>().is_err() {
+                    problems.push(format!("{what} address {address:?} isn't an address"));
+                }
+                if *decimals > 36 {
+                    problems.push(format!("{what} has implausible decimals {decimals}"));
+                }
+            }
+        };
+        for pair in &pairs {
+            check_token("base", &pair.base);
+            check_token("quote", &pair.quote);
+        }
+        for (i, hop) in self.route.iter().enumerate() {
+            check_token(&format!("route hop {i}"), &hop.token);
+        }
+
+        if let Some(a) = &self.weth_address {
+            if a.parse::<Address>().is_err() {
+                problems.push(format!("weth_address {a:?} isn't an address"));
+            }
+        }
+        if let Err(e) = self.gas.as_ref().map_or(Ok(()), |g| g.validate()) {
+            problems.push(e.to_string());
+        }
+        if let Err(e) = self
+            .outlier_filter
+            .as_ref()
+            .map_or(Ok(()), |o| o.validate())
+        {
+            problems.push(e.to_string());
+        }
+        if self.poll_interval_ms == 0 {
+            problems.push("poll_interval_ms must be above 0".to_string());
+        }
+        if self.evaluation_cadence_secs == Some(0) {
+            problems.push("evaluation_cadence_secs must be above 0".to_string());
+        }
+
+        let mut strategies = Vec::with_capacity(pairs.len());
+        for Pair {
+            base,
+            quote,
+            strategy,
+        } in pairs
+        {
+            let name = format!("{}/{}", base.symbol(), quote.symbol());
+            let built = serde_json::from_value::<strategy::Config>(strategy.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(strategy::Config::into_dyn);
+            match built {
+                Ok(built) => strategies.push((base, quote, strategy, built)),
+                Err(e) => problems.push(format!("{name} strategy: {e:#}")),
+            }
+        }
+
+        anyhow::ensure!(
+            problems.is_empty(),
+            "Invalid config:\n  {}",
+            problems.join("\n  ")
+        );
+        Ok(strategies)
+    }
+
+    /// Gives each of several pairs its own files, so they don't overwrite each other's.
+    fn separate_files(&mut self, name: &str) {
+        let files = [
+            &mut self.last_block_file,
+            &mut self.state_file,
+            &mut self.trade_log,
+            &mut self.record_feed,
+            &mut self.replay_feed,
+        ];
+        for path in files.into_iter().flatten() {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let mut file_name = format!("{stem}_{name}");
+            if let Some(extension) = path.extension() {
+                file_name = format!("{file_name}.{}", extension.to_string_lossy());
+            }
+            path.set_file_name(file_name);
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Name of the pair the current task trades, prefixed to its logs.
+    static PAIR: String;
+}
+
+/// A pool on a multi-hop route, swapping to `token`.
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Hop {
+    token: ConfigToken,
+    #[serde(default)]
+    fee: pool::FeeTier,
 }
 
 #[derive(serde::Deserialize)]
@@ -47,161 +496,1061 @@ enum ConfigToken {
 async fn main() -> anyhow::Result<()> {
     ::env_logger::builder()
         .filter(None, log::LevelFilter::Info)
+        .format(|buf, record| {
+            let pair = PAIR.try_with(|p| format!(" {p}")).unwrap_or_default();
+            writeln!(
+                buf,
+                "[{} {:<5} {}{pair}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        })
         .init();
 
     let options = Options::from_args();
 
-    let config: Config = ::config::Config::builder()
-        .add_source(config::File::with_name(&options.config_file))
-        .build()?
-        .try_deserialize()?;
+    let config_path = options.config_path();
+    anyhow::ensure!(
+        config_path.is_file(),
+        "Config file {config_path:?} doesn't exist"
+    );
+    let settings = load_settings(&config_path)?;
+    let mut config: Config = settings
+        .clone()
+        .try_deserialize()
+        .context(format!("Loading config {config_path:?}"))?;
+    let pairs = config.validate()?;
+
+    let telemetry = config
+        .otel_endpoint
+        .as_deref()
+        .map(telemetry::init)
+        .transpose()?;
+
+    let metrics = Arc::new(metrics::Metrics::default());
+    if let Some(metrics_config) = &config.metrics {
+        metrics::serve(metrics_config, metrics.clone()).await?;
+    }
 
-    let rpc_url: Url = config.rpc_url.parse()?;
-    let provider = ProviderBuilder::new().on_http(rpc_url.clone());
-    let chain_id = provider.get_chain_id().await?;
+    // HTTP, or WebSocket to subscribe to new blocks instead of polling for them.
+    let provider = ProviderBuilder::new()
+        .on_builtin(&config.rpc_url)
+        .await
+        .context(format!("Connecting to {}", config.rpc_url))?;
 
-    let mut strategy = config.strategy.into_dyn();
+    if pairs.len() == 1 {
+        let (base, quote, strategy_config, strategy) = pairs.into_iter().next().unwrap();
+        run(
+            config,
+            base,
+            quote,
+            strategy_config,
+            strategy,
+            provider,
+            metrics,
+        )
+        .await?;
+    } else {
+        // Strategies aren't Send, so every pair runs on this thread.
+        let tasks = tokio::task::LocalSet::new();
+        for (base, quote, strategy_config, strategy) in pairs {
+            let name = format!("{}/{}", base.symbol(), quote.symbol());
 
-    let base = to_token(&config.base, chain_id);
-    let quote = to_token(&config.quote, chain_id);
+            // Each pair gets its own copy of the shared options.
+            let mut config: Config = settings.clone().try_deserialize()?;
+            config.separate_files(&name.replace('/', "-"));
 
-    let mut last_block = None;
-    loop {
-        let block = poll_next_block(&provider, last_block, Duration::from_secs(1)).await?;
-        last_block = Some(block);
-        log::info!("Block {block}");
+            let provider = provider.clone();
+            let metrics = metrics.clone();
+            tasks.spawn_local(PAIR.scope(name, async move {
+                // Only this pair stops, the others keep trading.
+                let result = run(
+                    config,
+                    base,
+                    quote,
+                    strategy_config,
+                    strategy,
+                    provider,
+                    metrics,
+                )
+                .await;
+                if let Err(e) = result {
+                    log::error!("Stopped trading: {e:#}");
+                }
+            }));
+        }
+        tasks.await;
+    }
+
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown()?;
+    }
+
+    Ok(())
+}
+
+/// Trades the pair with `strategy`, built from `strategy_config`, until interrupted or a trade ends
+/// the run.
+async fn run<P: Provider + Clone + 'static>(
+    config: Config,
+    base: ConfigToken,
+    quote: ConfigToken,
+    strategy_config: serde_json::Value,
+    mut strategy: Box<dyn strategy::Strategy>,
+    provider: P,
+    metrics: Arc<metrics::Metrics>,
+) -> anyhow::Result<()> {
+    let (chain_id, replay) = match &config.replay_feed {
+        Some(path) => {
+            let (chain_id, records) = feed::read(path)?;
+            (chain_id, Some(records))
+        }
+        None => (provider.get_chain_id().await?, None),
+    };
+    if replay.is_none() {
+        venue::Venue::router(&venue::Uniswap { chain_id })?;
+    }
+
+    let weth = config
+        .weth_address
+        .as_deref()
+        .map(|a| a.parse().context(format!("Invalid weth_address {a:?}")))
+        .transpose()?;
+    let base = to_token(&base, chain_id, weth);
+    let quote = to_token(&quote, chain_id, weth);
+
+    let symbol = |c: &Currency| c.symbol().map_or("???", |v| v).to_string();
+    let (token0, token1) = if base.wrapped().address() < quote.wrapped().address() {
+        (&base, &quote)
+    } else {
+        (&quote, &base)
+    };
+    log::info!(
+        "Prices are {} per {}, the pool's token0 is {} and token1 is {}",
+        symbol(&base),
+        symbol(&quote),
+        symbol(token0),
+        symbol(token1)
+    );
+
+    let fee: FeeAmount = config.fee_tier.into();
+    if replay.is_none() {
+        match config.pool_address {
+            Some(address) => pool::check_at(address, &base, &quote, fee, &provider).await?,
+            None => pool::ensure_exists(chain_id, &base, &quote, fee, &provider).await?,
+        }
+    }
+
+    let route = config
+        .route
+        .iter()
+        .map(|hop| (to_token(&hop.token, chain_id, weth), hop.fee.into()))
+        .collect::<Vec<(Currency, FeeAmount)>>();
+    if let Some((last, _)) = route.last() {
+        anyhow::ensure!(
+            last.wrapped().address() == quote.wrapped().address(),
+            "The last route hop must be to the quote currency"
+        );
+    }
+    let mut from = &base;
+    for (i, (to, fee)) in route.iter().enumerate() {
+        anyhow::ensure!(
+            from.wrapped().address() != to.wrapped().address(),
+            "Route hop {i} swaps {} to itself",
+            to.symbol().map_or("???", |v| v)
+        );
+        if replay.is_none() {
+            pool::ensure_exists(chain_id, from, to, *fee, &provider)
+                .await
+                .context(format!("Route hop {i}"))?;
+        }
+        from = to;
+    }
+
+    let heartbeat = config.heartbeat.map(heartbeat::Heartbeat::new);
+    let mut watermarks = config.low_watermark.map(watermark::Watermarks::new);
+    let mut outlier_filter = config.outlier_filter.map(outlier::OutlierFilter::new);
+    let mut position = position::Position::default();
+    // Balances of each account trades are sent from, as of its latest trade.
+    let mut account_balances = std::collections::BTreeMap::new();
+    let mut chosen_tier = quote::ChosenTier::default();
+    let mut netting = match config.net_window_blocks {
+        Some(0) => anyhow::bail!("net_window_blocks must be above 0"),
+        Some(n) => Some(netting::Netting::new(n)),
+        None => None,
+    };
 
-        let block_id = BlockId::from(block);
+    if let Some(code) = &config.referral_code {
+        log::warn!("Ignoring referral_code {code:?}, the swap router has no referral parameter");
+    }
+
+    let slippage_escalation = match (config.slippage_step_bps, config.max_slippage_bps) {
+        (Some(0), _) => anyhow::bail!("slippage_step_bps must be above 0"),
+        (Some(step_bps), Some(max_bps)) => {
+            Some(execution::SlippageEscalation { step_bps, max_bps })
+        }
+        (Some(_), None) => anyhow::bail!("slippage_step_bps needs a max_slippage_bps cap"),
+        (None, _) => None,
+    };
+
+    let live_signers = config.mode.signers()?;
+    let live = !live_signers.is_empty();
+    anyhow::ensure!(
+        config.account.is_some()
+            || live
+            || !strategy.needs_balances()
+            || config.replay_feed.is_some(),
+        "The strategy needs balances, set `account` to read them from or trade live"
+    );
+    if live {
+        anyhow::ensure!(
+            config.signers.is_empty(),
+            "signers are impersonated on forks, set mode.live.signer_key_envs to trade live from several accounts"
+        );
+    }
+
+    let executor = execution::Executor {
+        chain_id,
+        rpc_url: config.rpc_url.clone(),
+        weth: execution::wrapped_native(chain_id, weth),
+        base: base.clone(),
+        quote: quote.clone(),
+        signers: config.signers,
+        next_signer: Default::default(),
+        paymaster: config.paymaster,
+        confirmation_timeout: Duration::from_secs(config.confirmation_timeout_secs),
+        approval_timeout: config
+            .interactive
+            .then(|| Duration::from_secs(config.interactive_timeout_secs)),
+        cancel_after: config.cancel_after_secs.map(Duration::from_secs),
+        max_realized_slippage_bps: config.max_realized_slippage_bps,
+        min_out: config.min_out,
+        approval: config.approval,
+        gas: config.gas,
+        max_gas_cost_bps: config.max_gas_cost_bps,
+        gas_token_price: config.gas_token_price,
+        slippage_bps: config.slippage_bps,
+        retry: execution::RetryPolicy {
+            slippage_escalation,
+            adaptive_retry: config.adaptive_retry,
+            min_retry_size: config.min_retry_size.map(Into::into),
+        },
+        fee: config.fee_recipient.map(|f| FeeOptions {
+            fee: Percent::new(f.fee_bps, 10_000),
+            recipient: f.recipient,
+        }),
+        unwrap_output: config.unwrap_output,
+        precision_loss: config.precision_loss,
+        min_gas_balance: config.min_gas_balance.map(Into::into),
+        funding: config.funding,
+        fallback: config
+            .fallback_router
+            .map(|config| venue::Deployment { chain_id, config }),
+        route,
+        live_signers,
+        speculations: Default::default(),
+    };
 
-        let pool = Pool::<EphemeralTickMapDataProvider>::from_pool_key_with_tick_data_provider(
+    // Replays can't make RPC calls, so quotes fail as over budget.
+    let max_rpc_calls_per_block = match replay {
+        Some(_) => Some(0),
+        None => config.max_rpc_calls_per_block,
+    };
+    let rpc_budget = Arc::new(rpc_budget::RpcBudget::new(max_rpc_calls_per_block));
+
+    let quoter = Arc::new(quote::Quoter::new(
+        provider.root().clone(),
+        match config.quoter_address {
+            Some(a) => a,
+            None => quote::MAINNET_QUOTER_V2.parse()?,
+        },
+        base.clone(),
+        quote.clone(),
+        fee,
+        config.quote_cache_ttl_blocks,
+        rpc_budget.clone(),
+        config.precision_loss,
+    ));
+
+    if let Some(records) = replay {
+        return replay_feed(records, &mut strategy, quoter);
+    }
+    if let Some(backtest) = &config.backtest {
+        return backtest::run(
+            backtest,
             chain_id,
-            FACTORY_ADDRESS,
-            base.address(),
-            quote.address(),
-            FeeAmount::LOW,
-            provider.clone(),
-            Some(block_id),
+            config.pool_address,
+            &base,
+            &quote,
+            fee,
+            &provider,
+            &strategy_config,
+            quoter,
         )
-        .await?;
+        .await;
+    }
+    if !config.dry_run && !live {
+        execution::ensure_anvil()?;
+    }
+    if let Some(path) = &config.state_file {
+        load_state(path, &mut strategy, &mut position);
+    }
+    let mut recorder = config
+        .record_feed
+        .as_deref()
+        .map(|path| feed::Recorder::create(path, chain_id))
+        .transpose()?;
+    let mut trade_log = config
+        .trade_log
+        .as_deref()
+        .map(trade_log::TradeLog::open)
+        .transpose()?;
+
+    let pair = format!("{}/{}", symbol(&base), symbol(&quote));
+    let metrics = metrics.pair(&pair);
+
+    let publisher = config.kafka.map(kafka::Publisher::spawn).transpose()?;
+
+    let poll_interval = Duration::from_millis(config.poll_interval_ms);
+    let new_blocks = blocks::NewBlocks::for_url(&config.rpc_url, &provider, poll_interval).await?;
+    let mut blocks = blocks::BlockTracker::new(
+        new_blocks,
+        config.retry,
+        config.last_block_file,
+        config.catch_up,
+    )?;
+    let mut last_price = None;
+    let mut last_liquidity = None;
+    let mut first_block = true;
+
+    let mut cadence = config
+        .evaluation_cadence_secs
+        .map(|secs| Cadence::new(Duration::from_secs(secs), tokio::time::Instant::now()));
+    // Set on Ctrl-C, checked between blocks so an in-flight trade finishes.
+    let (interrupt, mut interrupted) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Interrupted, stopping after the current block");
+            let _ = interrupt.send(true);
+        }
+    });
+
+    'blocks: loop {
+        if let Some(path) = &config.state_file {
+            save_state(path, strategy.as_ref(), &position)?;
+        }
+        let block = tokio::select! {
+            biased;
+            Ok(()) = interrupted.changed() => break 'blocks,
+            block = blocks.next(&provider).instrument(tracing::info_span!("poll")) => block?,
+        };
+        let block_span = telemetry::block_span(block, &pair);
+        rpc_budget.start_block();
+        metrics.block(block);
+        if config.speculative_fork {
+            executor.discard_speculations(block);
+        }
+        log::info!("Block {block}");
+
+        let block_id = config.state_block.block_id(block);
+
+        let pool = config
+            .retry
+            .retry("pool", || {
+                pool::fetch_traded(
+                    config.pool_address,
+                    chain_id,
+                    &base,
+                    &quote,
+                    fee,
+                    &provider,
+                    block_id,
+                )
+            })
+            .instrument(tracing::info_span!(parent: &block_span, "pool"))
+            .await?;
+        rpc_budget.essential(1);
+
+        if let Some(watermarks) = &mut watermarks {
+            if rpc_budget.optional("low watermark", watermarks.calls()) {
+                watermarks
+                    .check(&provider, &base, &quote, config.precision_loss, block_id)
+                    .await?;
+            }
+        }
+
+        if let Some(heartbeat) = &heartbeat {
+            let flow = heartbeat
+                .check(&position, |trade| {
+                    let (executor, pool) = (&executor, pool.clone());
+                    async move {
+                        // Halting regardless of what execution would do next.
+                        executor
+                            .execute(&trade, pool, block, &mut execution::AlwaysWanted)
+                            .await
+                            .map(drop)
+                    }
+                })
+                .await?;
+            if flow.is_break() {
+                break;
+            }
+        }
+
+        let price_lossy = match &config.price_source {
+            price::PriceSource::Aggregate { fee_tiers }
+                if rpc_budget.optional("aggregate price", fee_tiers.len()) =>
+            {
+                let pools = futures::future::try_join_all(fee_tiers.iter().map(|tier| {
+                    pool::fetch(chain_id, &base, &quote, (*tier).into(), &provider, block_id)
+                }))
+                .await?;
+                price::liquidity_weighted(&pools, &quote)?
+            }
+            price::PriceSource::Twap { window_secs } if rpc_budget.optional("TWAP price", 1) => {
+                let address = config
+                    .pool_address
+                    .unwrap_or_else(|| pool.address(None, None));
+                match pool::twap_tick(address, *window_secs, &provider, block_id).await {
+                    Ok(tick) => price::at_tick(&pool, &quote, tick),
+                    Err(e) => {
+                        log::warn!("No {window_secs}s TWAP, using the spot price: {e:#}");
+                        price::of_quote(&pool, &quote)?
+                    }
+                }
+            }
+            // Falls back to the traded pool's price when the aggregate or TWAP is over budget.
+            price::PriceSource::Single
+            | price::PriceSource::Aggregate { .. }
+            | price::PriceSource::Twap { .. } => price::of_quote(&pool, &quote)?,
+        };
+
+        if !(price_lossy.is_finite() && price_lossy > 0.) {
+            log::warn!("Skipping block, invalid price {price_lossy}");
+            continue;
+        }
+        metrics.price(price_lossy);
 
-        let price = pool.token1_price();
+        if let Some(filter) = &mut outlier_filter {
+            if let Some(deviations) = filter.check(price_lossy) {
+                log::warn!(
+                    "Skipping block, price {price_lossy} is {deviations:.1} MADs from the median"
+                );
+                continue;
+            }
+        }
 
         let context = strategy::TradeContext {
-            price_lossy: price.to_significant(8, None)?.parse()?,
+            block_number: block,
+            price_lossy,
+            fee_lossy: pool.fee as u32 as f64 / 1_000_000.,
+            liquidity: pool.liquidity,
+            liquidity_delta: last_liquidity
+                .map_or(0, |last: u128| pool.liquidity as i128 - last as i128),
+            quoter: quoter.clone(),
+            pool: Some(Arc::new(pool.clone())),
+            swaps: if strategy.needs_swaps() {
+                pool::swaps(&provider, &pool, &quote, block).await?
+            } else {
+                Vec::new()
+            },
+            balances: match config
+                .account
+                .or(executor.live_holder())
+                .filter(|_| strategy.needs_balances())
+            {
+                Some(account) => Some(balances(&provider, account, &base, &quote, block_id).await?),
+                None => None,
+            },
         };
+        last_liquidity = Some(pool.liquidity);
+
+        if let Some((from_block, from_price)) =
+            last_price.filter(|_| config.interpolate_missed_blocks)
+        {
+            for (missed, price_lossy) in
+                interpolate(from_block, from_price, block, context.price_lossy)
+            {
+                let context = strategy::TradeContext {
+                    block_number: missed,
+                    price_lossy,
+                    liquidity_delta: 0,
+                    swaps: Vec::new(),
+                    ..context.clone()
+                };
+                log::info!("Feeding interpolated block {missed} with context {context:?}");
+                if let Some(recorder) = &mut recorder {
+                    recorder.record(&context, true)?;
+                }
+                seed(
+                    strategy.as_mut(),
+                    &context,
+                    &format!("interpolated block {missed}"),
+                );
+            }
+        }
+        last_price = Some((block, context.price_lossy));
+
+        if std::mem::take(&mut first_block) && config.skip_first_block {
+            log::info!("Seeding strategy with first block, context {context:?}");
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&context, true)?;
+            }
+            seed(strategy.as_mut(), &context, "the first block");
+            continue;
+        }
+
+        if let Some(cadence) = &mut cadence {
+            let now = tokio::time::Instant::now();
+            if !cadence.due(now) {
+                log::info!("Next strategy evaluation in {:?}", cadence.next - now);
+                block_span.record("decision", "not evaluated");
+                if config.status_line {
+                    log::info!(
+                        "{}",
+                        status_line(block, price_lossy, &position, "not evaluated")
+                    );
+                }
+                continue;
+            }
+        }
 
         log::info!("Executing strategy with context {context:?}");
-        let Some(trade) = strategy.trade(&context) else {
+        if let Some(recorder) = &mut recorder {
+            recorder.record(&context, false)?;
+        }
+        let mut trades = tracing::info_span!(parent: &block_span, "strategy")
+            .in_scope(|| strategy.trade(&context));
+        let cancelled = strategy.cancellations();
+        if let Some(netting) = &mut netting {
+            netting.cancel(&cancelled);
+            trades = netting.net(block, trades).into_iter().collect();
+        } else if !cancelled.is_empty() {
+            log::warn!(
+                "Ignoring cancelled intents {cancelled:?}, only trades queued by net_window_blocks can be cancelled"
+            );
+        }
+        if trades.is_empty() {
             log::info!("Strategy produced no trade");
+            block_span.record("decision", "none");
+            if config.status_line {
+                log::info!("{}", status_line(block, price_lossy, &position, "none"));
+            }
             continue;
-        };
-        log::info!("Strategy produced {trade:?}");
+        }
 
-        // TODO(shelbyd): Allow providing private key and executing on actual chain.
-        let provider = ProviderBuilder::new().on_anvil_with_config(|anvil| {
-            log::info!("Forking chain {chain_id} at {block}");
-            anvil.fork(config.rpc_url.clone()).fork_block_number(block)
-        });
-        // TODO(shelbyd): Get anvil to produce blocks?
-        let after_trade = ControlFlow::Break("Chain forked for testing, so no blocks are produced");
-
-        let account = provider.get_accounts().await?[0];
-
-        let params = match trade {
-            strategy::Trade::Buy { amount } => {
-                let route = Route::new(vec![pool], base.clone(), quote.clone());
-                let trade = Trade::from_route(
-                    route,
-                    from_human_amount(amount, &quote)?,
-                    TradeType::ExactOutput,
-                )?;
-                swap_call_parameters(
-                    &mut [trade],
-                    SwapOptions {
-                        recipient: account,
-                        ..Default::default()
-                    },
-                )?
-            }
-            strategy::Trade::Sell { amount } => {
-                let route = Route::new(vec![pool], quote.clone(), base.clone());
-                let trade = Trade::from_route(
-                    route,
-                    from_human_amount(amount, &quote)?,
-                    TradeType::ExactInput,
-                )?;
-                swap_call_parameters(
-                    &mut [trade],
-                    SwapOptions {
-                        recipient: account,
-                        ..Default::default()
-                    },
-                )?
+        cap_trades(&mut trades, config.max_trades_per_block, &mut *strategy);
+
+        let decision = trades
+            .iter()
+            .map(describe)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join(", ");
+        block_span.record("decision", decision.as_str());
+        if config.status_line {
+            log::info!("{}", status_line(block, price_lossy, &position, &decision));
+        }
+
+        // Processing the block may have taken long enough that its tick data is stale.
+        let (pool, execution_block) = match config.max_tick_data_lag {
+            Some(max_lag) => {
+                let head = provider.get_block_number().await?;
+                rpc_budget.essential(1);
+                refresh_if_stale(pool, block, head, max_lag, |head| {
+                    rpc_budget.essential(1);
+                    pool::fetch_traded(
+                        config.pool_address,
+                        chain_id,
+                        &base,
+                        &quote,
+                        fee,
+                        &provider,
+                        BlockId::from(head),
+                    )
+                })
+                .await?
             }
+            None => (pool, block),
         };
 
-        let tx = TransactionRequest::default()
-            .from(account)
-            .to(*SWAP_ROUTER_02_ADDRESSES
-                .get(&chain_id)
-                .context(format!("Unknown swap router for chain id {chain_id}"))?)
-            .input(params.calldata.into())
-            .value(params.value);
+        for trade in trades {
+            log::info!("Strategy produced {trade:?}");
+
+            if let Some(held) =
+                position.within_holding_period(&trade, block, config.min_hold_blocks)
+            {
+                log::info!(
+                    "Suppressing sell, position held for {held} of {} blocks",
+                    config.min_hold_blocks
+                );
+                strategy.on_execution_failed(&trade);
+                continue;
+            }
 
-        log_balance("(base) before trade", account, &base, &provider).await?;
-        log_balance("(quot) before trade", account, &quote, &provider).await?;
+            let trade = match trade.options().percent_of_balance {
+                None => trade,
+                Some(percent) => {
+                    let Some(account) = config.account.or(executor.live_holder()) else {
+                        log::error!("Can't size {trade:?} by balance, set `account`");
+                        strategy.on_execution_failed(&trade);
+                        continue;
+                    };
+                    let sized = percent_of_balance(
+                        &trade,
+                        percent,
+                        account,
+                        (&base, &quote),
+                        price_lossy,
+                        &provider,
+                        config.state_block.block_id(execution_block),
+                        (&quoter, execution_block, worst_slippage(&config, &trade)),
+                    )
+                    .await;
+                    match sized {
+                        Ok(Some(sized)) => sized,
+                        Ok(None) => {
+                            log::info!("Skipping {trade:?}, no balance to trade {percent}% of");
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to size {trade:?} by balance: {e:#}");
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                    }
+                }
+            };
 
-        let hash = provider.send_transaction(tx).await?.watch().await?;
-        log::info!("Successfully executed transaction {hash}");
+            let trade = match &config.sizing {
+                None => trade,
+                Some(sizing) => {
+                    let sized = sizing
+                        .size(&trade, &quoter, price_lossy, block, quote.decimals())
+                        .await;
+                    match sized {
+                        Ok(Some(sized)) => sized,
+                        Ok(None) => {
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                        Err(e) => {
+                            log::error!("Failed to size {trade:?}: {e:#}");
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                    }
+                }
+            };
 
-        log_balance("(base) after trade", account, &base, &provider).await?;
-        log_balance("(quot) after trade", account, &quote, &provider).await?;
+            let (min, max) = (
+                config.min_position.map(Fraction::from),
+                config.max_position.map(Fraction::from),
+            );
+            let trade = match position.room(&trade, min.as_ref(), max.as_ref()) {
+                Some(room) => {
+                    let amount = trade.amount();
+                    let fits = room.numerator.clone() * &amount.denominator
+                        >= amount.numerator.clone() * &room.denominator;
+                    match (&trade, fits) {
+                        (_, true) => trade,
+                        (strategy::Trade::Buy { .. }, false)
+                            if room.numerator > BigInt::from(0) =>
+                        {
+                            log::info!(
+                                "Reducing {trade:?} to {} to stay within max_position",
+                                room.to_significant(6, None)?
+                            );
+                            trade.with_amount(room)
+                        }
+                        _ => {
+                            log::info!(
+                                "Skipping {trade:?}, it would take the position past its limit"
+                            );
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                    }
+                }
+                None => trade,
+            };
 
-        match after_trade {
-            ControlFlow::Continue(()) => {}
-            ControlFlow::Break(msg) => {
-                log::info!("Terminating due to after trade 'break': {msg}");
-                break;
+            let mut tiers: Vec<FeeAmount> =
+                config.route_fee_tiers.iter().map(|&t| t.into()).collect();
+            if let Some(min) = config.min_auto_liquidity.filter(|_| !tiers.is_empty()) {
+                tiers = pool::deep_enough(
+                    chain_id,
+                    &base,
+                    &quote,
+                    &tiers,
+                    min,
+                    &provider,
+                    BlockId::from(execution_block),
+                )
+                .await;
+                if tiers.is_empty() {
+                    log::warn!("Skipping {trade:?}, no fee tier has at least {min} liquidity");
+                    strategy.on_execution_failed(&trade);
+                    continue;
+                }
+            }
+            let best = quoter
+                .best_fee(
+                    &tiers,
+                    trade.direction(),
+                    trade.amount().clone(),
+                    execution_block,
+                )
+                .await;
+            let changed = best.and_then(|best| chosen_tier.choose(best, block));
+            if changed.is_some() && config.reseed_on_tier_change {
+                log::info!("Re-seeding strategy state for the new fee tier");
+                strategy = serde_json::from_value::<strategy::Config>(strategy_config.clone())?
+                    .into_dyn()?;
+            }
+            let pool = match best {
+                Some(best) if best != fee => {
+                    log::info!("Routing {trade:?} through the {best:?} tier");
+                    let fetched = pool::fetch(
+                        chain_id,
+                        &base,
+                        &quote,
+                        best,
+                        &provider,
+                        BlockId::from(execution_block),
+                    )
+                    .await;
+                    match fetched {
+                        Ok(pool) => pool,
+                        Err(e) => {
+                            log::error!("Failed to fetch the {best:?} tier pool: {e:#}");
+                            strategy.on_execution_failed(&trade);
+                            continue;
+                        }
+                    }
+                }
+                _ => pool.clone(),
+            };
+
+            // The strategy carries on as if dry run trades executed, so the logs show what it would do.
+            if config.dry_run {
+                let account = config.account.or(executor.live_account());
+                if let Err(e) = executor.dry_run(&provider, account, &trade, pool).await {
+                    log::error!("Dry run of {trade:?} failed: {e:#}");
+                }
+                continue;
+            }
+
+            let mut still_wanted = StrategyStillWants {
+                provider: &provider,
+                chain_id,
+                base: &base,
+                quote: &quote,
+                fee,
+                pool_address: config.pool_address,
+                strategy: &mut strategy,
+                context: &context,
+            };
+            metrics.attempted();
+            let result = executor
+                .execute(&trade, pool, execution_block, &mut still_wanted)
+                .instrument(tracing::info_span!(parent: &block_span, "execute", ?trade))
+                .await;
+            metrics.executed(result.is_ok());
+            let execution = match result {
+                Ok(execution) => execution,
+                Err(e) => {
+                    log::error!("Failed to execute {trade:?}: {e:#}");
+                    strategy.on_execution_failed(&trade);
+                    continue;
+                }
+            };
+            log::info!("Executed {}", execution.summary()?);
+            if let Some(trade_log) = &mut trade_log {
+                trade_log.write(&trade_log::Record::new(block, price_lossy, &execution)?)?;
+            }
+            if let Some(publisher) = &publisher {
+                publisher.publish(kafka::TradeEvent::new(block, &pair, &execution)?);
+            }
+            let fill_price = execution.price_lossy()?;
+            position.apply(&execution.trade, block, fill_price);
+            match execution::gas_in_base(
+                execution.gas_cost_wei,
+                executor.weth,
+                &base,
+                &quote,
+                fill_price,
+                config.gas_token_price,
+            ) {
+                Some(gas) => position.charge_gas(gas),
+                None => log::warn!("Can't value gas in the base currency, set gas_token_price"),
+            }
+            log::info!("Realized PnL {:.6}", position.realized_pnl());
+            account_balances.insert(execution.account, execution.balances_after);
+            if account_balances.len() > 1 {
+                for (account, balances) in &account_balances {
+                    log::info!(
+                        "{account} holds {} {} and {} {}",
+                        balances.base,
+                        symbol(&base),
+                        balances.quote,
+                        symbol(&quote)
+                    );
+                }
+            }
+            metrics.position(position.net_lossy());
+            if config.speculative_fork && !live {
+                executor.speculate_next(execution_block);
+            }
+
+            match execution.after_trade {
+                ControlFlow::Continue(()) => {}
+                ControlFlow::Break(msg) => {
+                    log::info!("Terminating due to after trade 'break': {msg}");
+                    break 'blocks;
+                }
+            }
+
+            if config.run_once {
+                log::info!("Stopping after the first trade, run_once is set");
+                break 'blocks;
             }
         }
     }
 
+    blocks.finish()?;
+    if let Some(path) = &config.state_file {
+        save_state(path, strategy.as_ref(), &position)?;
+    }
+
+    if *interrupted.borrow() {
+        let accounts = match config.account {
+            Some(account) => vec![account],
+            None => executor.live_accounts(),
+        };
+        if accounts.is_empty() {
+            log::info!("No account to log final balances of, set `account`");
+        }
+        for account in accounts {
+            execution::log_balance("(base) at shutdown", account, &base, &provider).await?;
+            execution::log_balance("(quot) at shutdown", account, &quote, &provider).await?;
+        }
+    }
+
+    if let Some(publisher) = publisher {
+        publisher.close().await?;
+    }
+
     Ok(())
 }
 
-async fn log_balance(
-    suffix: &str,
-    account: Address,
-    currency: &Currency,
-    provider: &impl alloy::providers::Provider,
+/// Feeds the strategy recorded contexts, logging its decisions as if every trade executed.
+fn replay_feed(
+    records: Vec<feed::Record>,
+    strategy: &mut Box<dyn strategy::Strategy>,
+    quoter: Arc<quote::Quoter>,
 ) -> anyhow::Result<()> {
-    alloy::sol! {
-        #[sol(rpc)]
-        interface ERC20 {
-            function balanceOf(address target) returns (uint256);
+    log::info!("Replaying {} recorded blocks", records.len());
+
+    for record in records {
+        let context = record.context(quoter.clone())?;
+        log::info!("Executing strategy with context {context:?}");
+        let trades = strategy.trade(&context);
+        if trades.is_empty() {
+            log::info!("Strategy produced no trade");
+        }
+
+        for trade in trades {
+            if record.discarded {
+                log::info!(
+                    "Discarding {trade:?} produced for block {}",
+                    context.block_number
+                );
+                strategy.on_execution_failed(&trade);
+            } else {
+                log::info!("Strategy produced {trade:?}");
+            }
         }
     }
 
-    let balance = match currency {
-        Currency::NativeCurrency(_) => provider.get_balance(account).await?,
-        Currency::Token(t) => {
-            let erc20 = ERC20::new(t.address(), provider);
-            erc20.balanceOf(account).call().await?._0
+    Ok(())
+}
+
+/// Strategy and position state saved by `save_state`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedState {
+    strategy: Option<serde_json::Value>,
+    position: serde_json::Value,
+}
+
+/// Restores the strategy's and position's state saved by `save_state`, starting fresh if there is
+/// none or it can't be used, e.g. the strategy config changed.
+fn load_state(
+    path: &Path,
+    strategy: &mut Box<dyn strategy::Strategy>,
+    position: &mut position::Position,
+) {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::info!("No strategy state at {path:?}, starting fresh");
+            return;
+        }
+        Err(e) => {
+            log::warn!("Failed to read strategy state {path:?}, starting fresh: {e}");
+            return;
+        }
+    };
+    let saved: SavedState = match serde_json::from_str(&contents) {
+        Ok(saved) => saved,
+        Err(e) => {
+            log::warn!("Failed to parse strategy state {path:?}, starting fresh: {e}");
+            return;
         }
     };
 
-    log::info!(
-        "{account} has {} {} {suffix}",
-        CurrencyAmount::from_raw_amount(currency, balance.to_big_int())?.to_exact(),
-        currency.symbol().map_or("???", |v| v)
-    );
+    let loaded = saved.strategy.map_or(Ok(()), |state| strategy.load(state));
+    match loaded {
+        Ok(()) => log::info!("Restored strategy state from {path:?}"),
+        Err(e) => log::warn!("Failed to restore strategy state {path:?}, starting fresh: {e:#}"),
+    }
+    match position::Position::load(saved.position) {
+        Ok(loaded) => {
+            *position = loaded;
+            log::info!(
+                "Restored position of {} from {path:?}",
+                position.net_lossy()
+            );
+        }
+        Err(e) => log::warn!("Failed to restore position {path:?}, starting flat: {e:#}"),
+    }
+}
 
+/// Writes the strategy's and position's state through a temporary file, so a crash mid-write leaves
+/// the previous state intact.
+fn save_state(
+    path: &Path,
+    strategy: &dyn strategy::Strategy,
+    position: &position::Position,
+) -> anyhow::Result<()> {
+    let state = SavedState {
+        strategy: strategy.save(),
+        position: position.save(),
+    };
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec(&state)?).context(format!("Writing {tmp:?}"))?;
+    std::fs::rename(&tmp, path).context(format!("Renaming {tmp:?} to {path:?}"))?;
     Ok(())
 }
 
-fn to_token(t: &ConfigToken, chain_id: u64) -> Currency {
+/// Re-runs the strategy on the latest block to decide whether a stuck trade is still wanted.
+struct StrategyStillWants<'a, P> {
+    provider: &'a P,
+    chain_id: u64,
+    base: &'a Currency,
+    quote: &'a Currency,
+    fee: FeeAmount,
+    pool_address: Option<Address>,
+    strategy: &'a mut Box<dyn strategy::Strategy>,
+
+    /// Context the trade was produced with.
+    context: &'a strategy::TradeContext,
+}
+
+#[async_trait::async_trait(?Send)]
+impl<P: Provider + Clone> execution::StillWanted for StrategyStillWants<'_, P> {
+    async fn still_wanted(&mut self, trade: &strategy::Trade) -> anyhow::Result<bool> {
+        let block = self.provider.get_block_number().await?;
+        let pool = pool::fetch_traded(
+            self.pool_address,
+            self.chain_id,
+            self.base,
+            self.quote,
+            self.fee,
+            self.provider,
+            BlockId::from(block),
+        )
+        .await?;
+
+        let context = strategy::TradeContext {
+            block_number: block,
+            price_lossy: price::of_quote(&pool, self.quote)?,
+            liquidity: pool.liquidity,
+            liquidity_delta: pool.liquidity as i128 - self.context.liquidity as i128,
+            pool: Some(Arc::new(pool)),
+            swaps: Vec::new(),
+            ..self.context.clone()
+        };
+        log::info!("Checking pending {trade:?} is still wanted with context {context:?}");
+
+        // Only asking, the pending trade is the one that would execute.
+        let wanted = self.strategy.trade(&context);
+        for t in &wanted {
+            self.strategy.on_execution_failed(t);
+        }
+        Ok(wanted.iter().any(|t| t.direction() == trade.direction()))
+    }
+}
+
+/// The config file at `path`, in the format its extension names.
+fn load_settings(path: &Path) -> anyhow::Result<::config::Config> {
+    let source = config::File::from(path).format(config_format(path)?);
+    Ok(::config::Config::builder().add_source(source).build()?)
+}
+
+/// `pool`, fetched at `block`, or if that's more than `max_lag` blocks behind `head` the pool
+/// `refetch` gets at `head`. With the block the pool is from.
+async fn refresh_if_stale<T, Fut>(
+    pool: T,
+    block: u64,
+    head: u64,
+    max_lag: u64,
+    refetch: impl FnOnce(u64) -> Fut,
+) -> anyhow::Result<(T, u64)>
+where
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    if head.saturating_sub(block) <= max_lag {
+        return Ok((pool, block));
+    }
+    log::warn!(
+        "Tick data from block {block} is {} blocks behind head {head}, re-fetching",
+        head - block
+    );
+    Ok((refetch(head).await?, head))
+}
+
+/// Format of a config file, from its extension.
+fn config_format(path: &Path) -> anyhow::Result<config::FileFormat> {
+    let extension = path.extension().and_then(|e| e.to_str());
+    Ok(match extension {
+        Some("yaml" | "yml") => config::FileFormat::Yaml,
+        Some("toml") => config::FileFormat::Toml,
+        Some("json") => config::FileFormat::Json,
+        _ => anyhow::bail!("Unknown config format for {path:?}, expected .yaml, .toml, or .json"),
+    })
+}
+
+impl ConfigToken {
+    fn symbol(&self) -> &str {
+        match self {
+            ConfigToken::Native => "native",
+            ConfigToken::Erc20 { symbol, .. } => symbol,
+        }
+    }
+}
+
+fn to_token(t: &ConfigToken, chain_id: u64, weth: Option<Address>) -> Currency {
     match t {
-        ConfigToken::Native => Currency::NativeCurrency(Ether::on_chain(chain_id)),
+        ConfigToken::Native => match weth {
+            Some(weth) if WETH9::on_chain(chain_id).map(|w| w.address()) != Some(weth) => {
+                log::warn!("Routing native currency as the configured wrapped token {weth}");
+                Currency::Token(token!(chain_id, weth, 18, "WETH"))
+            }
+            _ => Currency::NativeCurrency(Ether::on_chain(chain_id)),
+        },
         ConfigToken::Erc20 {
             symbol: name,
             address,
@@ -210,28 +1559,534 @@ fn to_token(t: &ConfigToken, chain_id: u64) -> Currency {
     }
 }
 
-async fn poll_next_block(
-    provider: &impl alloy::providers::Provider,
-    last_block: Option<u64>,
-    between_calls: Duration,
-) -> anyhow::Result<u64> {
-    loop {
-        let n = provider.get_block_number().await?;
-        if Some(n) != last_block {
-            return Ok(n);
+/// Feeds `context` to the strategy only to update its state, rolling back any trades it makes.
+fn seed(strategy: &mut dyn strategy::Strategy, context: &strategy::TradeContext, what: &str) {
+    for trade in strategy.trade(context) {
+        log::info!("Discarding {trade:?} produced for {what}");
+        strategy.on_execution_failed(&trade);
+    }
+}
+
+/// Single line summary of the bot's state for operators.
+fn status_line(block: u64, price: f64, position: &position::Position, decision: &str) -> String {
+    let net = position.net_lossy();
+    let side = match net.partial_cmp(&0.) {
+        Some(std::cmp::Ordering::Greater) => "long",
+        Some(std::cmp::Ordering::Less) => "short",
+        _ => "flat",
+    };
+    format!(
+        "STATUS block={block} price={price:.6} position={side} {:.6} unrealized_pnl={:.6} realized_pnl={:.6} decision={decision}",
+        net.abs(),
+        position.unrealized_pnl(price),
+        position.realized_pnl(),
+    )
+}
+
+/// `trade` resized to `percent` of `account`'s balance of the currency it spends, at most its
+/// amount. None if that's nothing. Buys are exact output, so their cost is quoted at `block` and
+/// they're shrunk until it fits in the balance with the pool fee, price impact and `slippage` on top,
+/// as the router may take that much.
+#[allow(clippy::too_many_arguments)]
+async fn percent_of_balance(
+    trade: &strategy::Trade,
+    percent: f64,
+    account: Address,
+    (base, quote): (&Currency, &Currency),
+    price: f64,
+    provider: &impl Provider,
+    block_id: BlockId,
+    (quoter, block, slippage): (&quote::Quoter, u64, f64),
+) -> anyhow::Result<Option<strategy::Trade>> {
+    // Amounts are in the quote currency, a buy's base balance is converted at `price`.
+    let (spent, to_quote) = match trade {
+        strategy::Trade::Buy { .. } => (base, 1. / price),
+        strategy::Trade::Sell { .. } => (quote, 1.),
+    };
+    let raw = execution::balance(provider, account, spent, block_id).await?;
+    let balance: f64 = CurrencyAmount::from_raw_amount(spent.clone(), raw.to_big_int())?
+        .to_exact()
+        .parse()?;
+    let budget = balance * percent / 100.;
+
+    let scale = 10u128.pow(quote.decimals() as u32);
+    let to_fraction = |amount: f64| Fraction::new((amount * scale as f64).floor() as u128, scale);
+    let amount = to_fraction(budget * to_quote);
+    if amount.numerator == BigInt::from(0) {
+        return Ok(None);
+    }
+
+    let cap = trade.amount();
+    let within_cap =
+        amount.numerator.clone() * &cap.denominator <= cap.numerator.clone() * &amount.denominator;
+    log::info!(
+        "{percent}% of {balance} {} is {} {}",
+        spent.symbol().map_or("???", |v| v),
+        amount.to_significant(6, None)?,
+        quote.symbol().map_or("???", |v| v),
+    );
+    let mut amount = if within_cap { amount } else { cap.clone() };
+
+    if let strategy::Trade::Buy { .. } = trade {
+        let cost = quoter
+            .quote(quote::Direction::Buy, amount.clone(), block)
+            .await?;
+        let max_cost = cost * (1. + slippage);
+        if max_cost > budget {
+            let lossy = amount.to_significant(18, None)?.parse::<f64>()?;
+            amount = to_fraction(lossy * budget / max_cost);
+            log::info!(
+                "Buying {} instead, {lossy} would cost up to {max_cost} with the fee and slippage",
+                amount.to_significant(6, None)?
+            );
+            if amount.numerator == BigInt::from(0) {
+                return Ok(None);
+            }
         }
-        tokio::time::sleep(between_calls).await;
     }
+    Ok(Some(trade.with_amount(amount)))
 }
 
-fn from_human_amount(
-    amount: Fraction,
-    currency: &Currency,
-) -> anyhow::Result<CurrencyAmount<Currency>> {
-    let amount = CurrencyAmount::from_fractional_amount(
-        currency.clone(),
-        amount.numerator,
-        amount.denominator,
+/// The most slippage `trade` may execute with, as a fraction, after any escalation on retries.
+fn worst_slippage(config: &Config, trade: &strategy::Trade) -> f64 {
+    match &trade.options().slippage_tolerance {
+        Some(tolerance) => {
+            let lossy = |v: &BigInt| v.to_f64().unwrap_or(f64::NAN);
+            lossy(&tolerance.numerator) / lossy(&tolerance.denominator)
+        }
+        None => config.max_slippage_bps.unwrap_or(config.slippage_bps) as f64 / 10_000.,
+    }
+}
+
+/// `account`'s balances of the pair at `block_id`, in human units.
+async fn balances(
+    provider: &impl Provider,
+    account: Address,
+    base: &Currency,
+    quote: &Currency,
+    block_id: BlockId,
+) -> anyhow::Result<strategy::Balances> {
+    let lossy = |currency: &Currency, raw: U256| -> anyhow::Result<f64> {
+        Ok(
+            CurrencyAmount::from_raw_amount(currency.clone(), raw.to_big_int())?
+                .to_exact()
+                .parse()?,
+        )
+    };
+    let (base_raw, quote_raw) = futures::try_join!(
+        execution::balance(provider, account, base, block_id),
+        execution::balance(provider, account, quote, block_id),
     )?;
-    Ok(amount.multiply(&Fraction::new(amount.meta().decimal_scale.clone(), 1))?)
+    Ok(strategy::Balances {
+        base: lossy(base, base_raw)?,
+        quote: lossy(quote, quote_raw)?,
+    })
+}
+
+fn describe(trade: &strategy::Trade) -> anyhow::Result<String> {
+    let side = match trade {
+        strategy::Trade::Buy { .. } => "buy",
+        strategy::Trade::Sell { .. } => "sell",
+    };
+    Ok(format!(
+        "{side} {}",
+        trade.amount().to_significant(6, None)?
+    ))
+}
+
+/// Drops trades beyond `max`, telling the strategy they weren't executed.
+fn cap_trades(
+    trades: &mut Vec<strategy::Trade>,
+    max: Option<usize>,
+    strategy: &mut dyn strategy::Strategy,
+) {
+    let Some(max) = max.filter(|&max| trades.len() > max) else {
+        return;
+    };
+    log::warn!(
+        "Dropping {} trades over max_trades_per_block {max}",
+        trades.len() - max
+    );
+    for dropped in trades.drain(max..) {
+        strategy.on_execution_failed(&dropped);
+    }
+}
+
+/// Evaluations on a fixed wall-clock cadence, each on whichever block is latest when it's due.
+struct Cadence {
+    every: Duration,
+    next: tokio::time::Instant,
+}
+
+impl Cadence {
+    fn new(every: Duration, start: tokio::time::Instant) -> Self {
+        Cadence { every, next: start }
+    }
+
+    /// Whether an evaluation is due at `now`. If so, the next is scheduled on the cadence, skipping
+    /// any that passed without a block.
+    fn due(&mut self, now: tokio::time::Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        while self.next <= now {
+            self.next += self.every;
+        }
+        true
+    }
+}
+
+/// Linearly interpolated prices for the blocks strictly between `from` and `to`.
+fn interpolate(
+    from_block: u64,
+    from_price: f64,
+    to_block: u64,
+    to_price: f64,
+) -> impl Iterator<Item = (u64, f64)> {
+    let span = to_block.saturating_sub(from_block) as f64;
+    (from_block + 1..to_block).map(move |n| {
+        let t = (n - from_block) as f64 / span;
+        (n, from_price + (to_price - from_price) * t)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{address, keccak256, Bytes},
+        sol_types::SolValue,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        mock_rpc::MockRpc,
+        strategy::{testing::*, Strategy},
+    };
+
+    #[test]
+    fn interpolates_a_multi_block_gap() {
+        let missed: Vec<_> = interpolate(10, 100., 14, 108.).collect();
+        assert_eq!(missed, [(11, 102.), (12, 104.), (13, 106.)]);
+
+        assert_eq!(interpolate(10, 100., 11, 108.).count(), 0);
+    }
+
+    #[test]
+    fn interpolated_blocks_reach_the_strategy_in_order() {
+        let quoter = quoter();
+        let mut recorder = Recorder::default();
+        for (block, price) in interpolate(1, 90., 5, 50.) {
+            recorder.trade(&context(&quoter, block, price));
+        }
+        assert_eq!(recorder.prices, [80., 70., 60.]);
+    }
+
+    #[test]
+    fn weth_override_wraps_a_native_leg_on_a_custom_chain() {
+        let custom_chain = 424_242;
+        let weth = address!("4200000000000000000000000000000000000006");
+
+        let Currency::Token(token) = to_token(&ConfigToken::Native, custom_chain, Some(weth))
+        else {
+            panic!("Expected the override token");
+        };
+        assert_eq!(token.address(), weth);
+        assert_eq!(token.chain_id(), custom_chain);
+
+        let native = |chain_id, weth| {
+            matches!(
+                to_token(&ConfigToken::Native, chain_id, weth),
+                Currency::NativeCurrency(_)
+            )
+        };
+        assert!(native(custom_chain, None));
+        // Overriding with the SDK's own address changes nothing.
+        assert!(native(1, Some(WETH9::on_chain(1).unwrap().address())));
+    }
+
+    #[test]
+    fn evaluates_on_the_cadence_regardless_of_block_frequency() {
+        let start = tokio::time::Instant::now();
+        let evaluations = |block_secs: u64| {
+            let mut cadence = Cadence::new(Duration::from_secs(60), start);
+            (0..=300 / block_secs)
+                .map(|i| Duration::from_secs(i * block_secs))
+                .filter(|&at| cadence.due(start + at))
+                .map(|at| at.as_secs())
+                .collect::<Vec<_>>()
+        };
+
+        // The first block at or after each minute.
+        assert_eq!(evaluations(1), [0, 60, 120, 180, 240, 300]);
+        assert_eq!(evaluations(12), [0, 60, 120, 180, 240, 300]);
+        assert_eq!(evaluations(7), [0, 63, 126, 182, 245]);
+        // Slower blocks than the cadence are each evaluated.
+        assert_eq!(evaluations(90), [0, 90, 180, 270]);
+    }
+
+    #[test]
+    fn position_is_restored_with_the_strategy_state() {
+        let path = std::env::temp_dir().join(format!("{}_state.json", std::process::id()));
+        let dca = || {
+            serde_json::from_value::<strategy::Config>(serde_json::json!({
+                "dca": { "amount": 1, "every_blocks": 2 },
+            }))
+            .unwrap()
+            .into_dyn()
+            .unwrap()
+        };
+        let mut before = dca();
+        run(&mut before, &[100.]);
+        let mut position = position::Position::default();
+        position.apply(&buy(2), 1, 100.);
+        save_state(&path, before.as_ref(), &position).unwrap();
+
+        let mut after = dca();
+        let mut restored = position::Position::default();
+        load_state(&path, &mut after, &mut restored);
+        assert_eq!(restored.net_lossy(), 2.);
+        assert_eq!(restored.held_for(3), Some(2));
+        // The strategy's schedule carried on too.
+        assert_eq!(signals(&run(&mut after, &[100.])[0]), []);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn buying_all_of_the_balance_leaves_headroom_for_the_fee_and_slippage() {
+        // Holding 2000 USDC, with WETH quoted at 2001 USDC after the fee.
+        let balance_of = keccak256("balanceOf(address)")[..4].to_vec();
+        let rpc = MockRpc::serve(move |method, params| match method {
+            "eth_call" => {
+                let call = &params[0];
+                let input: Bytes = serde_json::from_value(call["input"].clone())
+                    .or_else(|_| serde_json::from_value(call["data"].clone()))
+                    .map_err(|e| e.to_string())?;
+                if input.starts_with(&balance_of) {
+                    return Ok(json!(Bytes::from(
+                        U256::from(2_000_000_000u64).abi_encode()
+                    )));
+                }
+                // quoteExactOutputSingle's amount, after tokenIn and tokenOut.
+                let weth = U256::from_be_slice(&input[4 + 64..4 + 96]);
+                let usdc = weth * U256::from(2001) / U256::from(1_000_000_000_000u64);
+                let quoted = (usdc, U256::ZERO, U256::ZERO, U256::ZERO);
+                Ok(json!(Bytes::from(quoted.abi_encode())))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let quoter = quote::Quoter::new(
+            provider.root().clone(),
+            Address::repeat_byte(0x9a),
+            usdc(),
+            weth(),
+            FeeAmount::LOW,
+            0,
+            Arc::new(rpc_budget::RpcBudget::new(None)),
+            execution::PrecisionLoss::Error,
+        );
+        let all = strategy::Trade::Buy {
+            amount: Fraction::new(10, 1),
+            limit_price: None,
+            options: strategy::TradeOptions {
+                percent_of_balance: Some(100.),
+                ..Default::default()
+            },
+        };
+
+        let sized = percent_of_balance(
+            &all,
+            100.,
+            Address::repeat_byte(0xaa),
+            (&usdc(), &weth()),
+            2000.,
+            &provider,
+            BlockId::latest(),
+            (&quoter, 10, 0.005),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        // Not the whole 1 WETH the balance buys at the mid price, which would revert.
+        let bought = amount_lossy(sized.amount());
+        assert!(bought < 1.);
+        assert!(bought * 2001. * 1.005 <= 2000.);
+        assert!(bought > 0.99);
+    }
+
+    #[test]
+    fn trades_over_the_cap_are_dropped() {
+        let mut runaway = Scripted::new(vec![(0..10).map(|_| buy(1)).collect()]);
+        let mut trades = run(&mut runaway, &[100.]).remove(0);
+
+        cap_trades(&mut trades, Some(3), &mut runaway);
+        assert_eq!(trades.len(), 3);
+        assert_eq!(runaway.failed, 7);
+
+        cap_trades(&mut trades, None, &mut runaway);
+        cap_trades(&mut trades, Some(3), &mut runaway);
+        assert_eq!(trades.len(), 3);
+        assert_eq!(runaway.failed, 7);
+    }
+
+    #[test]
+    fn first_block_only_seeds_the_strategy() {
+        let quoter = quoter();
+        let mut strategy = Scripted::new(vec![vec![buy(1)], vec![buy(2)]]);
+
+        seed(&mut strategy, &context(&quoter, 1, 100.), "the first block");
+        assert_eq!(strategy.failed, 1);
+
+        let trades = strategy.trade(&context(&quoter, 2, 100.));
+        assert_eq!(signals(&trades), [(quote::Direction::Buy, 2.)]);
+        assert_eq!(strategy.failed, 1);
+    }
+
+    #[test]
+    fn status_line_summarizes_the_position() {
+        let mut position = position::Position::default();
+        assert_eq!(
+            status_line(7, 100., &position, "none"),
+            "STATUS block=7 price=100.000000 position=flat 0.000000 unrealized_pnl=0.000000 realized_pnl=0.000000 decision=none"
+        );
+
+        position.apply(&buy(2), 7, 100.);
+        position.apply(&sell(1), 8, 120.);
+        assert_eq!(
+            status_line(9, 110., &position, "sell 1"),
+            "STATUS block=9 price=110.000000 position=long 1.000000 unrealized_pnl=10.000000 realized_pnl=20.000000 decision=sell 1"
+        );
+
+        position.apply(&sell(3), 10, 110.);
+        assert!(status_line(10, 110., &position, "none").contains(" position=short 2.000000 "));
+    }
+
+    /// Writes `contents` to a temporary file named `name` and loads it as the bot would.
+    fn load(name: &str, contents: &str) -> anyhow::Result<Config> {
+        let path = std::env::temp_dir().join(format!("{}_{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let loaded = load_settings(&path).and_then(|s| Ok(s.try_deserialize::<Config>()?));
+        std::fs::remove_file(path).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn yaml_toml_and_json_configs_load_the_same() {
+        let yaml = r#"
+rpc_url: http://localhost:8545
+base:
+  erc20:
+    symbol: USDC
+    address: "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+    decimals: 6
+quote:
+  erc20:
+    symbol: WETH
+    address: "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+    decimals: 18
+strategy:
+  threshold:
+    buy:
+      at: 2060
+      amount: 0.1
+    sell:
+      at: 2070
+      amount: 0.1
+"#;
+        let toml = r#"
+rpc_url = "http://localhost:8545"
+
+[base.erc20]
+symbol = "USDC"
+address = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"
+decimals = 6
+
+[quote.erc20]
+symbol = "WETH"
+address = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+decimals = 18
+
+[strategy.threshold.buy]
+at = 2060
+amount = 0.1
+
+[strategy.threshold.sell]
+at = 2070
+amount = 0.1
+"#;
+        let json = r#"{
+            "rpc_url": "http://localhost:8545",
+            "base": { "erc20": {
+                "symbol": "USDC",
+                "address": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                "decimals": 6
+            } },
+            "quote": { "erc20": {
+                "symbol": "WETH",
+                "address": "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+                "decimals": 18
+            } },
+            "strategy": { "threshold": {
+                "buy": { "at": 2060, "amount": 0.1 },
+                "sell": { "at": 2070, "amount": 0.1 }
+            } }
+        }"#;
+
+        let loaded = [
+            load("config.yaml", yaml),
+            load("config.toml", toml),
+            load("config.json", json),
+        ]
+        .map(|config| {
+            let mut config = config.unwrap();
+            let (base, quote, strategy_config, _) =
+                config.validate().unwrap().into_iter().next().unwrap();
+            (
+                config.rpc_url,
+                base.symbol().to_string(),
+                quote.symbol().to_string(),
+                strategy_config,
+            )
+        });
+        assert_eq!(loaded[0], loaded[1]);
+        assert_eq!(loaded[0], loaded[2]);
+    }
+
+    #[test]
+    fn unknown_fields_and_extensions_are_rejected() {
+        let yaml = "rpc_url: http://localhost:8545\nrpc_ulr: typo\n";
+        let toml = "rpc_url = \"http://localhost:8545\"\nrpc_ulr = \"typo\"\n";
+        let json = r#"{ "rpc_url": "http://localhost:8545", "rpc_ulr": "typo" }"#;
+        for (name, contents) in [
+            ("typo.yaml", yaml),
+            ("typo.toml", toml),
+            ("typo.json", json),
+        ] {
+            let e = load(name, contents).unwrap_err();
+            assert!(format!("{e:#}").contains("rpc_ulr"), "{name}: {e:#}");
+        }
+
+        let e = load("config.ini", "rpc_url = x").unwrap_err();
+        assert!(e.to_string().contains("Unknown config format"));
+    }
+
+    #[tokio::test]
+    async fn stale_tick_data_is_refetched_at_the_head() {
+        // Pools are stood in for by the block they were fetched at.
+        let refetch = |head| async move { anyhow::Ok(head) };
+        assert_eq!(
+            refresh_if_stale(10, 10, 12, 2, refetch).await.unwrap(),
+            (10, 10)
+        );
+        assert_eq!(
+            refresh_if_stale(10, 10, 13, 2, refetch).await.unwrap(),
+            (13, 13)
+        );
+
+        let failing = |_| async { anyhow::bail!("RPC down") };
+        assert!(refresh_if_stale(10, 10, 13, 2, failing).await.is_err());
+        assert!(refresh_if_stale(10, 10, 12, 2, failing).await.is_ok());
+    }
 }
@@ -0,0 +1,178 @@
+//! Prometheus metrics, served as text from a minimal HTTP endpoint for scraping.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Where to serve metrics, e.g. `0.0.0.0:9100`.
+    listen_addr: SocketAddr,
+}
+
+/// Metrics of every pair traded, labelled by pair.
+#[derive(Default)]
+pub struct Metrics {
+    pairs: Mutex<BTreeMap<String, Arc<PairMetrics>>>,
+}
+
+/// Updated from the trading loop, cheap enough to keep when nothing scrapes them.
+#[derive(Default)]
+pub struct PairMetrics {
+    blocks_processed: AtomicU64,
+    trades_attempted: AtomicU64,
+    trades_succeeded: AtomicU64,
+    trades_failed: AtomicU64,
+
+    /// f64 bits.
+    last_price: AtomicU64,
+    /// f64 bits.
+    net_position: AtomicU64,
+    last_block: AtomicU64,
+}
+
+impl Metrics {
+    pub fn pair(&self, pair: &str) -> Arc<PairMetrics> {
+        self.pairs
+            .lock()
+            .unwrap()
+            .entry(pair.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// The text exposition format.
+    fn render(&self) -> String {
+        let pairs = self.pairs.lock().unwrap();
+        let load = |a: &AtomicU64| a.load(Ordering::Relaxed);
+        let load_f64 = |a: &AtomicU64| f64::from_bits(a.load(Ordering::Relaxed));
+
+        let mut out = String::new();
+        let mut metric =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&PairMetrics) -> f64| {
+                let _ = writeln!(out, "# HELP lhava_{name} {help}");
+                let _ = writeln!(out, "# TYPE lhava_{name} {kind}");
+                for (pair, metrics) in pairs.iter() {
+                    let _ = writeln!(out, "lhava_{name}{{pair=\"{pair}\"}} {}", value(metrics));
+                }
+            };
+        metric(
+            "blocks_processed_total",
+            "counter",
+            "Blocks polled.",
+            &|m| load(&m.blocks_processed) as f64,
+        );
+        metric(
+            "trades_attempted_total",
+            "counter",
+            "Trades sent to execution.",
+            &|m| load(&m.trades_attempted) as f64,
+        );
+        metric(
+            "trades_succeeded_total",
+            "counter",
+            "Trades executed.",
+            &|m| load(&m.trades_succeeded) as f64,
+        );
+        metric(
+            "trades_failed_total",
+            "counter",
+            "Trades that failed to execute.",
+            &|m| load(&m.trades_failed) as f64,
+        );
+        metric(
+            "price",
+            "gauge",
+            "Last price fed to the strategy, base per quote.",
+            &|m| load_f64(&m.last_price),
+        );
+        metric(
+            "net_position",
+            "gauge",
+            "Net quote currency bought.",
+            &|m| load_f64(&m.net_position),
+        );
+        metric("last_block", "gauge", "Last block polled.", &|m| {
+            load(&m.last_block) as f64
+        });
+        out
+    }
+}
+
+impl PairMetrics {
+    pub fn block(&self, block: u64) {
+        self.blocks_processed.fetch_add(1, Ordering::Relaxed);
+        self.last_block.store(block, Ordering::Relaxed);
+    }
+
+    pub fn price(&self, price: f64) {
+        self.last_price.store(price.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn attempted(&self) {
+        self.trades_attempted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn executed(&self, succeeded: bool) {
+        let counter = match succeeded {
+            true => &self.trades_succeeded,
+            false => &self.trades_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn position(&self, net: f64) {
+        self.net_position.store(net.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Serves `metrics` at `config.listen_addr` from a background task, answering any request.
+pub async fn serve(config: &Config, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(config.listen_addr)
+        .await
+        .context(format!("Binding metrics to {}", config.listen_addr))?;
+    log::info!("Serving metrics on {}", config.listen_addr);
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept metrics connection: {e}");
+                    continue;
+                }
+            };
+            let body = metrics.render();
+            tokio::spawn(async move {
+                // Only the request's arrival matters, every path gets the metrics.
+                let mut request = [0; 1024];
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let result = async {
+                    let _ = stream.read(&mut request).await?;
+                    stream.write_all(response.as_bytes()).await?;
+                    stream.shutdown().await
+                };
+                if let Err(e) = result.await {
+                    log::warn!("Failed to serve metrics to {peer}: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
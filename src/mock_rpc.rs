@@ -0,0 +1,180 @@
+//! A JSON-RPC server answering from a closure, standing in for a node or bundler in tests.
+
+use std::sync::{Arc, Mutex};
+
+use alloy::primitives::{Address, B256};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Answers a request's method and params with its result, or an error message.
+pub trait Handler: FnMut(&str, &Value) -> Result<Value, String> + Send + 'static {}
+
+impl<F: FnMut(&str, &Value) -> Result<Value, String> + Send + 'static> Handler for F {}
+
+pub struct MockRpc {
+    pub url: String,
+
+    /// Every request received, in order.
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockRpc {
+    /// Serves `handler` on a local port until the test ends.
+    pub async fn serve(mut handler: impl Handler) -> MockRpc {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let Some(request) = read_request(&mut stream).await else {
+                    continue;
+                };
+                let method = request["method"].as_str().unwrap_or_default();
+                let body = match handler(method, &request["params"]) {
+                    Ok(result) => {
+                        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+                    }
+                    Err(message) => json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": { "code": -32000, "message": message },
+                    }),
+                }
+                .to_string();
+                recorded.lock().unwrap().push(request);
+
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        MockRpc { url, requests }
+    }
+
+    /// Params of each request for `method`, in order.
+    pub fn params(&self, method: &str) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r["method"] == method)
+            .map(|r| r["params"].clone())
+            .collect()
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Value> {
+    let mut buf = Vec::new();
+    loop {
+        let mut chunk = [0; 4096];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        let text = String::from_utf8_lossy(&buf);
+        let Some(end) = text.find("\r\n\r\n") else {
+            continue;
+        };
+        let length = text[..end]
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .map_or(0, |(_, value)| value.trim().parse().unwrap());
+        let body = end + 4..end + 4 + length;
+        if buf.len() >= body.end {
+            return serde_json::from_slice(&buf[body]).ok();
+        }
+    }
+}
+
+/// A successful EIP-1559 transaction receipt, as a node returns it.
+pub fn receipt(hash: B256, logs: Vec<Value>) -> Value {
+    json!({
+        "type": "0x2",
+        "status": "0x1",
+        "cumulativeGasUsed": "0x30d40",
+        "logs": logs,
+        "logsBloom": format!("0x{}", "00".repeat(256)),
+        "transactionHash": hash,
+        "transactionIndex": "0x0",
+        "blockHash": B256::repeat_byte(0xcc),
+        "blockNumber": "0x2",
+        "gasUsed": "0x30d40",
+        "effectiveGasPrice": "0x3b9aca00",
+        "from": Address::repeat_byte(0xdd),
+        "to": Address::repeat_byte(0xee),
+        "contractAddress": null,
+    })
+}
+
+/// A pending EIP-1559 transaction from `from` at `nonce`, as a node returns it, paying 1 gwei with
+/// a 0.1 gwei tip.
+pub fn pending_transaction(hash: B256, from: Address, nonce: u64) -> Value {
+    json!({
+        "type": "0x2",
+        "chainId": "0x1",
+        "nonce": format!("{nonce:#x}"),
+        "gas": "0x30d40",
+        "maxFeePerGas": "0x3b9aca00",
+        "maxPriorityFeePerGas": "0x5f5e100",
+        "to": Address::repeat_byte(0xee),
+        "value": "0x0",
+        "input": "0x",
+        "accessList": [],
+        "r": "0x1",
+        "s": "0x1",
+        "v": "0x0",
+        "yParity": "0x0",
+        "hash": hash,
+        "from": from,
+        "blockHash": null,
+        "blockNumber": null,
+        "transactionIndex": null,
+    })
+}
+
+/// A log emitted by `address`.
+pub fn log(address: Address, topics: &[B256], data: &[u8]) -> Value {
+    json!({
+        "address": address,
+        "topics": topics,
+        "data": alloy::primitives::Bytes::copy_from_slice(data),
+    })
+}
+
+/// Block `number` without transactions, as a node returns it, hashed by its number.
+pub fn block(number: u64) -> Value {
+    let hash = |n: u64| B256::left_padding_from(&n.to_be_bytes());
+    json!({
+        "hash": hash(number),
+        "parentHash": hash(number.saturating_sub(1)),
+        "sha3Uncles": B256::ZERO,
+        "miner": Address::ZERO,
+        "stateRoot": B256::ZERO,
+        "transactionsRoot": B256::ZERO,
+        "receiptsRoot": B256::ZERO,
+        "logsBloom": format!("0x{}", "00".repeat(256)),
+        "difficulty": "0x0",
+        "number": format!("{number:#x}"),
+        "gasLimit": "0x1c9c380",
+        "gasUsed": "0x0",
+        "timestamp": format!("{:#x}", number * 12),
+        "extraData": "0x",
+        "mixHash": B256::ZERO,
+        "nonce": "0x0000000000000000",
+        "baseFeePerGas": "0x3b9aca00",
+        "uncles": [],
+        "transactions": [],
+    })
+}
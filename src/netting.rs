@@ -0,0 +1,171 @@
+use num_traits::{Signed, Zero};
+use uniswap_sdk_core::prelude::Fraction;
+
+use crate::strategy::Trade;
+
+/// Accumulates trades over a window of blocks, executing only their net as a single trade when the
+/// window closes. Limit prices of the netted trades are dropped.
+pub struct Netting {
+    window_blocks: u64,
+
+    /// Block the open window started at, with the trades queued in it so far.
+    open: Option<(u64, Vec<Trade>)>,
+}
+
+impl Netting {
+    pub fn new(window_blocks: u64) -> Self {
+        Netting {
+            window_blocks,
+            open: None,
+        }
+    }
+
+    /// Removes queued trades emitted under any of `intent_ids`, so they don't count towards the net.
+    pub fn cancel(&mut self, intent_ids: &[u64]) {
+        let Some((start, queued)) = &mut self.open else {
+            return;
+        };
+        queued.retain(|trade| {
+            let cancelled = trade
+                .options()
+                .intent_id
+                .is_some_and(|id| intent_ids.contains(&id));
+            if cancelled {
+                log::info!("Cancelled {trade:?} queued in window from block {start}");
+            }
+            !cancelled
+        });
+    }
+
+    /// Adds `trades` to the window, returning the net trade if the window closes at `block`.
+    pub fn net(&mut self, block: u64, trades: Vec<Trade>) -> Option<Trade> {
+        for trade in trades {
+            let (start, queued) = self.open.get_or_insert((block, Vec::new()));
+            log::info!(
+                "Netting {trade:?} into window from block {start}, {} trades so far",
+                queued.len() + 1
+            );
+            queued.push(trade);
+        }
+
+        let (start, _) = self.open.as_ref()?;
+        if block + 1 < start + self.window_blocks {
+            return None;
+        }
+
+        let (start, queued) = self.open.take()?;
+        let net = queued.iter().fold(Fraction::new(0, 1), |net, trade| {
+            let (amount, sign) = match trade {
+                Trade::Buy { amount, .. } => (amount, 1),
+                Trade::Sell { amount, .. } => (amount, -1),
+            };
+            Fraction::new(
+                net.numerator.clone() * &amount.denominator
+                    + amount.numerator.clone() * &net.denominator * sign,
+                net.denominator.clone() * &amount.denominator,
+            )
+        });
+        let amount = Fraction::new(net.numerator.abs(), net.denominator.clone());
+        log::info!(
+            "Window from block {start} closed with {} trades netting to {}",
+            queued.len(),
+            amount.to_significant(6, None).unwrap_or_default()
+        );
+        if net.numerator.is_zero() {
+            None
+        } else if net.numerator.is_positive() {
+            Some(Trade::Buy {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            })
+        } else {
+            Some(Trade::Sell {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        quote::Direction::{Buy, Sell},
+        strategy::{
+            testing::{buy, context, quoter, sell, signals},
+            Strategy, TradeContext, TradeOptions,
+        },
+    };
+
+    #[test]
+    fn opposing_trades_in_a_window_net_to_one_smaller_trade() {
+        let mut netting = Netting::new(3);
+        let mut net =
+            |block, trades| signals(&netting.net(block, trades).into_iter().collect::<Vec<_>>());
+
+        assert_eq!(net(1, vec![buy(5)]), []);
+        assert_eq!(net(2, vec![sell(2), sell(1)]), []);
+        assert_eq!(net(3, vec![buy(1)]), [(Buy, 3.)]);
+
+        // The next window opens with its first trade, and can net to a sell.
+        assert_eq!(net(4, vec![]), []);
+        assert_eq!(net(5, vec![buy(1)]), []);
+        assert_eq!(net(6, vec![sell(4)]), []);
+        assert_eq!(net(7, vec![]), [(Sell, 3.)]);
+
+        // Cancelling out entirely trades nothing.
+        assert_eq!(net(8, vec![buy(2)]), []);
+        assert_eq!(net(10, vec![sell(2)]), []);
+    }
+
+    /// Places a buy of 2 once, cancelling it if the price moves away above 1.
+    #[derive(Default)]
+    struct LimitOrder {
+        placed: bool,
+        cancelled: Vec<u64>,
+    }
+
+    impl Strategy for LimitOrder {
+        fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+            if !self.placed {
+                self.placed = true;
+                return vec![Trade::Buy {
+                    amount: Fraction::new(2, 1),
+                    limit_price: Some(1.),
+                    options: TradeOptions {
+                        intent_id: Some(7),
+                        ..Default::default()
+                    },
+                }];
+            }
+            if ctx.price_lossy > 1. {
+                self.cancelled.push(7);
+            }
+            vec![buy(1)]
+        }
+
+        fn cancellations(&mut self) -> Vec<u64> {
+            std::mem::take(&mut self.cancelled)
+        }
+    }
+
+    #[test]
+    fn strategy_cancels_a_queued_intent_before_it_executes() {
+        let quoter = quoter();
+        let mut strategy = LimitOrder::default();
+        let mut netting = Netting::new(3);
+        let mut step = |block, price| {
+            let trades = strategy.trade(&context(&quoter, block, price));
+            netting.cancel(&strategy.cancellations());
+            signals(&netting.net(block, trades).into_iter().collect::<Vec<_>>())
+        };
+
+        assert_eq!(step(1, 1.), []);
+        assert_eq!(step(2, 1.5), []);
+        // Only the two uncancelled buys of 1 execute.
+        assert_eq!(step(3, 1.5), [(Buy, 2.)]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// How many recent blocks' prices the median is taken over.
+    window: usize,
+
+    /// Prices more than this many median absolute deviations from the median are rejected.
+    k: f64,
+}
+
+impl Config {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.window > 0, "outlier_filter.window must be above 0");
+        anyhow::ensure!(self.k > 0., "outlier_filter.k must be above 0");
+        Ok(())
+    }
+}
+
+/// Rejects prices far from the rolling median, e.g. from a flash event or a manipulated pool.
+pub struct OutlierFilter {
+    config: Config,
+
+    /// Every recently observed price, rejected or not, so a sustained move is accepted eventually.
+    recent: VecDeque<f64>,
+}
+
+impl OutlierFilter {
+    pub fn new(config: Config) -> Self {
+        OutlierFilter {
+            recent: VecDeque::with_capacity(config.window),
+            config,
+        }
+    }
+
+    /// Records `price`, returning how many MADs it is from the median if that's an outlier.
+    pub fn check(&mut self, price: f64) -> Option<f64> {
+        let deviations = self.deviations(price);
+
+        if self.recent.len() == self.config.window {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(price);
+
+        deviations.filter(|d| *d > self.config.k)
+    }
+
+    fn deviations(&self, price: f64) -> Option<f64> {
+        // Not enough history to judge yet.
+        if self.recent.len() < self.config.window {
+            return None;
+        }
+
+        let median = median(self.recent.iter().copied().collect())?;
+        let mad = median(self.recent.iter().map(|p| (p - median).abs()).collect())?;
+        if mad == 0. {
+            return None;
+        }
+        Some((price - median).abs() / mad)
+    }
+}
+
+/// None for no values.
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.
+    } else {
+        values[mid]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(window: usize, k: f64) -> OutlierFilter {
+        let config = Config { window, k };
+        config.validate().unwrap();
+        OutlierFilter::new(config)
+    }
+
+    #[test]
+    fn a_spike_is_rejected() {
+        let mut filter = filter(5, 3.);
+        for price in [100., 101., 99., 100., 102.] {
+            assert_eq!(filter.check(price), None);
+        }
+
+        // Median 100, MAD 1.
+        assert_eq!(filter.check(150.), Some(50.));
+        assert_eq!(filter.check(101.), None);
+    }
+
+    #[test]
+    fn a_sustained_move_is_accepted_eventually() {
+        let mut filter = filter(3, 3.);
+        for price in [100., 101., 99.] {
+            filter.check(price);
+        }
+        assert!(filter.check(150.).is_some());
+        assert!(filter.check(150.).is_some());
+        // 150 is now the median.
+        assert_eq!(filter.check(150.), None);
+    }
+
+    #[test]
+    fn an_empty_window_is_invalid() {
+        assert!(Config { window: 0, k: 3. }.validate().is_err());
+        assert_eq!(median(Vec::new()), None);
+    }
+}
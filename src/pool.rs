@@ -0,0 +1,468 @@
+use alloy::{
+    eips::BlockId,
+    primitives::aliases::{I24, U160},
+    providers::Provider,
+    rpc::types::Filter,
+    sol_types::SolEvent,
+};
+use anyhow::Context;
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::{
+    execution::{from_human_amount, PrecisionLoss, UniswapV3Pool},
+    strategy::PoolSwap,
+};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeTier {
+    /// 0.01%
+    Lowest,
+    /// 0.05%
+    #[default]
+    Low,
+    /// 0.3%
+    Medium,
+    /// 1%
+    High,
+}
+
+impl From<FeeTier> for FeeAmount {
+    fn from(tier: FeeTier) -> Self {
+        match tier {
+            FeeTier::Lowest => FeeAmount::LOWEST,
+            FeeTier::Low => FeeAmount::LOW,
+            FeeTier::Medium => FeeAmount::MEDIUM,
+            FeeTier::High => FeeAmount::HIGH,
+        }
+    }
+}
+
+/// The canonical Uniswap V3 pool for the pair and fee, with tick data as of `block_id`.
+pub async fn fetch<P: Provider + Clone>(
+    chain_id: u64,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &P,
+    block_id: BlockId,
+) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+    fetch_from(
+        chain_id,
+        FACTORY_ADDRESS,
+        base,
+        quote,
+        fee,
+        provider,
+        block_id,
+    )
+    .await
+}
+
+alloy::sol! {
+    #[sol(rpc)]
+    interface PoolState {
+        function token0() returns (address);
+        function token1() returns (address);
+        function fee() returns (uint24);
+        function liquidity() returns (uint128);
+        function observe(uint32[] secondsAgos) returns (
+            int56[] tickCumulatives,
+            uint160[] secondsPerLiquidityCumulativeX128s
+        );
+        function slot0() returns (
+            uint160 sqrtPriceX96,
+            int24 tick,
+            uint16 observationIndex,
+            uint16 observationCardinality,
+            uint16 observationCardinalityNext,
+            uint8 feeProtocol,
+            bool unlocked
+        );
+    }
+}
+
+/// `fetch`, or `fetch_at` when the pool's address is configured.
+pub async fn fetch_traded<P: Provider + Clone>(
+    address: Option<Address>,
+    chain_id: u64,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &P,
+    block_id: BlockId,
+) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+    match address {
+        Some(address) => fetch_at(address, base, quote, fee, provider, block_id).await,
+        None => fetch(chain_id, base, quote, fee, provider, block_id).await,
+    }
+}
+
+/// The pool deployed at `address`, bypassing the factory, for pools not created by the canonical
+/// one. Its tokens and fee are assumed to have been checked with `check_at`.
+pub async fn fetch_at<P: Provider + Clone>(
+    address: Address,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &P,
+    block_id: BlockId,
+) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+    let state = PoolState::new(address, provider);
+    let slot0 = state.slot0().block(block_id);
+    let liquidity = state.liquidity().block(block_id);
+    let (slot0, liquidity) = futures::try_join!(slot0.call(), liquidity.call())?;
+
+    let ticks =
+        EphemeralTickMapDataProvider::new(address, provider.clone(), None, None, Some(block_id))
+            .await?;
+    Ok(Pool::new_with_tick_data_provider(
+        base.wrapped().clone(),
+        quote.wrapped().clone(),
+        fee,
+        slot0.sqrtPriceX96,
+        liquidity._0,
+        ticks,
+    )?)
+}
+
+/// Fails unless the pool at `address` is for the pair with `fee`.
+pub async fn check_at(
+    address: Address,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &impl Provider,
+) -> anyhow::Result<()> {
+    let state = PoolState::new(address, provider);
+    let (token0, token1, actual_fee) = (state.token0(), state.token1(), state.fee());
+    let (token0, token1, actual_fee) =
+        futures::try_join!(token0.call(), token1.call(), actual_fee.call())
+            .context(format!("Reading pool {address}"))?;
+
+    let mut expected = [base.wrapped().address(), quote.wrapped().address()];
+    expected.sort();
+    anyhow::ensure!(
+        [token0._0, token1._0] == expected,
+        "Pool {address} is for {}/{}, not {}/{}",
+        token0._0,
+        token1._0,
+        base.symbol().map_or("???", |v| v),
+        quote.symbol().map_or("???", |v| v),
+    );
+    anyhow::ensure!(
+        actual_fee._0.to::<u32>() == fee as u32,
+        "Pool {address} has fee {}, not {fee:?}",
+        actual_fee._0
+    );
+    Ok(())
+}
+
+/// The pool at `address`'s arithmetic mean tick over the `window_secs` before `block_id`.
+pub async fn twap_tick(
+    address: Address,
+    window_secs: u32,
+    provider: &impl Provider,
+    block_id: BlockId,
+) -> anyhow::Result<i32> {
+    anyhow::ensure!(window_secs > 0, "TWAP window must be above 0 seconds");
+    let state = PoolState::new(address, provider);
+    let observed = state
+        .observe(vec![window_secs, 0])
+        .block(block_id)
+        .call()
+        .await
+        .context("Observing, the pool's observation cardinality may be too small for the window")?;
+
+    let [start, end] = observed.tickCumulatives.as_slice() else {
+        anyhow::bail!("Expected 2 tick cumulatives from observe");
+    };
+    let delta = end.as_i64() - start.as_i64();
+    // Rounded towards negative infinity, like Uniswap's OracleLibrary.
+    Ok(i32::try_from(delta.div_euclid(window_secs as i64))?)
+}
+
+/// Fails if Uniswap has no pool for the pair with `fee`.
+pub async fn ensure_exists(
+    chain_id: u64,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &impl Provider,
+) -> anyhow::Result<()> {
+    let address = compute_pool_address(
+        FACTORY_ADDRESS,
+        base.address(),
+        quote.address(),
+        fee,
+        None,
+        Some(chain_id),
+    );
+    let code = provider.get_code_at(address).await?;
+    anyhow::ensure!(
+        !code.is_empty(),
+        "No {fee:?} fee tier pool for {}/{} (expected at {address})",
+        base.symbol().map_or("???", |v| v),
+        quote.symbol().map_or("???", |v| v),
+    );
+    Ok(())
+}
+
+/// Of `fees`, the tiers whose canonical pool has at least `min_liquidity` in range at `block_id`,
+/// logging why each other tier was rejected.
+pub async fn deep_enough(
+    chain_id: u64,
+    base: &Currency,
+    quote: &Currency,
+    fees: &[FeeAmount],
+    min_liquidity: u128,
+    provider: &impl Provider,
+    block_id: BlockId,
+) -> Vec<FeeAmount> {
+    let mut deep = Vec::with_capacity(fees.len());
+    for &fee in fees {
+        let address = compute_pool_address(
+            FACTORY_ADDRESS,
+            base.wrapped().address(),
+            quote.wrapped().address(),
+            fee,
+            None,
+            Some(chain_id),
+        );
+        let liquidity = PoolState::new(address, provider)
+            .liquidity()
+            .block(block_id)
+            .call()
+            .await;
+        match liquidity {
+            Ok(liquidity) if liquidity._0 >= min_liquidity => deep.push(fee),
+            Ok(liquidity) => log::warn!(
+                "Rejecting the {fee:?} tier, its liquidity {} is below {min_liquidity}",
+                liquidity._0
+            ),
+            Err(e) => log::warn!("Rejecting the {fee:?} tier, failed to read its liquidity: {e}"),
+        }
+    }
+    deep
+}
+
+/// Like `fetch`, for the pool created by `factory` instead of Uniswap's.
+pub async fn fetch_from<P: Provider + Clone>(
+    chain_id: u64,
+    factory: Address,
+    base: &Currency,
+    quote: &Currency,
+    fee: FeeAmount,
+    provider: &P,
+    block_id: BlockId,
+) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+    Ok(
+        Pool::<EphemeralTickMapDataProvider>::from_pool_key_with_tick_data_provider(
+            chain_id,
+            factory,
+            base.address(),
+            quote.address(),
+            fee,
+            provider.clone(),
+            Some(block_id),
+        )
+        .await?,
+    )
+}
+
+/// Bounds of the ticks usable by any pool.
+const MIN_TICK: i32 = -887272;
+const MAX_TICK: i32 = 887272;
+
+/// The usable tick nearest to a `token1_price` style price (token0 per token1, in human units).
+pub fn price_to_tick(pool: &Pool<EphemeralTickMapDataProvider>, price: f64) -> anyhow::Result<i32> {
+    let decimals = pool.token0.decimals() as i32 - pool.token1.decimals() as i32;
+    nearest_tick(price, decimals, i32::try_from(pool.tick_spacing())?)
+}
+
+/// `price_to_tick` for a pool whose token0 has `decimals` more decimals than its token1, and
+/// whose ticks are `spacing` apart.
+fn nearest_tick(price: f64, decimals: i32, spacing: i32) -> anyhow::Result<i32> {
+    // Ticks measure the raw token1 per token0 price, the inverse of `token1_price`.
+    let raw = 1. / (price * 10f64.powi(decimals));
+    let tick = (raw.ln() / 1.0001f64.ln()).round();
+    anyhow::ensure!(tick.is_finite(), "Price {price} has no corresponding tick");
+
+    let tick = (tick as i32).clamp(MIN_TICK, MAX_TICK);
+    let aligned = (tick as f64 / spacing as f64).round() as i32 * spacing;
+    Ok(if aligned > MAX_TICK {
+        aligned - spacing
+    } else if aligned < MIN_TICK {
+        aligned + spacing
+    } else {
+        aligned
+    })
+}
+
+/// Inverse of `price_to_tick`, the `token1_price` style price at `tick`.
+pub fn tick_to_price(pool: &Pool<EphemeralTickMapDataProvider>, tick: i32) -> f64 {
+    let decimals = pool.token0.decimals() as i32 - pool.token1.decimals() as i32;
+    1. / (1.0001f64.powi(tick) * 10f64.powi(decimals))
+}
+
+/// The `sqrtPriceLimitX96` for swaps that shouldn't go beyond `price`, in base per `quote`.
+pub fn sqrt_price_limit(
+    pool: &Pool<EphemeralTickMapDataProvider>,
+    quote: &Currency,
+    price: f64,
+) -> anyhow::Result<U160> {
+    // Ticks are oriented by token order, not by which token is the base.
+    let price = if quote.wrapped().address() == pool.token1.address() {
+        price
+    } else {
+        1. / price
+    };
+    let tick = price_to_tick(pool, price)?;
+    log::info!(
+        "Limit price {price} aligned to tick {tick}, price {}",
+        tick_to_price(pool, tick)
+    );
+    Ok(get_sqrt_ratio_at_tick(I24::try_from(tick)?)?)
+}
+
+/// What swapping `amount` (human units) of `input` through `pool` pays out, from its tick data.
+pub fn simulate_exact_input(
+    pool: &Pool<EphemeralTickMapDataProvider>,
+    input: &Currency,
+    amount: Fraction,
+) -> anyhow::Result<CurrencyAmount<Currency>> {
+    let output = if input.wrapped().address() == pool.token0.address() {
+        pool.token1.clone()
+    } else {
+        pool.token0.clone()
+    };
+
+    let amount = from_human_amount(amount, input, PrecisionLoss::Warn)?;
+    let route = Route::new(vec![pool.clone()], input.clone(), Currency::Token(output));
+    let swap = Trade::from_route(route, amount, TradeType::ExactInput)?;
+    Ok(swap.output_amount()?)
+}
+
+/// Swaps in `pool` during `block`, from its Swap events.
+pub async fn swaps(
+    provider: &impl Provider,
+    pool: &Pool<EphemeralTickMapDataProvider>,
+    quote: &Currency,
+    block: u64,
+) -> anyhow::Result<Vec<PoolSwap>> {
+    let filter = Filter::new()
+        .address(pool.address(None, None))
+        .event_signature(UniswapV3Pool::Swap::SIGNATURE_HASH)
+        .from_block(block)
+        .to_block(block);
+
+    let quote_is_token0 = pool.token0.address() == quote.wrapped().address();
+    let scale = BigInt::from(10).pow(quote.decimals() as u32);
+
+    provider
+        .get_logs(&filter)
+        .await?
+        .iter()
+        .map(|log| {
+            let swap = log.log_decode::<UniswapV3Pool::Swap>()?.inner.data;
+            let paid_in = if quote_is_token0 {
+                swap.amount0
+            } else {
+                swap.amount1
+            };
+            Ok(PoolSwap {
+                sender: swap.sender,
+                recipient: swap.recipient,
+                // Positive amounts are paid into the pool, so the swapper bought the negation.
+                quote_bought: Fraction::new(
+                    (-paid_in).to_string().parse::<BigInt>()?,
+                    scale.clone(),
+                ),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{Bytes, U256},
+        providers::ProviderBuilder,
+        sol_types::SolValue,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        mock_rpc::MockRpc,
+        strategy::testing::{usdc, weth},
+    };
+
+    #[test]
+    fn prices_round_to_the_nearest_usable_tick() {
+        // USDC per WETH in a USDC/WETH pool, token0 has 12 fewer decimals.
+        assert_eq!(nearest_tick(2000., -12, 1).unwrap(), 200311);
+        assert_eq!(nearest_tick(2000., -12, 10).unwrap(), 200310);
+        assert_eq!(nearest_tick(2000., -12, 60).unwrap(), 200340);
+        assert_eq!(nearest_tick(3000., -12, 60).unwrap(), 196260);
+
+        assert_eq!(nearest_tick(1., 0, 60).unwrap(), 0);
+        assert_eq!(nearest_tick(0.5, 0, 200).unwrap(), 7000);
+    }
+
+    #[test]
+    fn extreme_prices_stay_within_the_usable_ticks() {
+        assert_eq!(nearest_tick(1e-60, 0, 60).unwrap(), 887220);
+        assert_eq!(nearest_tick(1e60, 0, 60).unwrap(), -887220);
+        assert!(nearest_tick(0., 0, 60).is_err());
+    }
+
+    /// Tiers of WETH/USDC deep enough for `min_liquidity`, when the 0.05% pool has 100 liquidity
+    /// and the 0.3% pool 1000.
+    async fn deep_enough_for(min_liquidity: u128) -> Vec<FeeAmount> {
+        let medium = compute_pool_address(
+            FACTORY_ADDRESS,
+            weth().wrapped().address(),
+            usdc().wrapped().address(),
+            FeeAmount::MEDIUM,
+            None,
+            Some(1),
+        );
+        let rpc = MockRpc::serve(move |method, params| match method {
+            "eth_call" => {
+                let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+                let liquidity = if to == medium { 1000 } else { 100 };
+                Ok(json!(Bytes::from(U256::from(liquidity).abi_encode())))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let fees = [FeeAmount::LOW, FeeAmount::MEDIUM];
+        deep_enough(
+            1,
+            &usdc(),
+            &weth(),
+            &fees,
+            min_liquidity,
+            &provider,
+            BlockId::latest(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn tiers_below_the_liquidity_floor_are_rejected() {
+        assert_eq!(deep_enough_for(500).await, [FeeAmount::MEDIUM]);
+        assert_eq!(
+            deep_enough_for(100).await,
+            [FeeAmount::LOW, FeeAmount::MEDIUM]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_tier_qualifies_when_all_are_below_the_floor() {
+        assert_eq!(deep_enough_for(5000).await, []);
+    }
+}
@@ -0,0 +1,284 @@
+use num_traits::{Signed, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use uniswap_sdk_core::prelude::{BigInt, Fraction};
+
+use crate::strategy::Trade;
+
+/// Net amount of the quote currency acquired by executed trades.
+pub struct Position {
+    net: Fraction,
+
+    /// Block the current long position was opened at.
+    entry_block: Option<u64>,
+
+    /// Average price the current position was built at, lossy.
+    average_price: f64,
+
+    /// Profit, in the base currency, locked in by reducing positions, net of gas.
+    realized_pnl: f64,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            net: Fraction::new(0, 1),
+            entry_block: None,
+            average_price: 0.,
+            realized_pnl: 0.,
+        }
+    }
+}
+
+impl Position {
+    /// Records an executed `trade`, filled at `price`.
+    pub fn apply(&mut self, trade: &Trade, block: u64, price: f64) {
+        let was_long = self.net.numerator.is_positive();
+        let before = self.net_lossy();
+
+        let (amount, sign) = match trade {
+            Trade::Buy { amount, .. } => (amount, 1),
+            Trade::Sell { amount, .. } => (amount, -1),
+        };
+
+        self.net = Fraction::new(
+            self.net.numerator.clone() * &amount.denominator
+                + amount.numerator.clone() * &self.net.denominator * sign,
+            self.net.denominator.clone() * &amount.denominator,
+        );
+
+        self.entry_block = match (was_long, self.net.numerator.is_positive()) {
+            (_, false) => None,
+            (false, true) => Some(block),
+            (true, true) => self.entry_block,
+        };
+
+        let after = self.net_lossy();
+        if before != 0. && (after.abs() < before.abs() || before.signum() != after.signum()) {
+            let closed = (after - before).abs().min(before.abs());
+            self.realized_pnl += closed * (price - self.average_price) * before.signum();
+        }
+
+        self.average_price = if after == 0. {
+            0.
+        } else if before == 0. || before.signum() != after.signum() {
+            price
+        } else if after.abs() > before.abs() {
+            (before.abs() * self.average_price + (after - before).abs() * price) / after.abs()
+        } else {
+            self.average_price
+        };
+    }
+
+    pub fn net_lossy(&self) -> f64 {
+        self.net.numerator.to_f64().unwrap_or(f64::NAN)
+            / self.net.denominator.to_f64().unwrap_or(f64::NAN)
+    }
+
+    /// Profit, in the base currency, from closing the position at `price`.
+    pub fn unrealized_pnl(&self, price: f64) -> f64 {
+        self.net_lossy() * (price - self.average_price)
+    }
+
+    /// Deducts the cost of gas, valued in the base currency, from realized PnL.
+    pub fn charge_gas(&mut self, cost: f64) {
+        self.realized_pnl -= cost;
+    }
+
+    pub fn realized_pnl(&self) -> f64 {
+        self.realized_pnl
+    }
+
+    /// How many blocks the current long position has been held as of `block`.
+    pub fn held_for(&self, block: u64) -> Option<u64> {
+        self.entry_block.map(|entry| block.saturating_sub(entry))
+    }
+
+    /// How long the position has been held, if `trade` is a sell of it within `min_blocks` of
+    /// opening it as of `block`.
+    pub fn within_holding_period(&self, trade: &Trade, block: u64, min_blocks: u64) -> Option<u64> {
+        match (trade, self.held_for(block)) {
+            (Trade::Sell { .. }, Some(held)) if held < min_blocks => Some(held),
+            _ => None,
+        }
+    }
+
+    /// How far `trade` can move the position before passing `max` for a buy or `min` for a sell,
+    /// None when that side is unlimited.
+    pub fn room(
+        &self,
+        trade: &Trade,
+        min: Option<&Fraction>,
+        max: Option<&Fraction>,
+    ) -> Option<Fraction> {
+        let (from, to) = match trade {
+            Trade::Buy { .. } => (&self.net, max?),
+            Trade::Sell { .. } => (min?, &self.net),
+        };
+        Some(Fraction::new(
+            to.numerator.clone() * &from.denominator - from.numerator.clone() * &to.denominator,
+            to.denominator.clone() * &from.denominator,
+        ))
+    }
+
+    /// The trade that would bring the position back to zero.
+    pub fn flatten(&self) -> Option<Trade> {
+        let amount = Fraction::new(self.net.numerator.abs(), self.net.denominator.clone());
+        if self.net.numerator.is_positive() {
+            Some(Trade::Sell {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            })
+        } else if self.net.numerator.is_negative() {
+            Some(Trade::Buy {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// State to persist across restarts.
+    pub fn save(&self) -> serde_json::Value {
+        serde_json::json!(Saved {
+            net: (
+                self.net.numerator.to_string(),
+                self.net.denominator.to_string()
+            ),
+            entry_block: self.entry_block,
+            average_price: self.average_price,
+            realized_pnl: self.realized_pnl,
+        })
+    }
+
+    /// Restores state from `save`.
+    pub fn load(state: serde_json::Value) -> anyhow::Result<Position> {
+        let saved: Saved = serde_json::from_value(state)?;
+        let (numerator, denominator) = saved.net;
+        Ok(Position {
+            net: Fraction::new(numerator.parse::<BigInt>()?, denominator.parse::<BigInt>()?),
+            entry_block: saved.entry_block,
+            average_price: saved.average_price,
+            realized_pnl: saved.realized_pnl,
+        })
+    }
+}
+
+/// Saved `Position`, with the exact net amount as its numerator and denominator.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    net: (String, String),
+    entry_block: Option<u64>,
+    average_price: f64,
+    realized_pnl: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+    use uniswap_sdk_core::{prelude::Currency, token};
+
+    use super::*;
+    use crate::{
+        execution::{gas_in_base, wrapped_native},
+        strategy::testing::{buy, sell, usdc, weth},
+    };
+
+    #[test]
+    fn sells_within_the_holding_period_are_suppressed() {
+        let mut position = Position::default();
+        position.apply(&buy(2), 10, 100.);
+
+        assert_eq!(position.within_holding_period(&sell(1), 12, 5), Some(2));
+        assert_eq!(position.within_holding_period(&sell(1), 14, 5), Some(4));
+        assert_eq!(position.within_holding_period(&sell(1), 15, 5), None);
+        // Only sells wait, and only while long.
+        assert_eq!(position.within_holding_period(&buy(1), 12, 5), None);
+        assert_eq!(
+            Position::default().within_holding_period(&sell(1), 12, 5),
+            None
+        );
+    }
+
+    #[test]
+    fn adding_to_a_position_keeps_its_entry_block() {
+        let mut position = Position::default();
+        position.apply(&buy(2), 10, 100.);
+        position.apply(&buy(2), 13, 100.);
+        assert_eq!(position.held_for(15), Some(5));
+
+        position.apply(&sell(4), 16, 100.);
+        assert_eq!(position.held_for(17), None);
+    }
+
+    #[test]
+    fn realized_pnl_is_net_of_gas() {
+        let mut position = Position::default();
+        position.apply(&buy(1), 10, 2000.);
+        position.apply(&sell(1), 11, 2100.);
+        assert_eq!(position.realized_pnl(), 100.);
+
+        // 0.001 ETH of gas on each trade, valued at the quote currency WETH's price in USDC.
+        let gas = gas_in_base(
+            1_000_000_000_000_000,
+            wrapped_native(1, None),
+            &usdc(),
+            &weth(),
+            2000.,
+            None,
+        )
+        .unwrap();
+        assert_eq!(gas, 2.);
+        position.charge_gas(gas);
+        position.charge_gas(gas);
+        assert_eq!(position.realized_pnl(), 96.);
+    }
+
+    #[test]
+    fn gas_is_valued_whichever_currency_it_is() {
+        let wei = 1_000_000_000_000_000;
+        let weth_address = wrapped_native(1, None);
+        // The base currency is the gas token.
+        assert_eq!(
+            gas_in_base(wei, weth_address, &weth(), &usdc(), 0.0005, None),
+            Some(0.001)
+        );
+        // Neither is, so it needs a price for the gas token.
+        assert_eq!(
+            gas_in_base(wei, weth_address, &usdc(), &usdc(), 1., None),
+            None
+        );
+        assert_eq!(
+            gas_in_base(wei, weth_address, &usdc(), &usdc(), 1., Some(3000.)),
+            Some(3.)
+        );
+
+        // With a configured wrapped native token, it's the gas token instead of the SDK's.
+        let custom = Address::repeat_byte(0x42);
+        let custom_weth = Currency::Token(token!(1, custom, 18, "WETH"));
+        assert_eq!(
+            gas_in_base(wei, Some(custom), &custom_weth, &usdc(), 0.0005, None),
+            Some(0.001)
+        );
+        assert_eq!(
+            gas_in_base(wei, Some(custom), &weth(), &usdc(), 0.0005, None),
+            None
+        );
+    }
+
+    #[test]
+    fn survives_a_restart() {
+        let mut before = Position::default();
+        before.apply(&buy(3), 10, 100.);
+        before.apply(&sell(1), 12, 110.);
+        before.charge_gas(0.5);
+
+        let after = Position::load(before.save()).unwrap();
+        assert_eq!(after.net_lossy(), 2.);
+        assert_eq!(after.held_for(15), Some(5));
+        assert_eq!(after.realized_pnl(), 9.5);
+        assert_eq!(after.unrealized_pnl(120.), 40.);
+    }
+}
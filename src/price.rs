@@ -0,0 +1,90 @@
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::pool::{self, FeeTier};
+
+#[derive(serde::Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    /// The price of the pool being traded.
+    #[default]
+    Single,
+
+    /// Average of the price across several fee tiers' pools, weighted by each pool's in-range
+    /// liquidity. A thin pool being pushed around has little influence on the result.
+    Aggregate { fee_tiers: Vec<FeeTier> },
+
+    /// The traded pool's time weighted average price over the last `window_secs`, from its oracle.
+    /// Harder to push around within a block than the spot price.
+    Twap { window_secs: u32 },
+}
+
+pub fn lossy(price: &Price<Token, Token>) -> anyhow::Result<f64> {
+    Ok(price.to_significant(8, None)?.parse()?)
+}
+
+/// Base units per `quote` unit in `pool`, whichever of the pair sorts first by address.
+pub fn of_quote(
+    pool: &Pool<EphemeralTickMapDataProvider>,
+    quote: &Currency,
+) -> anyhow::Result<f64> {
+    lossy(&pool.price_of(quote.wrapped())?)
+}
+
+/// Base units per `quote` unit at `tick` of `pool`.
+pub fn at_tick(pool: &Pool<EphemeralTickMapDataProvider>, quote: &Currency, tick: i32) -> f64 {
+    // Ticks are oriented by token order, not by which token is the base.
+    let token1_price = pool::tick_to_price(pool, tick);
+    if quote.wrapped().address() == pool.token1.address() {
+        token1_price
+    } else {
+        1. / token1_price
+    }
+}
+
+/// Average price across `pools`, weighted by each pool's in-range liquidity.
+pub fn liquidity_weighted(
+    pools: &[Pool<EphemeralTickMapDataProvider>],
+    quote: &Currency,
+) -> anyhow::Result<f64> {
+    let prices = pools
+        .iter()
+        .map(|pool| Ok((of_quote(pool, quote)?, pool.liquidity as f64)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    weighted_mean(&prices)
+}
+
+/// Mean of each `(price, liquidity)`'s price, weighted by its liquidity.
+fn weighted_mean(prices: &[(f64, f64)]) -> anyhow::Result<f64> {
+    let mut weighted_sum = 0.;
+    let mut total_liquidity = 0.;
+    for &(price, liquidity) in prices {
+        weighted_sum += price * liquidity;
+        total_liquidity += liquidity;
+    }
+
+    anyhow::ensure!(total_liquidity > 0., "No liquidity in any aggregated pool");
+    Ok(weighted_sum / total_liquidity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregate_price_is_pulled_toward_the_deeper_pool() {
+        let deep = (100., 9e18);
+        let thin = (110., 1e18);
+        assert_eq!(weighted_mean(&[deep, thin]).unwrap(), 101.);
+        assert_eq!(weighted_mean(&[thin, deep]).unwrap(), 101.);
+
+        // Equally deep pools meet in the middle.
+        assert_eq!(weighted_mean(&[(100., 1e18), (110., 1e18)]).unwrap(), 105.);
+    }
+
+    #[test]
+    fn aggregate_needs_liquidity() {
+        assert!(weighted_mean(&[]).is_err());
+        assert!(weighted_mean(&[(100., 0.), (110., 0.)]).is_err());
+    }
+}
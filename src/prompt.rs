@@ -0,0 +1,75 @@
+use std::{sync::OnceLock, time::Duration};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, BufReader, Lines, Stdin},
+    sync::Mutex,
+};
+
+/// Lines typed by the operator, shared by every prompt so input buffered by one isn't lost to the
+/// next.
+static STDIN: OnceLock<Mutex<Lines<BufReader<Stdin>>>> = OnceLock::new();
+
+/// Asks the operator on stdin to approve `proposal`, no answer within `timeout` is a rejection.
+pub async fn approve_on_stdin(proposal: &str, timeout: Duration) -> anyhow::Result<bool> {
+    let stdin = STDIN.get_or_init(|| Mutex::new(BufReader::new(tokio::io::stdin()).lines()));
+    approve(&mut *stdin.lock().await, proposal, timeout).await
+}
+
+/// Asks the operator to approve `proposal`, reading their answer from `answers`. No answer within
+/// `timeout` is a rejection.
+pub async fn approve<R: AsyncBufRead + Unpin>(
+    answers: &mut Lines<R>,
+    proposal: &str,
+    timeout: Duration,
+) -> anyhow::Result<bool> {
+    println!("{proposal}");
+    println!("Approve? [y/N] (skipping in {timeout:?})");
+
+    // Reading a line is cancel safe, so an answer arriving after the timeout is kept for the next
+    // prompt rather than lost.
+    match tokio::time::timeout(timeout, answers.next_line()).await {
+        Ok(line) => {
+            let Some(line) = line? else {
+                log::warn!("No more answers, skipping");
+                return Ok(false);
+            };
+            let answer = line.trim();
+            Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+        }
+        Err(_) => {
+            log::warn!("No answer within {timeout:?}, skipping");
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[tokio::test]
+    async fn answers_approve_or_reject() {
+        let mut answers = BufReader::new(&b"y\n no\nYES\n\n"[..]).lines();
+        assert!(approve(&mut answers, "first", TIMEOUT).await.unwrap());
+        assert!(!approve(&mut answers, "second", TIMEOUT).await.unwrap());
+        assert!(approve(&mut answers, "third", TIMEOUT).await.unwrap());
+        assert!(!approve(&mut answers, "fourth", TIMEOUT).await.unwrap());
+        // Out of answers.
+        assert!(!approve(&mut answers, "fifth", TIMEOUT).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn no_answer_in_time_is_a_rejection() {
+        let (mut operator, stdin) = tokio::io::duplex(64);
+        let mut answers = BufReader::new(stdin).lines();
+        let short = Duration::from_millis(50);
+
+        assert!(!approve(&mut answers, "first", short).await.unwrap());
+        operator.write_all(b"y\n").await.unwrap();
+        assert!(approve(&mut answers, "second", short).await.unwrap());
+    }
+}
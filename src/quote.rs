@@ -0,0 +1,463 @@
+//! On-chain quotes from Uniswap's QuoterV2, cached so repeated identical quotes are cheap.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    eips::BlockId,
+    primitives::{
+        aliases::{U160, U24},
+        U256,
+    },
+    providers::RootProvider,
+};
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::{
+    execution::{from_human_amount, PrecisionLoss},
+    rpc_budget::RpcBudget,
+};
+
+alloy::sol! {
+    #[sol(rpc)]
+    interface QuoterV2 {
+        struct QuoteExactInputSingleParams {
+            address tokenIn;
+            address tokenOut;
+            uint256 amountIn;
+            uint24 fee;
+            uint160 sqrtPriceLimitX96;
+        }
+
+        struct QuoteExactOutputSingleParams {
+            address tokenIn;
+            address tokenOut;
+            uint256 amount;
+            uint24 fee;
+            uint160 sqrtPriceLimitX96;
+        }
+
+        function quoteExactInputSingle(QuoteExactInputSingleParams params) returns (
+            uint256 amountOut,
+            uint160 sqrtPriceX96After,
+            uint32 initializedTicksCrossed,
+            uint256 gasEstimate
+        );
+
+        function quoteExactOutputSingle(QuoteExactOutputSingleParams params) returns (
+            uint256 amountIn,
+            uint160 sqrtPriceX96After,
+            uint32 initializedTicksCrossed,
+            uint256 gasEstimate
+        );
+    }
+}
+
+// TODO(shelbyd): Per chain.
+pub const MAINNET_QUOTER_V2: &str = "0x61fFE014bA17989E743c5F6cB21bF9697530B21e";
+
+/// Which way a quote trades the quote currency, matching `strategy::Trade`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum Direction {
+    Buy,
+    Sell,
+}
+
+pub struct Quoter {
+    provider: RootProvider,
+    address: Address,
+
+    base: Currency,
+    quote: Currency,
+    fee: FeeAmount,
+
+    /// How many blocks a quote is reused for, 0 disables caching.
+    ttl_blocks: u64,
+
+    /// Raw base amounts by fee, direction, and raw quote amount, with the block they were quoted at.
+    cache: Mutex<HashMap<(u32, Direction, U256), (u64, U256)>>,
+
+    budget: Arc<RpcBudget>,
+    precision_loss: PrecisionLoss,
+}
+
+impl std::fmt::Debug for Quoter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Quoter")
+            .field("address", &self.address)
+            .field("ttl_blocks", &self.ttl_blocks)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Quoter {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        provider: RootProvider,
+        address: Address,
+        base: Currency,
+        quote: Currency,
+        fee: FeeAmount,
+        ttl_blocks: u64,
+        budget: Arc<RpcBudget>,
+        precision_loss: PrecisionLoss,
+    ) -> Self {
+        Quoter {
+            provider,
+            address,
+            base,
+            quote,
+            fee,
+            ttl_blocks,
+            cache: Default::default(),
+            budget,
+            precision_loss,
+        }
+    }
+
+    /// Base amount, in human units, paid to buy or received for selling `amount` of the quote
+    /// currency at `block`.
+    pub async fn quote(
+        &self,
+        direction: Direction,
+        amount: Fraction,
+        block: u64,
+    ) -> anyhow::Result<f64> {
+        self.quote_on(self.fee, direction, amount, block).await
+    }
+
+    /// Of `fees`, the tier whose pool gives the most base for selling `amount`, or takes the least
+    /// for buying it, at `block`. None if none of them could be quoted.
+    pub async fn best_fee(
+        &self,
+        fees: &[FeeAmount],
+        direction: Direction,
+        amount: Fraction,
+        block: u64,
+    ) -> Option<FeeAmount> {
+        let mut best: Option<(FeeAmount, f64)> = None;
+        for &fee in fees {
+            let base = match self.quote_on(fee, direction, amount.clone(), block).await {
+                Ok(base) => base,
+                Err(e) => {
+                    log::warn!("Failed to quote {direction:?} on the {fee:?} tier: {e:#}");
+                    continue;
+                }
+            };
+            log::info!("{direction:?} on the {fee:?} tier quoted at {base} base");
+
+            let better = match (direction, best) {
+                (_, None) => true,
+                (Direction::Buy, Some((_, b))) => base < b,
+                (Direction::Sell, Some((_, b))) => base > b,
+            };
+            if better {
+                best = Some((fee, base));
+            }
+        }
+        best.map(|(fee, _)| fee)
+    }
+
+    async fn quote_on(
+        &self,
+        fee: FeeAmount,
+        direction: Direction,
+        amount: Fraction,
+        block: u64,
+    ) -> anyhow::Result<f64> {
+        let raw: U256 = from_human_amount(amount, &self.quote, self.precision_loss)?
+            .quotient()
+            .to_string()
+            .parse()?;
+
+        let key = (fee as u32, direction, raw);
+        let cached = self.cache.lock().unwrap().get(&key).copied();
+        let base_raw = match cached {
+            Some((quoted_at, v)) if block.saturating_sub(quoted_at) < self.ttl_blocks => {
+                log::debug!("Quote cache hit for {direction:?} {raw} at block {block}");
+                v
+            }
+            _ => {
+                anyhow::ensure!(
+                    self.budget.optional("quote", 1),
+                    "RPC budget used up for this block"
+                );
+                let v = self.fetch(fee, direction, raw, block).await?;
+                let mut cache = self.cache.lock().unwrap();
+                let ttl = self.ttl_blocks;
+                cache.retain(|_, (at, _)| block.saturating_sub(*at) < ttl);
+                if ttl > 0 {
+                    cache.insert(key, (block, v));
+                }
+                v
+            }
+        };
+
+        Ok(
+            CurrencyAmount::from_raw_amount(self.base.clone(), base_raw.to_big_int())?
+                .to_exact()
+                .parse()?,
+        )
+    }
+
+    async fn fetch(
+        &self,
+        fee: FeeAmount,
+        direction: Direction,
+        amount: U256,
+        block: u64,
+    ) -> anyhow::Result<U256> {
+        let quoter = QuoterV2::new(self.address, &self.provider);
+        let (base, quote) = (
+            self.base.wrapped().address(),
+            self.quote.wrapped().address(),
+        );
+        let fee = U24::from(fee as u32);
+
+        let base_amount = match direction {
+            Direction::Buy => {
+                quoter
+                    .quoteExactOutputSingle(QuoterV2::QuoteExactOutputSingleParams {
+                        tokenIn: base,
+                        tokenOut: quote,
+                        amount,
+                        fee,
+                        sqrtPriceLimitX96: U160::ZERO,
+                    })
+                    .block(BlockId::from(block))
+                    .call()
+                    .await?
+                    .amountIn
+            }
+            Direction::Sell => {
+                quoter
+                    .quoteExactInputSingle(QuoterV2::QuoteExactInputSingleParams {
+                        tokenIn: quote,
+                        tokenOut: base,
+                        amountIn: amount,
+                        fee,
+                        sqrtPriceLimitX96: U160::ZERO,
+                    })
+                    .block(BlockId::from(block))
+                    .call()
+                    .await?
+                    .amountOut
+            }
+        };
+        Ok(base_amount)
+    }
+}
+
+/// The fee tier trades were last routed through, to notice when shifting liquidity changes it.
+#[derive(Debug, Default)]
+pub struct ChosenTier {
+    last: Option<FeeAmount>,
+}
+
+impl ChosenTier {
+    /// Records `fee` as chosen at `block`, returning the tier chosen before if it was different.
+    pub fn choose(&mut self, fee: FeeAmount, block: u64) -> Option<FeeAmount> {
+        let previous = self.last.replace(fee).filter(|&previous| previous != fee);
+        if let Some(previous) = previous {
+            log::warn!("Fee tier changed from {previous:?} to {fee:?} at block {block}");
+        }
+        previous
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use alloy::{
+        primitives::Bytes,
+        providers::{Provider, ProviderBuilder},
+        sol_types::SolValue,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        mock_rpc::MockRpc,
+        strategy::testing::{usdc, weth},
+    };
+
+    /// Quotes every amount of WETH at 2000 USDC, caching for `ttl_blocks`, with at most
+    /// `max_per_block` RPC calls per block.
+    async fn quoter(ttl_blocks: u64, max_per_block: Option<usize>) -> (Quoter, MockRpc) {
+        quoter_serving(
+            |_, _| {
+                (
+                    U256::from(2_000_000_000u64),
+                    U256::ZERO,
+                    U256::ZERO,
+                    U256::ZERO,
+                )
+            },
+            ttl_blocks,
+            max_per_block,
+        )
+        .await
+    }
+
+    /// Quotes with `quote`, given the fee and raw WETH amount quoted.
+    async fn quoter_serving(
+        quote: impl Fn(u32, U256) -> (U256, U256, U256, U256) + Send + 'static,
+        ttl_blocks: u64,
+        max_per_block: Option<usize>,
+    ) -> (Quoter, MockRpc) {
+        let rpc = MockRpc::serve(move |method, params| match method {
+            "eth_call" => {
+                let call = &params[0];
+                let input: Bytes = serde_json::from_value(call["input"].clone())
+                    .or_else(|_| serde_json::from_value(call["data"].clone()))
+                    .map_err(|e| e.to_string())?;
+                // After the selector, tokenIn, tokenOut, the amount, then the fee.
+                let word = |i: usize| U256::from_be_slice(&input[4 + 32 * i..4 + 32 * (i + 1)]);
+                let quoted = quote(word(3).to::<u32>(), word(2));
+                Ok(json!(Bytes::from(quoted.abi_encode())))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let quoter = Quoter::new(
+            provider.root().clone(),
+            Address::repeat_byte(0x9a),
+            usdc(),
+            weth(),
+            FeeAmount::MEDIUM,
+            ttl_blocks,
+            Arc::new(RpcBudget::new(max_per_block)),
+            PrecisionLoss::Error,
+        );
+        (quoter, rpc)
+    }
+
+    #[tokio::test]
+    async fn identical_quotes_in_a_block_make_one_call() {
+        let (quoter, rpc) = quoter(1, None).await;
+        let one = || Fraction::new(1, 1);
+
+        assert_eq!(
+            quoter.quote(Direction::Buy, one(), 10).await.unwrap(),
+            2000.
+        );
+        assert_eq!(
+            quoter.quote(Direction::Buy, one(), 10).await.unwrap(),
+            2000.
+        );
+        assert_eq!(rpc.params("eth_call").len(), 1);
+
+        // A different size, direction, or block is quoted again.
+        quoter
+            .quote(Direction::Buy, Fraction::new(2, 1), 10)
+            .await
+            .unwrap();
+        quoter.quote(Direction::Sell, one(), 10).await.unwrap();
+        quoter.quote(Direction::Buy, one(), 11).await.unwrap();
+        assert_eq!(rpc.params("eth_call").len(), 4);
+        assert_eq!(rpc.params("eth_call")[0][1], json!(format!("{:#x}", 10)));
+    }
+
+    #[tokio::test]
+    async fn zero_ttl_disables_the_cache() {
+        let (quoter, rpc) = quoter(0, None).await;
+        for _ in 0..2 {
+            quoter
+                .quote(Direction::Sell, Fraction::new(1, 1), 10)
+                .await
+                .unwrap();
+        }
+        assert_eq!(rpc.params("eth_call").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn quotes_over_budget_are_skipped() {
+        let (quoter, rpc) = quoter(1, Some(1)).await;
+        quoter.budget.start_block();
+        let one = || Fraction::new(1, 1);
+
+        quoter.quote(Direction::Buy, one(), 10).await.unwrap();
+        let e = quoter.quote(Direction::Sell, one(), 10).await.unwrap_err();
+        assert!(e.to_string().contains("RPC budget"));
+        // Cached quotes don't need the budget.
+        quoter.quote(Direction::Buy, one(), 10).await.unwrap();
+        assert_eq!(rpc.params("eth_call").len(), 1);
+
+        quoter.budget.start_block();
+        quoter.quote(Direction::Sell, one(), 11).await.unwrap();
+        assert_eq!(rpc.params("eth_call").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn large_trades_route_through_a_deeper_tier() {
+        // The 0.05% tier is cheaper but thin, the 0.3% tier deep.
+        let (quoter, _rpc) = quoter_serving(
+            |fee, raw| {
+                let weth = raw.to::<u128>() as f64 / 1e18;
+                let usdc = match fee {
+                    500 => weth * 2001. + weth * weth * 50.,
+                    _ => weth * 2006. + weth * weth,
+                };
+                let raw_usdc = U256::from((usdc * 1e6).round() as u128);
+                (raw_usdc, U256::ZERO, U256::ZERO, U256::ZERO)
+            },
+            0,
+            None,
+        )
+        .await;
+        let tiers = [FeeAmount::LOW, FeeAmount::MEDIUM];
+        let best = |amount| quoter.best_fee(&tiers, Direction::Buy, amount, 10);
+
+        assert_eq!(best(Fraction::new(1, 10)).await, Some(FeeAmount::LOW));
+        assert_eq!(best(Fraction::new(10, 1)).await, Some(FeeAmount::MEDIUM));
+    }
+
+    #[tokio::test]
+    async fn shifting_liquidity_changes_the_chosen_tier() {
+        // Which tier is deep, the other charges 1% more.
+        let deep = Arc::new(AtomicU32::new(500));
+        let (quoter, _rpc) = quoter_serving(
+            {
+                let deep = deep.clone();
+                move |fee, raw| {
+                    let usdc = raw.to::<u128>() as f64 / 1e18 * 2000.;
+                    let usdc = if fee == deep.load(Ordering::Relaxed) {
+                        usdc
+                    } else {
+                        usdc * 1.01
+                    };
+                    let raw_usdc = U256::from((usdc * 1e6).round() as u128);
+                    (raw_usdc, U256::ZERO, U256::ZERO, U256::ZERO)
+                }
+            },
+            0,
+            None,
+        )
+        .await;
+        let (quoter, tiers) = (&quoter, &[FeeAmount::LOW, FeeAmount::MEDIUM]);
+        let mut chosen = ChosenTier::default();
+        let route = |block| async move {
+            quoter
+                .best_fee(tiers, Direction::Buy, Fraction::new(1, 1), block)
+                .await
+                .unwrap()
+        };
+
+        let best = route(10).await;
+        assert_eq!(best, FeeAmount::LOW);
+        assert_eq!(chosen.choose(best, 10), None);
+        let best = route(11).await;
+        assert_eq!(chosen.choose(best, 11), None);
+
+        deep.store(3000, Ordering::Relaxed);
+        let best = route(12).await;
+        assert_eq!(best, FeeAmount::MEDIUM);
+        assert_eq!(chosen.choose(best, 12), Some(FeeAmount::LOW));
+        assert_eq!(chosen.choose(best, 13), None);
+    }
+}
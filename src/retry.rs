@@ -0,0 +1,75 @@
+//! Retrying RPC calls that fail transiently, so a blip doesn't kill a long running bot.
+
+use std::{future::Future, time::Duration};
+
+use alloy::transports::{RpcError, TransportError};
+
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Tries before giving up, including the first.
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+
+    /// Wait before the first retry, doubling for each retry after.
+    #[serde(default = "default_base_delay_ms")]
+    base_delay_ms: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_base_delay_ms() -> u64 {
+    200
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_attempts: default_max_attempts(),
+            base_delay_ms: default_base_delay_ms(),
+        }
+    }
+}
+
+impl Config {
+    /// Calls `f` until it succeeds, fails with an error that isn't transient, or runs out of
+    /// attempts.
+    pub async fn retry<T, E, F, Fut>(&self, what: &str, mut f: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<anyhow::Error>,
+    {
+        let mut delay = Duration::from_millis(self.base_delay_ms);
+        let mut attempt = 1;
+        loop {
+            let e = match f().await {
+                Ok(v) => return Ok(v),
+                Err(e) => e.into(),
+            };
+            if attempt >= self.max_attempts || !is_transient(&e) {
+                return Err(e);
+            }
+
+            log::warn!(
+                "Fetching {what} failed ({e:#}), retry {attempt} of {} in {delay:?}",
+                self.max_attempts - 1
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether `e` came from the transport or a node asking to be retried, rather than e.g. a response
+/// that didn't deserialize.
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<TransportError>()
+            .is_some_and(|e| matches!(e, RpcError::Transport(_)) || e.is_retry_err())
+    })
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bounds RPC reads per block. Essential reads always happen, optional ones are dropped once the
+/// budget is used up.
+pub struct RpcBudget {
+    max_per_block: Option<usize>,
+    used: AtomicUsize,
+}
+
+impl RpcBudget {
+    pub fn new(max_per_block: Option<usize>) -> Self {
+        RpcBudget {
+            max_per_block,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn start_block(&self) {
+        self.used.store(0, Ordering::Relaxed);
+    }
+
+    /// Counts a read that happens regardless of the budget.
+    pub fn essential(&self, calls: usize) {
+        self.used.fetch_add(calls, Ordering::Relaxed);
+    }
+
+    /// Whether an optional read of `calls` fits in the budget, counting it if so.
+    pub fn optional(&self, what: &str, calls: usize) -> bool {
+        let Some(max) = self.max_per_block else {
+            self.essential(calls);
+            return true;
+        };
+
+        let fits = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used + calls <= max).then_some(used + calls)
+            })
+            .is_ok();
+        if !fits {
+            log::warn!("RPC budget of {max} per block used up, skipping {what}");
+        }
+        fits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn optional_reads_are_skipped_under_a_tight_budget() {
+        let budget = RpcBudget::new(Some(3));
+        budget.essential(2);
+        assert!(budget.optional("quote", 1));
+        assert!(!budget.optional("quote", 1));
+        // Essential reads still happen over budget.
+        budget.essential(1);
+        assert!(!budget.optional("TWAP price", 1));
+
+        budget.start_block();
+        assert!(!budget.optional("aggregate price", 4));
+        assert!(budget.optional("aggregate price", 3));
+    }
+
+    #[test]
+    fn no_budget_allows_every_read() {
+        let budget = RpcBudget::new(None);
+        budget.essential(100);
+        assert!(budget.optional("quote", 100));
+    }
+}
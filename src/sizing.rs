@@ -0,0 +1,120 @@
+use uniswap_sdk_core::prelude::*;
+
+use crate::{quote::Quoter, strategy::Trade};
+
+/// Binary search steps when sizing, each costs a quote.
+const SEARCH_STEPS: usize = 16;
+
+/// Adjusts the size of trades produced by the strategy.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sizing {
+    /// The largest size, up to the strategy's amount, whose quoted average price is within
+    /// `max_slippage_bps` of the spot price. Includes the pool fee.
+    MaxSizeForSlippage { max_slippage_bps: f64 },
+}
+
+impl Sizing {
+    /// `trade` resized, or None if even the smallest size would exceed the target.
+    pub async fn size(
+        &self,
+        trade: &Trade,
+        quoter: &Quoter,
+        spot: f64,
+        block: u64,
+        quote_decimals: u8,
+    ) -> anyhow::Result<Option<Trade>> {
+        let Sizing::MaxSizeForSlippage { max_slippage_bps } = *self;
+
+        let scale = 10u128.pow(quote_decimals as u32);
+        let to_fraction = |x: f64| Fraction::new((x * scale as f64).floor() as u128, scale);
+        let slippage_bps = |amount: f64| async move {
+            let base = quoter
+                .quote(trade.direction(), to_fraction(amount), block)
+                .await?;
+            let average = base / amount;
+            anyhow::Ok(match trade {
+                Trade::Buy { .. } => (average / spot - 1.) * 10_000.,
+                Trade::Sell { .. } => (1. - average / spot) * 10_000.,
+            })
+        };
+
+        let cap = trade.amount().to_significant(18, None)?.parse::<f64>()?;
+        let Some((size, slippage)) = largest_within(cap, max_slippage_bps, slippage_bps).await?
+        else {
+            log::info!("No size within {max_slippage_bps} bps slippage");
+            return Ok(None);
+        };
+        if size == cap {
+            log::info!("Full size {cap} within target, slippage {slippage:.2} bps");
+            return Ok(Some(trade.clone()));
+        }
+        log::info!("Sized to {size} of {cap}, slippage {slippage:.2} bps");
+        Ok(Some(trade.with_amount(to_fraction(size))))
+    }
+}
+
+/// The largest amount up to `cap` whose slippage is within `max_bps`, with that slippage, found by
+/// binary search assuming slippage grows with the amount.
+async fn largest_within<F, Fut>(
+    cap: f64,
+    max_bps: f64,
+    slippage_bps: F,
+) -> anyhow::Result<Option<(f64, f64)>>
+where
+    F: Fn(f64) -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<f64>>,
+{
+    let at_cap = slippage_bps(cap).await?;
+    if at_cap <= max_bps {
+        return Ok(Some((cap, at_cap)));
+    }
+
+    let (mut lo, mut hi) = ((0., None), cap);
+    for _ in 0..SEARCH_STEPS {
+        let mid = (lo.0 + hi) / 2.;
+        let slippage = slippage_bps(mid).await?;
+        if slippage <= max_bps {
+            lo = (mid, Some(slippage));
+        } else {
+            hi = mid;
+        }
+    }
+    Ok(match lo {
+        (size, Some(slippage)) => Some((size, slippage)),
+        (_, None) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Slippage of a pool whose price moves 10 bps per unit traded, on top of a 30 bps fee.
+    async fn slippage_bps(amount: f64) -> anyhow::Result<f64> {
+        Ok(30. + 10. * amount)
+    }
+
+    #[tokio::test]
+    async fn search_converges_on_the_largest_size_within_target() {
+        let (size, slippage) = largest_within(100., 80., slippage_bps)
+            .await
+            .unwrap()
+            .unwrap();
+        // 5 units reach 80 bps, to within the search's resolution.
+        assert!(size <= 5. && size > 5. - 100. / 2f64.powi(SEARCH_STEPS as i32));
+        assert!(slippage <= 80.);
+    }
+
+    #[tokio::test]
+    async fn full_size_within_target_is_kept() {
+        let found = largest_within(4., 80., slippage_bps).await.unwrap();
+        assert_eq!(found, Some((4., 70.)));
+    }
+
+    #[tokio::test]
+    async fn nothing_is_within_a_target_below_the_fee() {
+        let found = largest_within(100., 20., slippage_bps).await.unwrap();
+        assert_eq!(found, None);
+    }
+}
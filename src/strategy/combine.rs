@@ -0,0 +1,642 @@
+//! Strategies routing each block between several child strategies.
+
+use num_traits::ToPrimitive;
+use serde::Deserialize;
+use uniswap_sdk_core::prelude::Fraction;
+
+use super::{Config, RollingWindow, Strategy, Trade, TradeContext, Wrapped};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Combine {
+    RegimeSwitch {
+        trend: Box<Config>,
+        range: Box<Config>,
+        regime_window: usize,
+        trend_threshold: f64,
+    },
+
+    /// Trades only when every child signals the same direction.
+    All(Vec<MemberConfig>),
+
+    /// Trades as the first child that signals a trade.
+    Any(Vec<MemberConfig>),
+}
+
+/// A child of `all` or `any`, optionally limited to a share of the account's capital.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MemberConfig {
+    Budgeted {
+        strategy: Config,
+
+        /// Fraction (0, 1] of the account's capital, valued in the quote currency, that the
+        /// position built from this child's trades may reach.
+        budget: f64,
+    },
+    Unlimited(Config),
+}
+
+impl Combine {
+    pub fn into_dyn(self) -> anyhow::Result<Box<dyn Strategy>> {
+        Ok(match self {
+            Combine::RegimeSwitch {
+                trend,
+                range,
+                regime_window,
+                trend_threshold,
+            } => {
+                anyhow::ensure!(regime_window > 0, "Regime switch window must be above 0");
+                Box::new(RegimeSwitch {
+                    trend: trend.into_dyn()?,
+                    range: range.into_dyn()?,
+                    window: RollingWindow::new(regime_window),
+                    trend_threshold,
+                    last_routed: Regime::Range,
+                })
+            }
+            Combine::All(children) => {
+                anyhow::ensure!(!children.is_empty(), "all needs at least one strategy");
+                Box::new(All {
+                    children: members(children)?,
+                    proposed: Vec::new(),
+                    traded: None,
+                })
+            }
+            Combine::Any(children) => {
+                anyhow::ensure!(!children.is_empty(), "any needs at least one strategy");
+                Box::new(Any {
+                    children: members(children)?,
+                    routed: None,
+                })
+            }
+        })
+    }
+}
+
+fn members(configs: Vec<MemberConfig>) -> anyhow::Result<Vec<Member>> {
+    configs
+        .into_iter()
+        .map(|config| {
+            let (config, budget) = match config {
+                MemberConfig::Budgeted { strategy, budget } => {
+                    anyhow::ensure!(
+                        budget > 0. && budget <= 1.,
+                        "Member budget must be above 0 and at most 1"
+                    );
+                    (strategy, Some(budget))
+                }
+                MemberConfig::Unlimited(config) => (config, None),
+            };
+            Ok(Member {
+                strategy: config.into_dyn()?,
+                budget,
+                position: 0.,
+            })
+        })
+        .collect()
+}
+
+fn save_members(members: &[Member]) -> Option<serde_json::Value> {
+    let states = members
+        .iter()
+        .map(|m| Wrapped::save(m.position, &*m.strategy))
+        .collect::<Vec<_>>();
+    serde_json::to_value(states).ok()
+}
+
+fn load_members(members: &mut [Member], state: serde_json::Value) -> anyhow::Result<()> {
+    let states: Vec<Option<serde_json::Value>> = serde_json::from_value(state)?;
+    anyhow::ensure!(
+        states.len() == members.len(),
+        "Saved {} strategies, configured {}",
+        states.len(),
+        members.len()
+    );
+    for (member, state) in members.iter_mut().zip(states) {
+        if let Some(state) = state {
+            member.position = Wrapped::load(state, &mut *member.strategy)?;
+        }
+    }
+    Ok(())
+}
+
+/// A child of `All` or `Any`, with the position built from its trades.
+struct Member {
+    strategy: Box<dyn Strategy>,
+
+    /// Fraction of the account's capital the member's position may reach, unlimited when None.
+    budget: Option<f64>,
+
+    /// Net quote currency bought on this member's trades, lossy.
+    position: f64,
+}
+
+impl Member {
+    /// Whether `trades` keep the member's position within its budget of the account's capital.
+    /// Trades that shrink the position always do.
+    fn within_budget(&self, trades: &[Trade], ctx: &TradeContext) -> bool {
+        let Some(budget) = self.budget else {
+            return true;
+        };
+        let after = self.position + net_lossy(trades);
+        if after.abs() <= self.position.abs() {
+            return true;
+        }
+
+        let Some(balances) = ctx.balances else {
+            log::warn!("No balances to value a budgeted strategy's capital, not trading");
+            return false;
+        };
+        let limit = budget * (balances.quote + balances.base / ctx.price_lossy);
+        if after.abs() > limit {
+            log::info!("Strategy's position would reach {after:.6}, over its budget of {limit:.6}");
+            return false;
+        }
+        true
+    }
+
+    fn roll_back(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            self.strategy.on_execution_failed(trade);
+        }
+    }
+}
+
+/// Net quote currency bought by `trades`, lossy.
+fn net_lossy(trades: &[Trade]) -> f64 {
+    trades
+        .iter()
+        .map(|t| {
+            let amount = t.amount();
+            let amount = amount.numerator.to_f64().unwrap_or(f64::NAN)
+                / amount.denominator.to_f64().unwrap_or(f64::NAN);
+            match t {
+                Trade::Buy { .. } => amount,
+                Trade::Sell { .. } => -amount,
+            }
+        })
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Regime {
+    Trend,
+    Range,
+}
+
+/// Routes each block to a trend or range strategy, depending on how strongly the price is trending.
+///
+/// Trendiness is the move of the least squares line fitted to the last `regime_window` prices, as a
+/// fraction of their mean. Above `trend_threshold` is trending, otherwise ranging. Each strategy only
+/// sees the blocks routed to it, and keeps its state while the other is active.
+pub struct RegimeSwitch {
+    trend: Box<dyn Strategy>,
+    range: Box<dyn Strategy>,
+
+    window: RollingWindow,
+    trend_threshold: f64,
+
+    /// Which strategy the most recent block was routed to.
+    last_routed: Regime,
+}
+
+impl Strategy for RegimeSwitch {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        self.window.push(ctx.price_lossy);
+
+        let regime = if self.window.is_full() {
+            let span = (self.window.period - 1) as f64;
+            let trendiness = (self.window.slope() * span / self.window.mean()).abs();
+            log::info!("Trendiness {trendiness:.5}");
+            if trendiness > self.trend_threshold {
+                Regime::Trend
+            } else {
+                Regime::Range
+            }
+        } else {
+            Regime::Range
+        };
+
+        if regime != self.last_routed {
+            log::info!(
+                "Switching from {:?} to {regime:?} strategy",
+                self.last_routed
+            );
+        }
+        self.last_routed = regime;
+
+        match regime {
+            Regime::Trend => self.trend.trade(ctx),
+            Regime::Range => self.range.trade(ctx),
+        }
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        match self.last_routed {
+            Regime::Trend => self.trend.on_execution_failed(trade),
+            Regime::Range => self.range.on_execution_failed(trade),
+        }
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        let mut ids = self.trend.cancellations();
+        ids.extend(self.range.cancellations());
+        ids
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.trend.needs_swaps() || self.range.needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.trend.needs_balances() || self.range.needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "prices": self.window.prices(),
+            "trend": self.trend.save(),
+            "range": self.range.save(),
+        }))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct State {
+            prices: Vec<f64>,
+            trend: Option<serde_json::Value>,
+            range: Option<serde_json::Value>,
+        }
+        let state: State = serde_json::from_value(state)?;
+        if let Some(trend) = state.trend {
+            self.trend.load(trend)?;
+        }
+        if let Some(range) = state.range {
+            self.range.load(range)?;
+        }
+        self.window.restore(state.prices);
+        Ok(())
+    }
+}
+
+/// Trades only when every child agrees, e.g. a threshold confirmed by an RSI.
+///
+/// A child's proposal is the total of the trades it returns, which must all be in one direction.
+/// When every child proposes the same direction, trades the smallest proposal, with the limit price
+/// and options of that child's first trade. If any child has no trade, or the children disagree on
+/// direction, there's no trade. A child whose proposal would take its position over its budget has
+/// no trade.
+pub struct All {
+    children: Vec<Member>,
+
+    /// Each child's trades for the most recent block, rolled back if they aren't executed.
+    proposed: Vec<Vec<Trade>>,
+
+    /// Net quote currency of the most recent block's trade, counted towards every child's position.
+    traded: Option<f64>,
+}
+
+impl All {
+    fn roll_back(&mut self) {
+        let traded = self.traded.take().unwrap_or(0.);
+        for (child, trades) in self.children.iter_mut().zip(self.proposed.drain(..)) {
+            child.position -= traded;
+            child.roll_back(&trades);
+        }
+    }
+}
+
+impl Strategy for All {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.traded = None;
+        self.proposed = self
+            .children
+            .iter_mut()
+            .map(|child| {
+                let trades = child.strategy.trade(ctx);
+                if child.within_budget(&trades, ctx) {
+                    trades
+                } else {
+                    child.roll_back(&trades);
+                    Vec::new()
+                }
+            })
+            .collect();
+        match agreed(&self.proposed) {
+            Some(trade) => {
+                let net = net_lossy(std::slice::from_ref(&trade));
+                for child in &mut self.children {
+                    child.position += net;
+                }
+                self.traded = Some(net);
+                vec![trade]
+            }
+            None => {
+                self.roll_back();
+                Vec::new()
+            }
+        }
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        self.roll_back();
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.children
+            .iter_mut()
+            .flat_map(|c| c.strategy.cancellations())
+            .collect()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.children.iter().any(|c| c.strategy.needs_swaps())
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.children
+            .iter()
+            .any(|c| c.budget.is_some() || c.strategy.needs_balances())
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        save_members(&self.children)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        load_members(&mut self.children, state)
+    }
+}
+
+/// The smallest proposal if every child's trades are in the same direction.
+fn agreed(proposed: &[Vec<Trade>]) -> Option<Trade> {
+    let mut smallest: Option<(&Trade, Fraction)> = None;
+    for trades in proposed {
+        let first = trades.first()?;
+        if trades.iter().any(|t| t.direction() != first.direction())
+            || smallest
+                .as_ref()
+                .is_some_and(|(s, _)| s.direction() != first.direction())
+        {
+            log::info!("Strategies disagree on direction, not trading");
+            return None;
+        }
+
+        let total = trades.iter().skip(1).fold(first.amount().clone(), |a, t| {
+            let b = t.amount();
+            Fraction::new(
+                a.numerator.clone() * &b.denominator + b.numerator.clone() * &a.denominator,
+                a.denominator.clone() * &b.denominator,
+            )
+        });
+        let is_larger = smallest.as_ref().is_some_and(|(_, s)| {
+            total.numerator.clone() * &s.denominator >= s.numerator.clone() * &total.denominator
+        });
+        if !is_larger {
+            smallest = Some((first, total));
+        }
+    }
+
+    smallest.map(|(trade, amount)| trade.with_amount(amount))
+}
+
+/// Trades as the first child, in order, that has a trade within its budget.
+///
+/// Every child sees every block so their state stays current, and trades from children after the
+/// first are rolled back as not executed.
+pub struct Any {
+    children: Vec<Member>,
+
+    /// Child whose trades were most recently returned.
+    routed: Option<usize>,
+}
+
+impl Strategy for Any {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.routed = None;
+        let mut result = Vec::new();
+        for (i, child) in self.children.iter_mut().enumerate() {
+            let trades = child.strategy.trade(ctx);
+            if trades.is_empty() {
+                continue;
+            }
+            if self.routed.is_none() && child.within_budget(&trades, ctx) {
+                child.position += net_lossy(&trades);
+                self.routed = Some(i);
+                result = trades;
+            } else {
+                child.roll_back(&trades);
+            }
+        }
+        result
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        if let Some(i) = self.routed {
+            let child = &mut self.children[i];
+            child.position -= net_lossy(std::slice::from_ref(trade));
+            child.strategy.on_execution_failed(trade);
+        }
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.children
+            .iter_mut()
+            .flat_map(|c| c.strategy.cancellations())
+            .collect()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.children.iter().any(|c| c.strategy.needs_swaps())
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.children
+            .iter()
+            .any(|c| c.budget.is_some() || c.strategy.needs_balances())
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        save_members(&self.children)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        load_members(&mut self.children, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        quote::Direction::{Buy, Sell},
+        strategy::{testing::*, Balances},
+    };
+
+    /// Feeds `blocks` blocks at a price of 2000 to `strategy`, with capital worth 4 of the quote
+    /// currency, returning each block's trades.
+    fn run_with_capital(strategy: &mut dyn Strategy, blocks: u64) -> Vec<Vec<Trade>> {
+        let quoter = quoter();
+        (1..=blocks)
+            .map(|block| {
+                let mut ctx = context(&quoter, block, 2000.);
+                ctx.balances = Some(Balances {
+                    base: 6000.,
+                    quote: 1.,
+                });
+                strategy.trade(&ctx)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn member_over_budget_is_suppressed_while_another_trades() {
+        let mut any = strategy(json!({ "any": [
+            { "strategy": { "always_buy": 1 }, "budget": 0.5 },
+            { "always_buy": 3 },
+        ] }));
+        assert!(any.needs_balances());
+
+        // The first may hold half of the capital of 4.
+        let trades = run_with_capital(&mut *any, 4);
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![(Buy, 1.)],
+                vec![(Buy, 1.)],
+                vec![(Buy, 3.)],
+                vec![(Buy, 3.)],
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_trades_return_their_budget() {
+        let mut any = strategy(json!({ "any": [
+            { "strategy": { "always_buy": 2 }, "budget": 0.5 },
+            { "always_buy": 3 },
+        ] }));
+        for trades in run_with_capital(&mut *any, 3) {
+            assert_eq!(signals(&trades), [(Buy, 2.)]);
+            any.on_execution_failed(&trades[0]);
+        }
+    }
+
+    #[test]
+    fn all_stops_once_a_member_is_over_budget() {
+        let mut all = strategy(json!({ "all": [
+            { "strategy": { "always_buy": 1 }, "budget": 0.5 },
+            { "always_buy": 1 },
+        ] }));
+        let trades = run_with_capital(&mut *all, 3);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![(Buy, 1.)], vec![(Buy, 1.)], vec![]]
+        );
+    }
+
+    #[test]
+    fn budgets_must_be_a_fraction_of_capital() {
+        for budget in [0., 1.5] {
+            let config = json!({ "any": [{ "strategy": { "always_buy": 1 }, "budget": budget }] });
+            let config: Config = serde_json::from_value(config).unwrap();
+            assert!(config.into_dyn().is_err());
+        }
+    }
+
+    #[test]
+    fn regime_switch_routes_by_trendiness() {
+        let config = json!({ "regime_switch": {
+            "trend": { "always_buy": 1 },
+            "range": { "always_sell": 1 },
+            "regime_window": 3,
+            "trend_threshold": 0.05,
+        } });
+
+        // Ranging until the window is full.
+        let trades = run(&mut *strategy(config.clone()), &[100., 110., 120., 130.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![(Sell, 1.)],
+                vec![(Sell, 1.)],
+                vec![(Buy, 1.)],
+                vec![(Buy, 1.)],
+            ]
+        );
+
+        let trades = run(&mut *strategy(config), &[100., 101., 100., 99., 100.]);
+        assert!(signals_per_block(&trades)
+            .iter()
+            .all(|signals| signals == &[(Sell, 1.)]));
+    }
+
+    #[test]
+    fn regime_switch_of_transforms_feeds_each_only_its_blocks() {
+        let mut switch = strategy(json!({ "regime_switch": {
+            "trend": { "ema": { "carry": 0.5, "inner": { "threshold": {
+                "sell": { "at": 120.0, "amount": 1 },
+            } } } },
+            "range": { "sma": { "window": 2, "inner": { "threshold": {
+                "buy": { "at": 105.0, "amount": 1 },
+            } } } },
+            "regime_window": 3,
+            "trend_threshold": 0.05,
+        } }));
+
+        // Ranging for the first two blocks, then trending. The EMA starts at 120, had it seen the
+        // ranging blocks it would be at 112.5 and not sell.
+        let trades = run(&mut *switch, &[100., 110., 120., 130.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![(Buy, 1.)],
+                vec![(Buy, 1.)],
+                vec![(Sell, 1.)],
+                vec![(Sell, 1.)],
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_children_keep_separate_state() {
+        let ema = |carry: f64| {
+            json!({ "ema": { "carry": carry, "inner": { "threshold": {
+                "buy": { "at": 100.0, "amount": 1 },
+            } } } })
+        };
+        let mut all = strategy(json!({ "all": [ema(0.), ema(0.5)] }));
+
+        // The raw price is at 100 from the second block, the smoothed one from the third.
+        let trades = run(&mut *all, &[110., 95., 95.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![(Buy, 1.)]]
+        );
+    }
+
+    #[test]
+    fn nested_combinators_route_through_every_level() {
+        let mut nested = strategy(json!({ "any": [
+            { "all": [
+                { "confirm": { "blocks": 2, "inner": { "always_buy": 1 } } },
+                { "always_buy": 2 },
+            ] },
+            { "always_sell": 3 },
+        ] }));
+
+        // Until the confirmation, `all` disagrees and `any` falls through to the sell.
+        let trades = run(&mut *nested, &[100., 100., 100.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![(Sell, 3.)], vec![(Buy, 1.)], vec![(Buy, 1.)]]
+        );
+    }
+}
@@ -1,25 +1,321 @@
-use serde::Deserialize;
-use uniswap_sdk_core::prelude::Fraction;
+use std::{collections::VecDeque, sync::Arc};
+
+use anyhow::Context;
+use num_traits::{Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use uniswap_sdk_core::prelude::{
+    Address, Currency, CurrencyAmount, Fraction, FractionBase, Percent,
+};
+use uniswap_v3_sdk::prelude::{EphemeralTickMapDataProvider, Pool};
+
+use crate::{
+    pool,
+    quote::{Direction, Quoter},
+};
+
+mod combine;
+#[cfg(test)]
+pub mod testing;
+mod transform;
+
+pub use combine::Combine;
+pub use transform::Transform;
 
 pub trait Strategy {
-    // TODO(shelbyd): Can return multiple trades?
-    fn trade(&mut self, ctx: &TradeContext) -> Option<Trade>;
+    /// Trades to execute this block, in order. Empty for no trade.
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade>;
+
+    /// Called when a trade just returned from `trade` was not executed, so any state advanced
+    /// for it can be rolled back.
+    fn on_execution_failed(&mut self, _trade: &Trade) {}
+
+    /// Intent ids of previously returned trades that shouldn't execute anymore, taken after each
+    /// `trade`. Only trades still queued by `net_window_blocks` can be cancelled.
+    fn cancellations(&mut self) -> Vec<u64> {
+        Vec::new()
+    }
+
+    /// Whether `TradeContext::swaps` should be filled in, which costs an extra RPC call per block.
+    fn needs_swaps(&self) -> bool {
+        false
+    }
+
+    /// Whether `TradeContext::balances` should be filled in, which needs the `account` config or
+    /// live trading, and costs two extra RPC calls per block. Live without `account`, they're the
+    /// balances of the account trading next.
+    fn needs_balances(&self) -> bool {
+        false
+    }
+
+    /// History to persist across restarts, `None` if there's nothing worth keeping.
+    fn save(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Restores history from `save`.
+    fn load(&mut self, _state: serde_json::Value) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl Strategy for Box<dyn Strategy> {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        (**self).trade(ctx)
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        (**self).on_execution_failed(trade)
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        (**self).cancellations()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        (**self).needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        (**self).needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        (**self).save()
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        (**self).load(state)
+    }
+}
+
+/// Saved state of a wrapper strategy, with that of its inner strategy.
+#[derive(Serialize, Deserialize)]
+struct Wrapped<T> {
+    state: T,
+    inner: Option<serde_json::Value>,
+}
+
+impl<T: Serialize> Wrapped<T> {
+    fn save(state: T, inner: &dyn Strategy) -> Option<serde_json::Value> {
+        serde_json::to_value(Wrapped {
+            state,
+            inner: inner.save(),
+        })
+        .ok()
+    }
+}
+
+impl<T: serde::de::DeserializeOwned> Wrapped<T> {
+    /// Loads the inner strategy's state, returning the wrapper's.
+    fn load(state: serde_json::Value, inner: &mut dyn Strategy) -> anyhow::Result<T> {
+        let wrapped: Wrapped<T> = serde_json::from_value(state)?;
+        if let Some(state) = wrapped.inner {
+            inner.load(state)?;
+        }
+        Ok(wrapped.state)
+    }
 }
 
 /// Useful context for trading Strategies to utilize in determining if trades should happen.
-// TODO(shelbyd): Uniswap's quoting available here.
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct TradeContext {
+    pub block_number: u64,
+
     pub price_lossy: f64,
+
+    /// The pool fee as a fraction of the input, e.g. 0.003 for the 0.3% tier.
+    pub fee_lossy: f64,
+
+    /// The pool's in-range liquidity.
+    pub liquidity: u128,
+
+    /// Change in the pool's in-range liquidity since the previous block, zero on the first block.
+    pub liquidity_delta: i128,
+
+    pub quoter: Arc<Quoter>,
+
+    /// The traded pool with its tick data, for simulating swaps locally. Not available when
+    /// replaying a recorded feed.
+    pub pool: Option<Arc<Pool<EphemeralTickMapDataProvider>>>,
+
+    /// Swaps in the traded pool during this block, empty unless the strategy `needs_swaps`.
+    pub swaps: Vec<PoolSwap>,
+
+    /// The trading account's balances, unless the strategy doesn't `needs_balances`.
+    pub balances: Option<Balances>,
+}
+
+/// Balances of the configured account, in human units.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Balances {
+    pub base: f64,
+    pub quote: f64,
+}
+
+// Manual so the pool's tick data isn't logged with every context.
+impl std::fmt::Debug for TradeContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TradeContext")
+            .field("block_number", &self.block_number)
+            .field("price_lossy", &self.price_lossy)
+            .field("fee_lossy", &self.fee_lossy)
+            .field("liquidity", &self.liquidity)
+            .field("liquidity_delta", &self.liquidity_delta)
+            .field("quoter", &self.quoter)
+            .field("swaps", &self.swaps)
+            .field("balances", &self.balances)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A swap in the traded pool.
+#[derive(Debug, Clone)]
+pub struct PoolSwap {
+    pub sender: Address,
+    pub recipient: Address,
+
+    /// Quote currency the swapper received from the pool, negative if they paid it in.
+    pub quote_bought: Fraction,
+}
+
+impl TradeContext {
+    /// Whether the price is usable, warning if not. Strategies check this before updating state
+    /// from the price, as a NaN or infinity would poison it permanently.
+    pub fn has_valid_price(&self) -> bool {
+        let valid = self.price_lossy.is_finite() && self.price_lossy > 0.;
+        if !valid {
+            log::warn!(
+                "Ignoring invalid price {} at block {}",
+                self.price_lossy,
+                self.block_number
+            );
+        }
+        valid
+    }
+
+    /// Price actually paid per quote unit when buying, after the pool fee.
+    pub fn effective_buy_price(&self) -> f64 {
+        self.price_lossy / (1. - self.fee_lossy)
+    }
+
+    /// Price actually received per quote unit when selling, after the pool fee.
+    pub fn effective_sell_price(&self) -> f64 {
+        self.price_lossy * (1. - self.fee_lossy)
+    }
+
+    /// On-chain quote of the base amount for trading `amount` of the quote currency at this block.
+    /// Identical quotes are cached, so strategy components can ask for the same quote freely.
+    pub fn quote(&self, direction: Direction, amount: Fraction) -> anyhow::Result<f64> {
+        // Strategies are synchronous, so block this worker thread on the quote.
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(self.quoter.quote(
+                direction,
+                amount,
+                self.block_number,
+            ))
+        })
+    }
+
+    /// What swapping `amount` (human units) of `input` through the pool would pay out, simulated
+    /// from its tick data so it accounts for liquidity and price impact without an RPC call.
+    pub fn quote_exact_input(
+        &self,
+        input: &Currency,
+        amount: Fraction,
+    ) -> anyhow::Result<CurrencyAmount<Currency>> {
+        let pool = self
+            .pool
+            .as_deref()
+            .context("No pool available to quote against")?;
+        pool::simulate_exact_input(pool, input, amount)
+    }
 }
 
-// TODO(shelbyd): Restrictions on execution, like max-rate. Basically things that go in UniSwap SwapOptions.
-#[derive(Debug)]
+/// Amounts are always in human units of the quote currency, as prices are base per quote.
+#[derive(Debug, Clone)]
 pub enum Trade {
-    Buy { amount: Fraction },
-    Sell { amount: Fraction },
+    Buy {
+        /// Quote currency received, paying whatever base currency that costs.
+        amount: Fraction,
+        /// Highest price to buy up to, the swap stops there rather than paying more.
+        limit_price: Option<f64>,
+        options: TradeOptions,
+    },
+    Sell {
+        /// Quote currency spent, receiving whatever base currency that pays.
+        amount: Fraction,
+        /// Lowest price to sell down to, the swap stops there rather than receiving less.
+        limit_price: Option<f64>,
+        options: TradeOptions,
+    },
+}
+
+/// Restrictions on how a trade executes, the defaults leave them to the executor's configuration.
+#[derive(Debug, Clone, Default)]
+pub struct TradeOptions {
+    /// How much worse than quoted the swap may execute before reverting. Overrides `slippage_bps`,
+    /// and isn't escalated on retries.
+    pub slippage_tolerance: Option<Percent>,
+
+    /// The swap reverts if not mined within this many seconds of the latest block's timestamp.
+    pub deadline_secs: Option<u64>,
+
+    /// Trade this percentage of the account's balance of the currency spent instead, at most
+    /// `amount`. Resolved against the balance right before executing.
+    pub percent_of_balance: Option<f64>,
+
+    /// Id the strategy emitted this trade under, for cancelling it with `cancellations` while it's
+    /// still queued.
+    pub intent_id: Option<u64>,
+}
+
+impl Trade {
+    pub fn amount(&self) -> &Fraction {
+        match self {
+            Trade::Buy { amount, .. } | Trade::Sell { amount, .. } => amount,
+        }
+    }
+
+    pub fn limit_price(&self) -> Option<f64> {
+        match self {
+            Trade::Buy { limit_price, .. } | Trade::Sell { limit_price, .. } => *limit_price,
+        }
+    }
+
+    pub fn options(&self) -> &TradeOptions {
+        match self {
+            Trade::Buy { options, .. } | Trade::Sell { options, .. } => options,
+        }
+    }
+
+    pub fn direction(&self) -> Direction {
+        match self {
+            Trade::Buy { .. } => Direction::Buy,
+            Trade::Sell { .. } => Direction::Sell,
+        }
+    }
+
+    /// The same trade with a different amount.
+    pub fn with_amount(&self, amount: Fraction) -> Trade {
+        let (limit_price, options) = (self.limit_price(), self.options().clone());
+        match self {
+            Trade::Buy { .. } => Trade::Buy {
+                amount,
+                limit_price,
+                options,
+            },
+            Trade::Sell { .. } => Trade::Sell {
+                amount,
+                limit_price,
+                options,
+            },
+        }
+    }
 }
 
+/// A strategy, either standalone or composed of others by a `Transform` or `Combine`. Compositions
+/// nest arbitrarily, e.g. a regime switch between an EMA of a threshold and an SMA of an RSI, and
+/// each child keeps its own state.
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Config {
@@ -27,39 +323,230 @@ pub enum Config {
     AlwaysBuy(AlwaysBuy),
     AlwaysSell(AlwaysSell),
     Threshold(Threshold),
-    Ema { carry: f64, inner: Box<Config> },
+    Laddered(Laddered),
+    LiquidityExit(LiquidityExit),
+    PercentB(PercentBConfig),
+    Mirror(Mirror),
+    Rebalance(Rebalance),
+    Rsi(RsiConfig),
+    Grid(GridConfig),
+    Dca {
+        amount: FractionInput,
+        every_blocks: u64,
+    },
+    EmaCross {
+        fast: f64,
+        slow: f64,
+        amount: FractionInput,
+    },
+
+    /// Wrappers around a single inner strategy. Tried after the strategies above.
+    #[serde(untagged)]
+    Transform(Transform),
+
+    /// Strategies routing between several child strategies.
+    #[serde(untagged)]
+    Combine(Combine),
 }
 
 impl Config {
-    pub fn into_dyn(self) -> Box<dyn Strategy> {
-        match self {
+    pub fn into_dyn(self) -> anyhow::Result<Box<dyn Strategy>> {
+        Ok(match self {
             Config::Empty(()) => Box::new(Empty),
             Config::AlwaysBuy(v) => Box::new(v),
             Config::AlwaysSell(v) => Box::new(v),
-            Config::Threshold(v) => Box::new(v),
-            Config::Ema { carry, inner } => {
-                let inner = inner.into_dyn();
-                Box::new(ExponentialMovingAverage {
-                    carry,
-                    inner,
+            Config::Threshold(v) => {
+                anyhow::ensure!(
+                    v.buy.is_some() || v.sell.is_some(),
+                    "Threshold needs a buy or a sell"
+                );
+                let mut percents = [&v.buy, &v.sell]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|p| p.percent_of_balance);
+                anyhow::ensure!(
+                    percents.all(|p| p > 0. && p <= 100.),
+                    "Threshold percent_of_balance must be above 0 and at most 100"
+                );
+                Box::new(v)
+            }
+            Config::LiquidityExit(v) => Box::new(v),
+            Config::Mirror(v) => {
+                anyhow::ensure!(
+                    v.scale.is_finite() && v.scale > 0.,
+                    "Mirror scale must be above 0"
+                );
+                Box::new(v)
+            }
+            Config::Rsi(config) => {
+                anyhow::ensure!(config.period > 0, "RSI period must be above 0");
+                Box::new(Rsi {
+                    config,
+                    last_price: None,
+                    changes: 0,
+                    average_gain: 0.,
+                    average_loss: 0.,
                     last: None,
+                    rollback_to: None,
                 })
             }
-        }
+            Config::Grid(config) => {
+                anyhow::ensure!(config.levels > 0, "Grid levels must be above 0");
+                anyhow::ensure!(
+                    config.lower < config.upper,
+                    "Grid lower must be below upper"
+                );
+                let step = (config.upper - config.lower) / config.levels as f64;
+                let lines = (0..=config.levels)
+                    .map(|i| config.lower + step * i as f64)
+                    .collect::<Vec<_>>();
+                Box::new(Grid {
+                    filled: vec![false; lines.len()],
+                    lines,
+                    config,
+                    cell: None,
+                    pending: Vec::new(),
+                })
+            }
+            Config::Dca {
+                amount,
+                every_blocks,
+            } => {
+                anyhow::ensure!(every_blocks > 0, "DCA every_blocks must be above 0");
+                Box::new(Dca {
+                    amount,
+                    every_blocks,
+                    count: 0,
+                })
+            }
+            Config::EmaCross { fast, slow, amount } => {
+                anyhow::ensure!(
+                    (0. ..=1.).contains(&fast) && (0. ..=1.).contains(&slow),
+                    "EMA cross carries must be between 0 and 1"
+                );
+                anyhow::ensure!(fast < slow, "EMA cross fast carry must be below slow carry");
+                Box::new(EmaCross {
+                    fast,
+                    slow,
+                    amount,
+                    averages: None,
+                    fast_above: None,
+                    rollback_to: None,
+                })
+            }
+            Config::Rebalance(v) => {
+                anyhow::ensure!(
+                    (0. ..=100.).contains(&v.target_base_pct),
+                    "Rebalance target_base_pct must be between 0 and 100"
+                );
+                Box::new(v)
+            }
+            Config::Laddered(mut v) => {
+                v.bands.sort_by(|a, b| b.below.total_cmp(&a.below));
+                Box::new(v)
+            }
+            Config::PercentB(config) => {
+                anyhow::ensure!(config.period > 0, "Percent B period must be above 0");
+                Box::new(PercentB {
+                    window: RollingWindow::new(config.period),
+                    config,
+                    last: None,
+                    rollback_to: None,
+                })
+            }
+            Config::Transform(t) => t.into_dyn()?,
+            Config::Combine(c) => c.into_dyn()?,
+        })
     }
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
-#[serde(untagged)]
-// TODO(shelbyd): Allow decimal input.
-enum FractionInput {
+/// An exact amount, from an integer (`2`), a decimal (`"0.25"`), or a fraction
+/// (`{ numerator: 1, denominator: 4 }`). Unquoted decimals like `0.25` are read as written.
+#[derive(Debug, Clone, Copy)]
+pub enum FractionInput {
     Int(u64),
     Fraction { numerator: u64, denominator: u64 },
 }
 
-impl Into<Fraction> for FractionInput {
-    fn into(self) -> Fraction {
-        match self {
+impl FractionInput {
+    /// Parses a decimal like `1.5` exactly, as `15/10`.
+    fn parse_decimal(s: &str) -> Result<Self, String> {
+        let (whole, fractional) = s.split_once('.').unwrap_or((s, ""));
+        let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (whole.is_empty() && fractional.is_empty()) || !digits(whole) || !digits(fractional) {
+            return Err(format!("invalid decimal {s:?}, expected e.g. \"0.25\""));
+        }
+
+        let too_precise = || format!("decimal {s:?} has too many digits");
+        let denominator = 10u64
+            .checked_pow(fractional.len() as u32)
+            .ok_or_else(too_precise)?;
+        let numerator = format!("{whole}{fractional}")
+            .parse::<u64>()
+            .map_err(|_| too_precise())?;
+        Ok(FractionInput::Fraction {
+            numerator,
+            denominator,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for FractionInput {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Ratio {
+            numerator: u64,
+            denominator: u64,
+        }
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = FractionInput;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an integer, a decimal, or a numerator and denominator")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(FractionInput::Int(v))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map(FractionInput::Int)
+                    .map_err(|_| E::custom(format!("amount {v} can't be negative")))
+            }
+
+            // Formatted as the shortest decimal that reads back as `v`, i.e. as it was written.
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+                FractionInput::parse_decimal(&v.to_string()).map_err(E::custom)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                FractionInput::parse_decimal(v.trim()).map_err(E::custom)
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let ratio = Ratio::deserialize(serde::de::value::MapAccessDeserializer::new(map))?;
+                Ok(FractionInput::Fraction {
+                    numerator: ratio.numerator,
+                    denominator: ratio.denominator,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl From<FractionInput> for Fraction {
+    fn from(input: FractionInput) -> Self {
+        match input {
             FractionInput::Int(i) => Fraction::new(i, 1),
             FractionInput::Fraction {
                 numerator,
@@ -74,10 +561,12 @@ impl Into<Fraction> for FractionInput {
 pub struct AlwaysBuy(FractionInput);
 
 impl Strategy for AlwaysBuy {
-    fn trade(&mut self, _: &TradeContext) -> Option<Trade> {
-        Some(Trade::Buy {
+    fn trade(&mut self, _: &TradeContext) -> Vec<Trade> {
+        vec![Trade::Buy {
             amount: self.0.into(),
-        })
+            limit_price: None,
+            options: Default::default(),
+        }]
     }
 }
 
@@ -86,18 +575,20 @@ impl Strategy for AlwaysBuy {
 pub struct AlwaysSell(FractionInput);
 
 impl Strategy for AlwaysSell {
-    fn trade(&mut self, _: &TradeContext) -> Option<Trade> {
-        Some(Trade::Sell {
+    fn trade(&mut self, _: &TradeContext) -> Vec<Trade> {
+        vec![Trade::Sell {
             amount: self.0.into(),
-        })
+            limit_price: None,
+            options: Default::default(),
+        }]
     }
 }
 
 pub struct Empty;
 
 impl Strategy for Empty {
-    fn trade(&mut self, _: &TradeContext) -> Option<Trade> {
-        None
+    fn trade(&mut self, _: &TradeContext) -> Vec<Trade> {
+        Vec::new()
     }
 }
 
@@ -105,57 +596,1280 @@ impl Strategy for Empty {
 pub struct Threshold {
     buy: Option<ThresholdPoint>,
     sell: Option<ThresholdPoint>,
+
+    #[serde(default)]
+    price_basis: PriceBasis,
+}
+
+/// Which price a Threshold's `at` is compared against.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PriceBasis {
+    /// The pool's mid price, ignoring fees.
+    #[default]
+    Spot,
+
+    /// The price actually paid or received after the pool fee. Buys need the spot price to fall
+    /// further, and sells need it to rise further, than with `spot`.
+    Effective,
+
+    /// The average price of an on-chain quote for the point's amount, so it includes price impact.
+    Quoted,
 }
 
 #[derive(Debug, Deserialize)]
 struct ThresholdPoint {
     at: f64,
     amount: FractionInput,
+
+    /// Trade this percentage of the balance spent (base to buy, quote to sell), at most `amount`.
+    percent_of_balance: Option<f64>,
+
+    /// Worst price the swap may reach, rounded to the nearest usable tick of the pool.
+    limit_price: Option<f64>,
+}
+
+impl Threshold {
+    fn price(
+        &self,
+        ctx: &TradeContext,
+        direction: Direction,
+        amount: FractionInput,
+    ) -> Option<f64> {
+        match (self.price_basis, direction) {
+            (PriceBasis::Spot, _) => Some(ctx.price_lossy),
+            (PriceBasis::Effective, Direction::Buy) => Some(ctx.effective_buy_price()),
+            (PriceBasis::Effective, Direction::Sell) => Some(ctx.effective_sell_price()),
+            (PriceBasis::Quoted, _) => {
+                let amount: Fraction = amount.into();
+                let amount_lossy = amount.numerator.to_f64()? / amount.denominator.to_f64()?;
+                match ctx.quote(direction, amount) {
+                    Ok(base) => Some(base / amount_lossy),
+                    Err(e) => {
+                        log::warn!("Failed to quote {direction:?}: {e:#}");
+                        None
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl Strategy for Threshold {
-    fn trade(&mut self, ctx: &TradeContext) -> Option<Trade> {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
         if let Some(buy) = &self.buy {
-            if ctx.price_lossy <= buy.at {
-                return Some(Trade::Buy {
+            if self
+                .price(ctx, Direction::Buy, buy.amount)
+                .is_some_and(|p| p <= buy.at)
+            {
+                return vec![Trade::Buy {
                     amount: buy.amount.into(),
-                });
+                    limit_price: buy.limit_price,
+                    options: TradeOptions {
+                        percent_of_balance: buy.percent_of_balance,
+                        ..Default::default()
+                    },
+                }];
             }
         }
 
         if let Some(sell) = &self.sell {
-            if ctx.price_lossy >= sell.at {
-                return Some(Trade::Sell {
+            if self
+                .price(ctx, Direction::Sell, sell.amount)
+                .is_some_and(|p| p >= sell.at)
+            {
+                return vec![Trade::Sell {
                     amount: sell.amount.into(),
-                });
+                    limit_price: sell.limit_price,
+                    options: TradeOptions {
+                        percent_of_balance: sell.percent_of_balance,
+                        ..Default::default()
+                    },
+                }];
             }
         }
 
-        None
+        Vec::new()
+    }
+}
+
+/// Buys more the cheaper it gets, each band's amount is bought once when the price falls below it.
+#[derive(Debug, Deserialize)]
+pub struct Laddered {
+    /// Sorted from highest to lowest `below`.
+    bands: Vec<Band>,
+
+    /// How many bands the price has been below since last rising out of them.
+    #[serde(skip)]
+    filled: usize,
+
+    /// `filled` before the most recent trade, in case it doesn't execute.
+    #[serde(skip)]
+    rollback_to: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Band {
+    below: f64,
+    amount: FractionInput,
+}
+
+impl Strategy for Laddered {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        let depth = self
+            .bands
+            .iter()
+            .take_while(|b| ctx.price_lossy < b.below)
+            .count();
+
+        let crossed = depth > self.filled;
+        self.rollback_to = crossed.then_some(self.filled);
+        self.filled = depth;
+        if !crossed {
+            return Vec::new();
+        }
+
+        vec![Trade::Buy {
+            amount: self.bands[depth - 1].amount.into(),
+            limit_price: None,
+            options: Default::default(),
+        }]
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        if let Some(filled) = self.rollback_to.take() {
+            self.filled = filled;
+        }
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!(self.filled))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        let filled = serde_json::from_value(state)?;
+        anyhow::ensure!(
+            filled <= self.bands.len(),
+            "Saved {filled} filled bands, but there are only {}",
+            self.bands.len()
+        );
+        self.filled = filled;
+        Ok(())
+    }
+}
+
+/// Sells when liquidity providers pull a large part of the pool's liquidity, a potential exit.
+#[derive(Debug, Deserialize)]
+pub struct LiquidityExit {
+    /// Fraction [0, 1] of the previous block's liquidity that must be removed to trigger.
+    min_drop: f64,
+    amount: FractionInput,
+}
+
+impl Strategy for LiquidityExit {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        let previous = ctx.liquidity as f64 - ctx.liquidity_delta as f64;
+        let removed = -(ctx.liquidity_delta as f64);
+        if previous <= 0. || removed < self.min_drop * previous {
+            return Vec::new();
+        }
+
+        log::info!("Liquidity dropped by {removed} of {previous}");
+        vec![Trade::Sell {
+            amount: self.amount.into(),
+            limit_price: None,
+            options: Default::default(),
+        }]
+    }
+}
+
+/// The most recent `period` prices, for rolling statistics.
+pub struct RollingWindow {
+    period: usize,
+    prices: VecDeque<f64>,
+}
+
+impl RollingWindow {
+    pub fn new(period: usize) -> Self {
+        RollingWindow {
+            period,
+            prices: VecDeque::with_capacity(period),
+        }
+    }
+
+    pub fn push(&mut self, price: f64) {
+        if self.prices.len() == self.period {
+            self.prices.pop_front();
+        }
+        self.prices.push_back(price);
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.prices.len() == self.period
+    }
+
+    pub fn prices(&self) -> Vec<f64> {
+        self.prices.iter().copied().collect()
+    }
+
+    /// Replaces the prices, keeping the most recent `period`.
+    pub fn restore(&mut self, prices: Vec<f64>) {
+        self.prices.clear();
+        for price in prices {
+            self.push(price);
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.prices.iter().sum::<f64>() / self.prices.len() as f64
+    }
+
+    /// Least squares slope of the prices, per block.
+    pub fn slope(&self) -> f64 {
+        let n = self.prices.len() as f64;
+        let mean_x = (n - 1.) / 2.;
+        let mean_y = self.mean();
+        let (mut covariance, mut variance) = (0., 0.);
+        for (x, y) in self.prices.iter().enumerate() {
+            let dx = x as f64 - mean_x;
+            covariance += dx * (y - mean_y);
+            variance += dx * dx;
+        }
+        if variance == 0. {
+            0.
+        } else {
+            covariance / variance
+        }
+    }
+
+    /// Population standard deviation.
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance =
+            self.prices.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / self.prices.len() as f64;
+        variance.sqrt()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PercentBConfig {
+    /// How many blocks the bands' mean and standard deviation are taken over.
+    period: usize,
+
+    /// Bands are this many standard deviations from the mean.
+    num_std: f64,
+
+    /// Buy when %B falls below this, usually ~0.1.
+    buy_below: f64,
+
+    /// Sell when %B rises above this, usually ~0.9.
+    sell_above: f64,
+
+    amount: FractionInput,
+}
+
+/// Trades on where the price sits within its Bollinger bands, as %B = (price - lower) / (upper - lower),
+/// so 0 is the lower band and 1 the upper.
+pub struct PercentB {
+    config: PercentBConfig,
+    window: RollingWindow,
+
+    /// %B at the previous block.
+    last: Option<f64>,
+
+    /// `last` before the most recent trade, in case it doesn't execute.
+    rollback_to: Option<Option<f64>>,
+}
+
+impl Strategy for PercentB {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        self.window.push(ctx.price_lossy);
+        if !self.window.is_full() {
+            return Vec::new();
+        }
+
+        let (mean, band) = (
+            self.window.mean(),
+            self.config.num_std * self.window.std_dev(),
+        );
+        if band == 0. {
+            return Vec::new();
+        }
+        let b = (ctx.price_lossy - (mean - band)) / (2. * band);
+        log::info!("%B is {b:.3}");
+
+        let previous = self.last.replace(b);
+        let crossed_below = |t: f64| b < t && !previous.is_some_and(|p| p < t);
+        let crossed_above = |t: f64| b > t && !previous.is_some_and(|p| p > t);
+
+        let trade = if crossed_below(self.config.buy_below) {
+            Trade::Buy {
+                amount: self.config.amount.into(),
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else if crossed_above(self.config.sell_above) {
+            Trade::Sell {
+                amount: self.config.amount.into(),
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else {
+            self.rollback_to = None;
+            return Vec::new();
+        };
+
+        self.rollback_to = Some(previous);
+        vec![trade]
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        if let Some(last) = self.rollback_to.take() {
+            self.last = last;
+        }
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "prices": self.window.prices(), "last": self.last }))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct State {
+            prices: Vec<f64>,
+            last: Option<f64>,
+        }
+        let state: State = serde_json::from_value(state)?;
+        self.window.restore(state.prices);
+        self.last = state.last;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RsiConfig {
+    /// How many blocks' price changes the average gain and loss are smoothed over, usually 14.
+    period: usize,
+
+    /// Buy when the RSI falls below this, usually ~30.
+    buy_below: f64,
+
+    /// Sell when the RSI rises above this, usually ~70.
+    sell_above: f64,
+
+    amount: FractionInput,
+}
+
+/// Trades momentum with Wilder's Relative Strength Index, 100 - 100 / (1 + average gain / average
+/// loss), buying when it crosses below `buy_below` (oversold) and selling when it crosses above
+/// `sell_above` (overbought).
+pub struct Rsi {
+    config: RsiConfig,
+
+    last_price: Option<f64>,
+
+    /// How many price changes have been seen, the averages are simple means until `period`.
+    changes: usize,
+    average_gain: f64,
+    average_loss: f64,
+
+    /// RSI at the previous block.
+    last: Option<f64>,
+
+    /// `last` before the most recent trade, in case it doesn't execute.
+    rollback_to: Option<Option<f64>>,
+}
+
+impl Rsi {
+    /// Updates the averages with the latest price, returning the RSI once there are `period`
+    /// changes.
+    fn update(&mut self, price: f64) -> Option<f64> {
+        let change = price - self.last_price.replace(price)?;
+        let (gain, loss) = (change.max(0.), (-change).max(0.));
+
+        let period = self.config.period as f64;
+        self.changes += 1;
+        if self.changes <= self.config.period {
+            // Seeded with the simple mean of the first `period` changes.
+            self.average_gain += gain / period;
+            self.average_loss += loss / period;
+        } else {
+            self.average_gain = (self.average_gain * (period - 1.) + gain) / period;
+            self.average_loss = (self.average_loss * (period - 1.) + loss) / period;
+        }
+        if self.changes < self.config.period {
+            return None;
+        }
+
+        Some(if self.average_loss == 0. {
+            100.
+        } else {
+            100. - 100. / (1. + self.average_gain / self.average_loss)
+        })
     }
 }
 
-/// Composable wrapper strategy that provides an exponential moving average price to the inner strategy.
-pub struct ExponentialMovingAverage {
-    // TODO(shelbyd): Make possible to provide as type parameter.
-    inner: Box<dyn Strategy>,
+impl Strategy for Rsi {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+        let Some(rsi) = self.update(ctx.price_lossy) else {
+            return Vec::new();
+        };
+        log::info!("RSI is {rsi:.2}");
+
+        let previous = self.last.replace(rsi);
+        let crossed_below = |t: f64| rsi < t && !previous.is_some_and(|p| p < t);
+        let crossed_above = |t: f64| rsi > t && !previous.is_some_and(|p| p > t);
+
+        let trade = if crossed_below(self.config.buy_below) {
+            Trade::Buy {
+                amount: self.config.amount.into(),
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else if crossed_above(self.config.sell_above) {
+            Trade::Sell {
+                amount: self.config.amount.into(),
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else {
+            self.rollback_to = None;
+            return Vec::new();
+        };
+
+        self.rollback_to = Some(previous);
+        vec![trade]
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        if let Some(last) = self.rollback_to.take() {
+            self.last = last;
+        }
+    }
 
-    /// The fraction [0, 1] to multiply the previous price by. Usually ~0.9.
-    carry: f64,
+    fn save(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(RsiState {
+            last_price: self.last_price,
+            changes: self.changes,
+            average_gain: self.average_gain,
+            average_loss: self.average_loss,
+            last: self.last,
+        })
+        .ok()
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        let state: RsiState = serde_json::from_value(state)?;
+        self.last_price = state.last_price;
+        self.changes = state.changes;
+        self.average_gain = state.average_gain;
+        self.average_loss = state.average_loss;
+        self.last = state.last;
+        Ok(())
+    }
+}
 
+#[derive(Serialize, Deserialize)]
+struct RsiState {
+    last_price: Option<f64>,
+    changes: usize,
+    average_gain: f64,
+    average_loss: f64,
     last: Option<f64>,
 }
 
-impl Strategy for ExponentialMovingAverage {
-    fn trade(&mut self, ctx: &TradeContext) -> Option<Trade> {
-        let price = self
-            .last
-            .map(|p| p * self.carry + ctx.price_lossy * (1. - self.carry))
-            .unwrap_or(ctx.price_lossy);
-        self.last = Some(price);
+/// Copies the trades of `target_address` in the traded pool, scaled by `scale`.
+#[derive(Debug, Deserialize)]
+pub struct Mirror {
+    target_address: Address,
+    scale: f64,
+}
+
+/// Resolution `Mirror::scale` is applied at.
+const MIRROR_SCALE_DENOMINATOR: u64 = 1_000_000;
+
+impl Strategy for Mirror {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        let target =
+            |s: &&PoolSwap| s.sender == self.target_address || s.recipient == self.target_address;
+        let bought = ctx
+            .swaps
+            .iter()
+            .filter(target)
+            .fold(Fraction::new(0, 1), |sum, s| {
+                Fraction::new(
+                    sum.numerator * &s.quote_bought.denominator
+                        + s.quote_bought.numerator.clone() * &sum.denominator,
+                    sum.denominator * &s.quote_bought.denominator,
+                )
+            });
+        if bought.numerator.is_zero() {
+            return Vec::new();
+        }
+
+        log::info!(
+            "Mirroring {} net bought by {}",
+            bought.to_significant(6, None).unwrap_or_default(),
+            self.target_address
+        );
+
+        let scale = (self.scale * MIRROR_SCALE_DENOMINATOR as f64).round() as u64;
+        let amount = Fraction::new(
+            bought.numerator.abs() * scale,
+            bought.denominator.clone() * MIRROR_SCALE_DENOMINATOR,
+        );
+
+        vec![if bought.numerator.is_positive() {
+            Trade::Buy {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else {
+            Trade::Sell {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        }]
+    }
+
+    fn needs_swaps(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps `target_base_pct` of the account's value in the base currency, trading back to the target
+/// when the actual percentage drifts more than `band_pct` percentage points away from it.
+#[derive(Debug, Deserialize)]
+pub struct Rebalance {
+    target_base_pct: f64,
+    band_pct: f64,
+}
+
+/// Resolution `Rebalance` trade amounts are rounded to.
+const REBALANCE_AMOUNT_DENOMINATOR: u64 = 1_000_000_000;
+
+impl Strategy for Rebalance {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        let Some(balances) = ctx.balances else {
+            log::warn!("No balances to rebalance with");
+            return Vec::new();
+        };
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        // Valued in the base currency.
+        let value = balances.base + balances.quote * ctx.price_lossy;
+        if value <= 0. {
+            return Vec::new();
+        }
+        let base_pct = balances.base / value * 100.;
+        log::info!(
+            "{base_pct:.2}% of value in base, target {}%",
+            self.target_base_pct
+        );
+        if (base_pct - self.target_base_pct).abs() <= self.band_pct {
+            return Vec::new();
+        }
+
+        // Positive when there's too much base, which is spent buying the quote currency.
+        let excess_base = balances.base - value * self.target_base_pct / 100.;
+        let quote_amount = (excess_base / ctx.price_lossy).abs();
+        let amount = Fraction::new(
+            (quote_amount * REBALANCE_AMOUNT_DENOMINATOR as f64).round() as u64,
+            REBALANCE_AMOUNT_DENOMINATOR,
+        );
+        if amount.numerator.is_zero() {
+            return Vec::new();
+        }
+
+        vec![if excess_base > 0. {
+            Trade::Buy {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else {
+            Trade::Sell {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        }]
+    }
+
+    fn needs_balances(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GridConfig {
+    lower: f64,
+    upper: f64,
+
+    /// How many equal steps [lower, upper] is split into, giving `levels + 1` grid lines.
+    levels: usize,
+    amount: FractionInput,
+}
+
+/// Buys `amount` each time the price falls through a grid line, and sells it again when the price
+/// rises through the next line up. Each line holds at most one buy, so a price hovering around a
+/// line doesn't keep trading. A jump through several lines trades once per line.
+pub struct Grid {
+    config: GridConfig,
+
+    /// Ascending.
+    lines: Vec<f64>,
+
+    /// Whether the buy at each line is still held.
+    filled: Vec<bool>,
+
+    /// How many lines were at or below the previous price.
+    cell: Option<usize>,
+
+    /// Lines changed by the trades most recently returned, with whether they were buys, in case
+    /// they don't execute.
+    pending: Vec<(usize, bool)>,
+}
+
+impl Strategy for Grid {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.pending.clear();
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
 
-        log::info!("Giving inner strategy price as {price}");
+        let cell = self
+            .lines
+            .iter()
+            .take_while(|&&l| l <= ctx.price_lossy)
+            .count();
+        let Some(previous) = self.cell.replace(cell) else {
+            return Vec::new();
+        };
+
+        let mut trades = Vec::new();
+        // Falling through lines, from the highest crossed down.
+        for line in (cell..previous).rev() {
+            if !self.filled[line] {
+                log::info!("Price fell through grid line {}", self.lines[line]);
+                self.filled[line] = true;
+                self.pending.push((line, true));
+                trades.push(Trade::Buy {
+                    amount: self.config.amount.into(),
+                    limit_price: None,
+                    options: Default::default(),
+                });
+            }
+        }
+        // Rising through lines, selling what was bought at the line below each.
+        for line in previous..cell {
+            if line > 0 && self.filled[line - 1] {
+                log::info!("Price rose through grid line {}", self.lines[line]);
+                self.filled[line - 1] = false;
+                self.pending.push((line - 1, false));
+                trades.push(Trade::Sell {
+                    amount: self.config.amount.into(),
+                    limit_price: None,
+                    options: Default::default(),
+                });
+            }
+        }
+        trades
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        let bought = matches!(trade, Trade::Buy { .. });
+        if let Some(i) = self.pending.iter().position(|&(_, b)| b == bought) {
+            let (line, _) = self.pending.remove(i);
+            self.filled[line] = !bought;
+        }
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "filled": self.filled, "cell": self.cell }))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct State {
+            filled: Vec<bool>,
+            cell: Option<usize>,
+        }
+        let state: State = serde_json::from_value(state)?;
+        anyhow::ensure!(
+            state.filled.len() == self.lines.len(),
+            "Saved {} grid lines, but there are {}",
+            state.filled.len(),
+            self.lines.len()
+        );
+        self.filled = state.filled;
+        self.cell = state.cell;
+        Ok(())
+    }
+}
+
+/// Dollar-cost averaging, buys `amount` on the first block and every `every_blocks` after,
+/// regardless of price.
+pub struct Dca {
+    amount: FractionInput,
+    every_blocks: u64,
+
+    /// How many blocks `trade` has been called for.
+    count: u64,
+}
+
+impl Strategy for Dca {
+    fn trade(&mut self, _: &TradeContext) -> Vec<Trade> {
+        let due = self.count % self.every_blocks == 0;
+        self.count += 1;
+        if !due {
+            return Vec::new();
+        }
+
+        vec![Trade::Buy {
+            amount: self.amount.into(),
+            limit_price: None,
+            options: Default::default(),
+        }]
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!(self.count))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        self.count = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
+
+/// Buys when a fast EMA of the price crosses above a slow one, and sells when it crosses below.
+/// `fast` and `slow` are carries like `ema`'s, so the fast one is the smaller.
+pub struct EmaCross {
+    fast: f64,
+    slow: f64,
+    amount: FractionInput,
+
+    /// Fast and slow averages.
+    averages: Option<(f64, f64)>,
+
+    /// Whether the fast average was above the slow one at the previous block.
+    fast_above: Option<bool>,
+
+    /// `fast_above` before the most recent trade, in case it doesn't execute.
+    rollback_to: Option<Option<bool>>,
+}
+
+impl Strategy for EmaCross {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.rollback_to = None;
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        let price = ctx.price_lossy;
+        let (fast, slow) = self
+            .averages
+            .map(|(f, s)| {
+                (
+                    f * self.fast + price * (1. - self.fast),
+                    s * self.slow + price * (1. - self.slow),
+                )
+            })
+            .unwrap_or((price, price));
+        self.averages = Some((fast, slow));
+        log::info!("Fast EMA {fast}, slow EMA {slow}");
+
+        // Equal averages, as on the first block, haven't crossed either way yet.
+        if fast == slow {
+            return Vec::new();
+        }
+        let above = fast > slow;
+        let previous = self.fast_above.replace(above);
+        if previous != Some(!above) {
+            return Vec::new();
+        }
+
+        self.rollback_to = Some(previous);
+        let amount = self.amount.into();
+        vec![if above {
+            Trade::Buy {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        } else {
+            Trade::Sell {
+                amount,
+                limit_price: None,
+                options: Default::default(),
+            }
+        }]
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        if let Some(fast_above) = self.rollback_to.take() {
+            self.fast_above = fast_above;
+        }
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "averages": self.averages, "fast_above": self.fast_above }))
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        #[derive(Deserialize)]
+        struct State {
+            averages: Option<(f64, f64)>,
+            fast_above: Option<bool>,
+        }
+        let state: State = serde_json::from_value(state)?;
+        self.averages = state.averages;
+        self.fast_above = state.fast_above;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::Address;
+    use serde_json::json;
+    use uniswap_sdk_core::prelude::Fraction;
+
+    use super::{testing::*, Balances, Config, PoolSwap, Rsi, TradeContext};
+    use crate::quote::Direction::{Buy, Sell};
+
+    #[test]
+    fn threshold_buys_at_and_below() {
+        let mut threshold =
+            strategy(json!({ "threshold": { "buy": { "at": 100.0, "amount": 1 } } }));
+        let trades = run(&mut threshold, &[101., 100.5, 100., 99.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![(Buy, 1.)], vec![(Buy, 1.)]]
+        );
+    }
+
+    #[test]
+    fn threshold_sells_at_and_above() {
+        let mut threshold =
+            strategy(json!({ "threshold": { "sell": { "at": 110.0, "amount": 2 } } }));
+        let trades = run(&mut threshold, &[109., 109.5, 110., 111.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![(Sell, 2.)], vec![(Sell, 2.)]]
+        );
+    }
+
+    #[test]
+    fn threshold_holds_between_buy_and_sell() {
+        let mut threshold = strategy(json!({ "threshold": {
+            "buy": { "at": 100.0, "amount": 1 },
+            "sell": { "at": 110.0, "amount": 1 },
+        } }));
+        let trades = run(&mut threshold, &[100., 100.01, 105., 109.99, 110.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![(Buy, 1.)], vec![], vec![], vec![], vec![(Sell, 1.)]]
+        );
+    }
+
+    #[test]
+    fn threshold_price_basis_in_a_one_percent_pool() {
+        let quoter = quoter();
+        let signals_at = |basis: &str, price: f64| {
+            let mut threshold = strategy(json!({ "threshold": {
+                "buy": { "at": 100.0, "amount": 1 },
+                "sell": { "at": 110.0, "amount": 1 },
+                "price_basis": basis,
+            } }));
+            let ctx = TradeContext {
+                fee_lossy: 0.01,
+                ..context(&quoter, 1, price)
+            };
+            signals(&threshold.trade(&ctx))
+        };
+
+        // Effective buy price is spot / 0.99, effective sell price is spot * 0.99.
+        assert_eq!(signals_at("spot", 99.5), [(Buy, 1.)]);
+        assert_eq!(signals_at("effective", 99.5), []);
+        assert_eq!(signals_at("effective", 98.), [(Buy, 1.)]);
+
+        assert_eq!(signals_at("spot", 110.5), [(Sell, 1.)]);
+        assert_eq!(signals_at("effective", 110.5), []);
+        assert_eq!(signals_at("effective", 112.), [(Sell, 1.)]);
+    }
+
+    #[test]
+    fn laddered_buys_more_in_deeper_bands() {
+        let ladder = json!({ "laddered": { "bands": [
+            { "below": 100.0, "amount": 1 },
+            { "below": 90.0, "amount": 2 },
+            { "below": 80.0, "amount": 5 },
+        ] } });
+
+        let trades = run(
+            &mut strategy(ladder.clone()),
+            &[105., 99., 95., 89., 79., 75.],
+        );
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![],
+                vec![(Buy, 1.)],
+                vec![],
+                vec![(Buy, 2.)],
+                vec![(Buy, 5.)],
+                vec![],
+            ]
+        );
+
+        // Skipping a band buys the deepest one reached.
+        let trades = run(&mut strategy(ladder), &[105., 85.]);
+        assert_eq!(signals_per_block(&trades), [vec![], vec![(Buy, 2.)]]);
+    }
+
+    #[test]
+    fn laddered_bands_survive_a_restart() {
+        let ladder = json!({ "laddered": { "bands": [
+            { "below": 100.0, "amount": 1 },
+            { "below": 90.0, "amount": 2 },
+        ] } });
+        let mut before = strategy(ladder.clone());
+        run(&mut before, &[95.]);
+
+        // The band already bought isn't bought again.
+        let mut after = strategy(ladder);
+        after.load(before.save().unwrap()).unwrap();
+        assert_eq!(
+            signals_per_block(&run(&mut after, &[95., 85.])),
+            [vec![], vec![(Buy, 2.)]]
+        );
+
+        let mut fewer =
+            strategy(json!({ "laddered": { "bands": [{ "below": 100.0, "amount": 1 }] } }));
+        assert!(fewer.load(json!(2)).is_err());
+    }
+
+    #[test]
+    fn liquidity_exit_sells_on_a_large_drop() {
+        let mut exit = strategy(json!({ "liquidity_exit": { "min_drop": 0.3, "amount": 1 } }));
+        let quoter = quoter();
+        let mut signals_at = |liquidity: u128, liquidity_delta: i128| {
+            let ctx = TradeContext {
+                liquidity,
+                liquidity_delta,
+                ..context(&quoter, 1, 100.)
+            };
+            signals(&exit.trade(&ctx))
+        };
+
+        // The first block reports no change.
+        assert_eq!(signals_at(1_000, 0), []);
+        assert_eq!(signals_at(1_500, 500), []);
+        // 400 of 1400 removed is under 30%.
+        assert_eq!(signals_at(1_000, -400), []);
+        // 500 of 1500 removed is over 30%.
+        assert_eq!(signals_at(1_000, -500), [(Sell, 1.)]);
+    }
+
+    #[test]
+    fn failed_grid_buy_rolls_back_its_level() {
+        let grid = json!({ "grid": { "lower": 90.0, "upper": 110.0, "levels": 2, "amount": 1 } });
+
+        // Filled, the line at 100 holds its buy until the price rises through 110.
+        let mut filled = strategy(grid.clone());
+        let trades = run(&mut filled, &[105., 95., 105., 95., 111.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![(Buy, 1.)], vec![], vec![], vec![(Sell, 1.)]]
+        );
+
+        // Failed, the line at 100 is free to buy again and there's nothing to sell.
+        let mut failed = strategy(grid);
+        let trades = run(&mut failed, &[105., 95.]);
+        failed.on_execution_failed(&trades[1][0]);
+        let trades = run(&mut failed, &[105., 95., 111.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![(Buy, 1.)], vec![(Sell, 1.)]]
+        );
+    }
+
+    #[test]
+    fn percent_b_trades_at_both_extremes() {
+        let mut percent_b = strategy(json!({ "percent_b": {
+            "period": 3,
+            "num_std": 1.0,
+            "buy_below": 0.1,
+            "sell_above": 0.9,
+            "amount": 1,
+        } }));
+        // Below the lower band, back inside, above the upper band, back inside, then no bands.
+        let trades = run(&mut percent_b, &[100., 100., 80., 80., 120., 120., 120.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![],
+                vec![],
+                vec![(Buy, 1.)],
+                vec![],
+                vec![(Sell, 1.)],
+                vec![],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn mirror_buys_a_scaled_copy_of_the_target() {
+        let (target, other) = (Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let mut mirror = strategy(json!({ "mirror": { "target_address": target, "scale": 0.5 } }));
+        let quoter = quoter();
+        let swap = |sender, quote_bought: i64| PoolSwap {
+            sender,
+            recipient: sender,
+            quote_bought: Fraction::new(quote_bought, 1),
+        };
+        let mut signals_for = |swaps| {
+            let ctx = TradeContext {
+                swaps,
+                ..context(&quoter, 1, 100.)
+            };
+            signals(&mirror.trade(&ctx))
+        };
+
+        assert_eq!(signals_for(vec![]), []);
+        assert_eq!(signals_for(vec![swap(other, 4)]), []);
+        assert_eq!(
+            signals_for(vec![swap(target, 2), swap(other, -6)]),
+            [(Buy, 1.)]
+        );
+        assert_eq!(signals_for(vec![swap(target, -4)]), [(Sell, 2.)]);
+    }
+
+    #[test]
+    fn mirror_scale_must_be_positive() {
+        for scale in [0., -1.] {
+            let config = json!({ "mirror": { "target_address": Address::ZERO, "scale": scale } });
+            let config = serde_json::from_value::<Config>(config).unwrap();
+            assert!(config.into_dyn().is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_prices_are_skipped_without_corrupting_state() {
+        let configs = [
+            json!({ "percent_b": {
+                "period": 3,
+                "num_std": 1.0,
+                "buy_below": 0.1,
+                "sell_above": 0.9,
+                "amount": 1,
+            } }),
+            json!({ "rsi": { "period": 2, "buy_below": 30.0, "sell_above": 70.0, "amount": 1 } }),
+            json!({ "grid": { "lower": 90.0, "upper": 110.0, "levels": 2, "amount": 1 } }),
+            json!({ "ema_cross": { "fast": 0.5, "slow": 0.9, "amount": 1 } }),
+            json!({ "ema": { "carry": 0.5, "inner": { "threshold": {
+                "buy": { "at": 90.0, "amount": 1 },
+                "sell": { "at": 110.0, "amount": 1 },
+            } } } }),
+            json!({ "sma": { "window": 2, "inner": { "threshold": {
+                "buy": { "at": 90.0, "amount": 1 },
+                "sell": { "at": 110.0, "amount": 1 },
+            } } } }),
+        ];
+        let clean = [100., 100., 80., 80., 120., 120., 95., 85., 130.];
+        let bad = [f64::NAN, 0., f64::INFINITY, -5.];
+        // A bad price after every clean one.
+        let dirty: Vec<_> = clean
+            .iter()
+            .zip(bad.iter().cycle())
+            .flat_map(|(&price, &bad)| [price, bad])
+            .collect();
+
+        for config in configs {
+            let expected = signals_per_block(&run(&mut strategy(config.clone()), &clean));
+            let trades = run(&mut strategy(config.clone()), &dirty);
+            let (kept, skipped): (Vec<_>, Vec<_>) = trades
+                .chunks(2)
+                .map(|pair| (pair[0].clone(), pair[1].clone()))
+                .unzip();
+            assert!(skipped.iter().all(Vec::is_empty), "{config}");
+            assert_eq!(signals_per_block(&kept), expected, "{config}");
+        }
+    }
+
+    #[test]
+    fn rebalance_trades_back_to_the_target_beyond_the_band() {
+        let mut rebalance =
+            strategy(json!({ "rebalance": { "target_base_pct": 50.0, "band_pct": 5.0 } }));
+        let quoter = quoter();
+        let mut signals_with = |base, quote| {
+            let ctx = TradeContext {
+                balances: Some(Balances { base, quote }),
+                ..context(&quoter, 1, 2000.)
+            };
+            signals(&rebalance.trade(&ctx))
+        };
+
+        // 52.6% in base is within the band.
+        assert_eq!(signals_with(1000., 0.45), []);
+        // 62.5% in base, buying 0.1 puts 800 of 1600 in each.
+        let trades = signals_with(1000., 0.3);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].0, Buy);
+        assert_close(trades[0].1, 0.1);
+        // 16.7% in base, selling 0.2 puts 600 of 1200 in each.
+        let trades = signals_with(200., 0.5);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].0, Sell);
+        assert_close(trades[0].1, 0.2);
+
+        let without_balances = rebalance.trade(&context(&quoter, 1, 2000.));
+        assert!(without_balances.is_empty());
+    }
+
+    #[test]
+    fn rsi_matches_wilders_smoothing() {
+        let config = json!({ "period": 3, "buy_below": 50.0, "sell_above": 85.0, "amount": 1 });
+        let mut rsi = Rsi {
+            config: serde_json::from_value(config.clone()).unwrap(),
+            last_price: None,
+            changes: 0,
+            average_gain: 0.,
+            average_loss: 0.,
+            last: None,
+            rollback_to: None,
+        };
+        let prices = [44., 46., 45., 47., 49., 48., 46.];
+
+        // Changes +2 -1 +2 seed gain 4/3 and loss 1/3, then +2 -1 -2 are smoothed in as
+        // (average * 2 + change) / 3.
+        let expected = [80., 87.5, 100. - 100. / (1. + 28. / 13.), 100. - 100. / 1.7];
+        let actual: Vec<_> = prices.iter().filter_map(|&p| rsi.update(p)).collect();
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.into_iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+        }
+
+        // Nothing until `period` changes, then crossing above 85 and below 50.
+        let trades = run(&mut strategy(json!({ "rsi": config })), &prices);
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+                vec![(Sell, 1.)],
+                vec![],
+                vec![(Buy, 1.)],
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_walks_up_and_down_through_its_lines() {
+        let mut grid = strategy(json!({ "grid": {
+            "lower": 90.0,
+            "upper": 110.0,
+            "levels": 4,
+            "amount": 1,
+        } }));
+        // Lines at 90, 95, 100, 105, and 110.
+        let trades = run(
+            &mut grid,
+            &[107., 103., 97., 98., 96., 101., 106., 89., 111.],
+        );
+        assert_eq!(
+            signals_per_block(&trades),
+            [
+                vec![],
+                vec![(Buy, 1.)],
+                vec![(Buy, 1.)],
+                // Hovering around a line doesn't trade again.
+                vec![],
+                vec![],
+                // Rising through 100 would sell the buy at 95, which there isn't.
+                vec![],
+                vec![(Sell, 1.)],
+                // The buy at 105 is still held, so falling through it doesn't buy again.
+                vec![(Buy, 1.), (Buy, 1.), (Buy, 1.)],
+                vec![(Sell, 1.), (Sell, 1.), (Sell, 1.), (Sell, 1.)],
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_fills_survive_a_restart() {
+        let grid = json!({ "grid": { "lower": 90.0, "upper": 110.0, "levels": 2, "amount": 1 } });
+        let mut before = strategy(grid.clone());
+        run(&mut before, &[105., 95.]);
+
+        // Falling through 100 again doesn't buy, rising through 110 sells what was bought.
+        let mut after = strategy(grid);
+        after.load(before.save().unwrap()).unwrap();
+        assert_eq!(
+            signals_per_block(&run(&mut after, &[105., 95., 111.])),
+            [vec![], vec![], vec![(Sell, 1.)]]
+        );
+    }
+
+    #[test]
+    fn dca_schedule_survives_a_restart() {
+        let dca = json!({ "dca": { "amount": 1, "every_blocks": 3 } });
+        let mut before = strategy(dca.clone());
+        run(&mut before, &[100.; 2]);
+
+        let mut after = strategy(dca);
+        after.load(before.save().unwrap()).unwrap();
+        assert_eq!(
+            signals_per_block(&run(&mut after, &[100.; 2])),
+            [vec![], vec![(Buy, 1.)]]
+        );
+    }
+
+    #[test]
+    fn dca_buys_every_few_calls() {
+        let mut dca = strategy(json!({ "dca": { "amount": 1, "every_blocks": 3 } }));
+        let trades = run(&mut dca, &[100.; 10]);
+        let calls_with_buys: Vec<_> = signals_per_block(&trades)
+            .iter()
+            .enumerate()
+            .filter(|(_, signals)| *signals == &[(Buy, 1.)])
+            .map(|(i, _)| i + 1)
+            .collect();
+        assert_eq!(calls_with_buys, [1, 4, 7, 10]);
+        assert_eq!(trades.iter().flatten().count(), 4);
+
+        let never = json!({ "dca": { "amount": 1, "every_blocks": 0 } });
+        let never = serde_json::from_value::<Config>(never).unwrap();
+        assert!(never.into_dyn().is_err());
+    }
 
-        self.inner.trade(&TradeContext { price_lossy: price })
+    #[test]
+    fn ema_cross_buys_then_sells_on_a_rise_and_fall() {
+        let mut cross = strategy(json!({ "ema_cross": { "fast": 0.5, "slow": 0.9, "amount": 1 } }));
+        // The dip sets the fast EMA below the slow one, so the rise is a cross.
+        let prices = [100., 98., 96., 100., 104., 108., 104., 100., 96., 92.];
+        let trades = run(&mut cross, &prices);
+        let crossings: Vec<_> = signals_per_block(&trades)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, signals)| !signals.is_empty())
+            .collect();
+        assert_eq!(crossings, [(4, vec![(Buy, 1.)]), (8, vec![(Sell, 1.)])]);
     }
 }
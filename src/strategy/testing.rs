@@ -0,0 +1,164 @@
+//! Drives strategies through a series of prices without a chain, so they can be unit tested.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use alloy::{
+    primitives::address,
+    providers::{Provider, ProviderBuilder},
+};
+use num_traits::ToPrimitive;
+use uniswap_sdk_core::{prelude::*, token};
+use uniswap_v3_sdk::prelude::FeeAmount;
+
+use super::{Config, Strategy, Trade, TradeContext};
+use crate::{
+    execution::PrecisionLoss,
+    quote::{Direction, Quoter},
+    rpc_budget::RpcBudget,
+};
+
+pub fn weth() -> Currency {
+    Currency::Token(token!(
+        1,
+        address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+        18,
+        "WETH"
+    ))
+}
+
+pub fn usdc() -> Currency {
+    Currency::Token(token!(
+        1,
+        address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48"),
+        6,
+        "USDC"
+    ))
+}
+
+/// A quoter with no RPC budget, so any quote fails without touching the network.
+pub fn quoter() -> Arc<Quoter> {
+    let provider = ProviderBuilder::new().on_http("http://127.0.0.1:1".parse().unwrap());
+    Arc::new(Quoter::new(
+        provider.root().clone(),
+        Address::ZERO,
+        usdc(),
+        weth(),
+        FeeAmount::MEDIUM,
+        0,
+        Arc::new(RpcBudget::new(Some(0))),
+        PrecisionLoss::Warn,
+    ))
+}
+
+/// Context of a block at `price` in a 0.3% pool, with no pool data, swaps, or balances.
+pub fn context(quoter: &Arc<Quoter>, block: u64, price: f64) -> TradeContext {
+    TradeContext {
+        block_number: block,
+        price_lossy: price,
+        fee_lossy: 0.003,
+        liquidity: 0,
+        liquidity_delta: 0,
+        quoter: quoter.clone(),
+        pool: None,
+        swaps: Vec::new(),
+        balances: None,
+    }
+}
+
+/// Builds the strategy configured by `config`, as it would be read from the config file.
+pub fn strategy(config: serde_json::Value) -> Box<dyn Strategy> {
+    serde_json::from_value::<Config>(config)
+        .unwrap()
+        .into_dyn()
+        .unwrap()
+}
+
+/// Feeds `prices` to `strategy` on consecutive blocks from 1, returning each block's trades.
+pub fn run(strategy: &mut dyn Strategy, prices: &[f64]) -> Vec<Vec<Trade>> {
+    let quoter = quoter();
+    prices
+        .iter()
+        .zip(1..)
+        .map(|(&price, block)| strategy.trade(&context(&quoter, block, price)))
+        .collect()
+}
+
+/// Side and amount of each trade, for comparing against expected trades.
+pub fn signals(trades: &[Trade]) -> Vec<(Direction, f64)> {
+    trades
+        .iter()
+        .map(|t| (t.direction(), amount_lossy(t.amount())))
+        .collect()
+}
+
+/// `signals` of each block.
+pub fn signals_per_block(blocks: &[Vec<Trade>]) -> Vec<Vec<(Direction, f64)>> {
+    blocks.iter().map(|trades| signals(trades)).collect()
+}
+
+pub fn amount_lossy(amount: &Fraction) -> f64 {
+    amount.numerator.to_f64().unwrap() / amount.denominator.to_f64().unwrap()
+}
+
+/// Never trades, remembering each price it was given.
+#[derive(Default)]
+pub struct Recorder {
+    pub prices: Vec<f64>,
+}
+
+impl Strategy for Recorder {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.prices.push(ctx.price_lossy);
+        Vec::new()
+    }
+}
+
+/// Returns the trades scripted for each call in turn, then nothing, and counts how many of its
+/// trades were reported as not executed.
+#[derive(Default)]
+pub struct Scripted {
+    pub script: VecDeque<Vec<Trade>>,
+    pub failed: usize,
+}
+
+impl Scripted {
+    pub fn new(script: Vec<Vec<Trade>>) -> Self {
+        Scripted {
+            script: script.into(),
+            failed: 0,
+        }
+    }
+}
+
+impl Strategy for Scripted {
+    fn trade(&mut self, _: &TradeContext) -> Vec<Trade> {
+        self.script.pop_front().unwrap_or_default()
+    }
+
+    fn on_execution_failed(&mut self, _: &Trade) {
+        self.failed += 1;
+    }
+}
+
+pub fn buy(amount: u64) -> Trade {
+    Trade::Buy {
+        amount: Fraction::new(amount, 1),
+        limit_price: None,
+        options: Default::default(),
+    }
+}
+
+pub fn sell(amount: u64) -> Trade {
+    Trade::Sell {
+        amount: Fraction::new(amount, 1),
+        limit_price: None,
+        options: Default::default(),
+    }
+}
+
+pub fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() <= 1e-9 * expected.abs().max(1.),
+        "{actual} is not close to {expected}"
+    );
+}
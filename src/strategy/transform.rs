@@ -0,0 +1,473 @@
+//! Strategies wrapping a single inner strategy, transforming the context it sees or gating the
+//! trades it makes.
+
+use serde::Deserialize;
+
+use super::{Config, RollingWindow, Strategy, Trade, TradeContext, Wrapped};
+use crate::quote::Direction;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    Ema { carry: f64, inner: Box<Config> },
+    Sma { window: usize, inner: Box<Config> },
+    Confirm { blocks: usize, inner: Box<Config> },
+    Cooldown { blocks: u64, inner: Box<Config> },
+}
+
+impl Transform {
+    pub fn into_dyn(self) -> anyhow::Result<Box<dyn Strategy>> {
+        Ok(match self {
+            Transform::Ema { carry, inner } => {
+                anyhow::ensure!(
+                    (0. ..=1.).contains(&carry),
+                    "EMA carry must be between 0 and 1"
+                );
+                Box::new(ExponentialMovingAverage::new(carry, inner.into_dyn()?))
+            }
+            Transform::Sma { window, inner } => {
+                anyhow::ensure!(window > 0, "SMA window must be above 0");
+                Box::new(SimpleMovingAverage {
+                    inner: inner.into_dyn()?,
+                    window: RollingWindow::new(window),
+                })
+            }
+            Transform::Confirm { blocks, inner } => Box::new(Confirm {
+                blocks,
+                inner: inner.into_dyn()?,
+                streak: None,
+            }),
+            Transform::Cooldown { blocks, inner } => Box::new(Cooldown {
+                blocks,
+                inner: inner.into_dyn()?,
+                remaining: 0,
+                started_by: Vec::new(),
+            }),
+        })
+    }
+}
+
+/// Composable wrapper strategy that provides an exponential moving average price to the inner strategy.
+pub struct ExponentialMovingAverage<S> {
+    inner: S,
+
+    /// The fraction [0, 1] to multiply the previous price by. Usually ~0.9.
+    carry: f64,
+
+    last: Option<f64>,
+}
+
+impl<S: Strategy> ExponentialMovingAverage<S> {
+    pub fn new(carry: f64, inner: S) -> Self {
+        ExponentialMovingAverage {
+            inner,
+            carry,
+            last: None,
+        }
+    }
+}
+
+impl<S: Strategy> Strategy for ExponentialMovingAverage<S> {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        let price = self
+            .last
+            .map(|p| p * self.carry + ctx.price_lossy * (1. - self.carry))
+            .unwrap_or(ctx.price_lossy);
+        self.last = Some(price);
+
+        log::info!("Giving inner strategy price as {price}");
+
+        self.inner.trade(&TradeContext {
+            price_lossy: price,
+            ..ctx.clone()
+        })
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        self.inner.on_execution_failed(trade);
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.inner.cancellations()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.inner.needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.inner.needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Wrapped::save(self.last, &self.inner)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        self.last = Wrapped::load(state, &mut self.inner)?;
+        Ok(())
+    }
+}
+
+/// Composable wrapper strategy that provides the mean of the last `window` prices to the inner
+/// strategy, or of every price so far until there are that many.
+pub struct SimpleMovingAverage {
+    inner: Box<dyn Strategy>,
+    window: RollingWindow,
+}
+
+impl Strategy for SimpleMovingAverage {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        if !ctx.has_valid_price() {
+            return Vec::new();
+        }
+
+        self.window.push(ctx.price_lossy);
+        let price = self.window.mean();
+
+        log::info!("Giving inner strategy price as {price}");
+
+        self.inner.trade(&TradeContext {
+            price_lossy: price,
+            ..ctx.clone()
+        })
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        self.inner.on_execution_failed(trade);
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.inner.cancellations()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.inner.needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.inner.needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Wrapped::save(self.window.prices(), &*self.inner)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        let prices = Wrapped::load(state, &mut *self.inner)?;
+        self.window.restore(prices);
+        Ok(())
+    }
+}
+
+/// Composable wrapper strategy that only forwards the inner strategy's trades once it has signalled
+/// the same direction for `blocks` consecutive blocks, filtering out one block blips.
+pub struct Confirm {
+    inner: Box<dyn Strategy>,
+    blocks: usize,
+
+    /// Direction the inner strategy has signalled, and for how many consecutive blocks.
+    streak: Option<(Direction, usize)>,
+}
+
+impl Strategy for Confirm {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        let trades = self.inner.trade(ctx);
+
+        // A signal is any number of trades in the same direction.
+        let signal = trades
+            .first()
+            .map(Trade::direction)
+            .filter(|first| trades.iter().all(|t| t.direction() == *first));
+        let Some(signal) = signal else {
+            if !trades.is_empty() {
+                log::info!("Mixed signal from {} trades, not confirming", trades.len());
+            }
+            self.streak = None;
+            for trade in &trades {
+                self.inner.on_execution_failed(trade);
+            }
+            return Vec::new();
+        };
+
+        let count = match self.streak {
+            Some((direction, count)) if direction == signal => count + 1,
+            _ => 1,
+        };
+        self.streak = Some((signal, count));
+
+        if count < self.blocks {
+            log::info!(
+                "{signal:?} signal for {count} of {} blocks, not yet confirmed",
+                self.blocks
+            );
+            for trade in &trades {
+                self.inner.on_execution_failed(trade);
+            }
+            return Vec::new();
+        }
+
+        trades
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        self.inner.on_execution_failed(trade);
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.inner.cancellations()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.inner.needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.inner.needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Wrapped::save(self.streak, &*self.inner)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        self.streak = Wrapped::load(state, &mut *self.inner)?;
+        Ok(())
+    }
+}
+
+/// Composable wrapper strategy that suppresses the inner strategy's trades for `blocks` blocks after
+/// each block it trades on. The inner strategy still sees every block.
+pub struct Cooldown {
+    inner: Box<dyn Strategy>,
+    blocks: u64,
+
+    /// Blocks left to suppress trades for.
+    remaining: u64,
+
+    /// Directions of the trades that started the current cooldown, in case none of them execute.
+    started_by: Vec<Direction>,
+}
+
+impl Strategy for Cooldown {
+    fn trade(&mut self, ctx: &TradeContext) -> Vec<Trade> {
+        self.started_by.clear();
+        let trades = self.inner.trade(ctx);
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            if !trades.is_empty() {
+                log::info!(
+                    "Cooling down for {} more blocks, suppressing {} trades",
+                    self.remaining,
+                    trades.len()
+                );
+            }
+            for trade in &trades {
+                self.inner.on_execution_failed(trade);
+            }
+            return Vec::new();
+        }
+
+        if !trades.is_empty() {
+            self.remaining = self.blocks;
+            self.started_by = trades.iter().map(Trade::direction).collect();
+        }
+        trades
+    }
+
+    fn on_execution_failed(&mut self, trade: &Trade) {
+        let direction = trade.direction();
+        if let Some(i) = self.started_by.iter().position(|&d| d == direction) {
+            self.started_by.remove(i);
+            // Nothing that started the cooldown traded, so there's nothing to cool down from.
+            if self.started_by.is_empty() {
+                self.remaining = 0;
+            }
+        }
+        self.inner.on_execution_failed(trade);
+    }
+
+    fn cancellations(&mut self) -> Vec<u64> {
+        self.inner.cancellations()
+    }
+
+    fn needs_swaps(&self) -> bool {
+        self.inner.needs_swaps()
+    }
+
+    fn needs_balances(&self) -> bool {
+        self.inner.needs_balances()
+    }
+
+    fn save(&self) -> Option<serde_json::Value> {
+        Wrapped::save(self.remaining, &*self.inner)
+    }
+
+    fn load(&mut self, state: serde_json::Value) -> anyhow::Result<()> {
+        self.remaining = Wrapped::load(state, &mut *self.inner)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        quote::Direction::Buy,
+        strategy::testing::{self, *},
+    };
+
+    #[test]
+    fn ema_starts_at_the_first_price() {
+        let mut ema = ExponentialMovingAverage::new(0.9, Recorder::default());
+        run(&mut ema, &[42.]);
+        assert_eq!(ema.inner.prices, [42.]);
+    }
+
+    #[test]
+    fn ema_follows_the_recurrence() {
+        // Each average is carry * previous + (1 - carry) * price.
+        let mut ema = ExponentialMovingAverage::new(0.5, Recorder::default());
+        run(&mut ema, &[10., 20., 20., 0.]);
+        assert_eq!(ema.inner.prices, [10., 15., 17.5, 8.75]);
+
+        let mut ema = ExponentialMovingAverage::new(0.9, Recorder::default());
+        run(&mut ema, &[100., 110., 90.]);
+        let expected = [100., 101., 99.9];
+        assert_eq!(ema.inner.prices.len(), expected.len());
+        for (&actual, expected) in ema.inner.prices.iter().zip(expected) {
+            assert_close(actual, expected);
+        }
+    }
+
+    #[test]
+    fn ema_carry_bounds() {
+        let mut ema = ExponentialMovingAverage::new(0., Recorder::default());
+        run(&mut ema, &[1., 2., 3.]);
+        assert_eq!(ema.inner.prices, [1., 2., 3.]);
+
+        let mut ema = ExponentialMovingAverage::new(1., Recorder::default());
+        run(&mut ema, &[1., 2., 3.]);
+        assert_eq!(ema.inner.prices, [1., 1., 1.]);
+    }
+
+    #[test]
+    fn ema_delays_a_threshold() {
+        let mut ema = testing::strategy(json!({ "ema": {
+            "carry": 0.5,
+            "inner": { "threshold": { "buy": { "at": 100.0, "amount": 1 } } },
+        } }));
+        // Averages 120, 105, 100.
+        let trades = run(&mut ema, &[120., 90., 95.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![(Buy, 1.)]]
+        );
+    }
+
+    fn confirm(blocks: usize, script: Vec<Vec<Trade>>) -> Confirm {
+        Confirm {
+            inner: Box::new(Scripted::new(script)),
+            blocks,
+            streak: None,
+        }
+    }
+
+    #[test]
+    fn confirm_filters_a_one_block_blip() {
+        let mut confirm = testing::strategy(json!({ "confirm": {
+            "blocks": 2,
+            "inner": { "threshold": { "buy": { "at": 100.0, "amount": 1 } } },
+        } }));
+        let trades = run(&mut *confirm, &[90., 110., 90., 90.]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![], vec![(Buy, 1.)]]
+        );
+    }
+
+    #[test]
+    fn confirm_forwards_a_sustained_signal() {
+        let mut confirm = confirm(3, vec![vec![buy(1)]; 4]);
+        let trades = run(&mut confirm, &[1.; 4]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![], vec![], vec![(Buy, 1.)], vec![(Buy, 1.)]]
+        );
+    }
+
+    #[test]
+    fn confirm_streak_survives_a_restart() {
+        let mut before = confirm(2, vec![vec![buy(1)]]);
+        run(&mut before, &[1.]);
+
+        let mut after = confirm(2, vec![vec![buy(1)]]);
+        after.load(before.save().unwrap()).unwrap();
+        assert_eq!(
+            signals_per_block(&run(&mut after, &[1.])),
+            [vec![(Buy, 1.)]]
+        );
+    }
+
+    fn cooldown(blocks: u64, script: Vec<Vec<Trade>>) -> Cooldown {
+        Cooldown {
+            inner: Box::new(Scripted::new(script)),
+            blocks,
+            remaining: 0,
+            started_by: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cooldown_suppresses_the_blocks_after_a_trade() {
+        let mut cooldown = testing::strategy(json!({ "cooldown": {
+            "blocks": 2,
+            "inner": { "always_buy": 1 },
+        } }));
+        let trades = run(&mut cooldown, &[100.; 5]);
+        assert_eq!(
+            signals_per_block(&trades),
+            [vec![(Buy, 1.)], vec![], vec![], vec![(Buy, 1.)], vec![]]
+        );
+    }
+
+    #[test]
+    fn cooldown_ends_only_when_what_started_it_failed() {
+        // Failing the trade that started it trades again on the next block.
+        let mut failed = cooldown(2, vec![vec![buy(1)], vec![buy(2)]]);
+        let trades = run(&mut failed, &[100.]);
+        failed.on_execution_failed(&trades[0][0]);
+        assert_eq!(signals(&run(&mut failed, &[100.])[0]), [(Buy, 2.)]);
+
+        // One of two trades executing keeps it cooling down.
+        let mut partial = cooldown(2, vec![vec![buy(1), sell(1)], vec![buy(2)]]);
+        let trades = run(&mut partial, &[100.]);
+        partial.on_execution_failed(&trades[0][1]);
+        assert_eq!(signals(&run(&mut partial, &[100.])[0]), []);
+
+        // A failure reported after the block that started it doesn't end it either.
+        let mut late = cooldown(2, vec![vec![buy(1)], vec![], vec![buy(2)]]);
+        let trades = run(&mut late, &[100., 100.]);
+        late.on_execution_failed(&trades[0][0]);
+        assert_eq!(signals(&run(&mut late, &[100.])[0]), []);
+    }
+
+    #[test]
+    fn cooldown_survives_a_restart() {
+        let mut before = cooldown(2, vec![vec![buy(1)]]);
+        run(&mut before, &[100.]);
+
+        let mut after = cooldown(2, vec![vec![buy(2)], vec![buy(3)], vec![buy(4)]]);
+        after.load(before.save().unwrap()).unwrap();
+        assert_eq!(
+            signals_per_block(&run(&mut after, &[100.; 3])),
+            [vec![], vec![], vec![(Buy, 4.)]]
+        );
+    }
+}
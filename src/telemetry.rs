@@ -0,0 +1,92 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, Layer};
+
+/// Exports `tracing` spans to the OTLP collector at `endpoint`, logging is unaffected.
+pub fn init(endpoint: &str) -> anyhow::Result<SdkTracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer(&provider)))?;
+
+    Ok(provider)
+}
+
+fn layer<S>(provider: &SdkTracerProvider) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+}
+
+/// Span of one block's pipeline, with the strategy's `decision` recorded once it's made.
+pub fn block_span(block: u64, pair: &str) -> tracing::Span {
+    tracing::info_span!(
+        "block",
+        block,
+        pair = %pair,
+        decision = tracing::field::Empty
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::Value;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+
+    use super::*;
+
+    fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| &kv.value)
+    }
+
+    #[test]
+    fn a_blocks_pipeline_is_exported_as_spans() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry().with(layer(&provider));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let block = block_span(42, "WETH/USDC");
+            for stage in ["pool", "strategy", "execute"] {
+                let _stage = tracing::info_span!(parent: &block, "stage", stage).entered();
+            }
+            block.record("decision", "buy 1 WETH");
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let block = spans.iter().find(|s| s.name == "block").unwrap();
+        assert_eq!(attribute(block, "block"), Some(&Value::I64(42)));
+        assert_eq!(attribute(block, "pair"), Some(&Value::from("WETH/USDC")));
+        assert_eq!(
+            attribute(block, "decision"),
+            Some(&Value::from("buy 1 WETH"))
+        );
+
+        let stages = spans
+            .iter()
+            .filter(|s| s.name == "stage")
+            .collect::<Vec<_>>();
+        assert_eq!(stages.len(), 3);
+        for stage in stages {
+            assert_eq!(stage.parent_span_id, block.span_context.span_id());
+        }
+    }
+}
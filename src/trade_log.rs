@@ -0,0 +1,114 @@
+//! Durable record of executed trades, one line per trade.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use alloy::primitives::TxHash;
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{execution::Execution, strategy};
+
+#[derive(Serialize)]
+pub struct Record {
+    pub block: u64,
+    pub side: &'static str,
+
+    /// Quote currency amount of the trade, in human units.
+    pub amount: String,
+
+    /// Pool price observed on the block the trade was decided on.
+    pub price_lossy: f64,
+
+    pub tx_hash: TxHash,
+
+    pub base_before: f64,
+    pub quote_before: f64,
+    pub base_after: f64,
+    pub quote_after: f64,
+}
+
+impl Record {
+    pub fn new(block: u64, price_lossy: f64, execution: &Execution) -> anyhow::Result<Self> {
+        let trade = &execution.trade;
+        Ok(Record {
+            block,
+            side: match trade {
+                strategy::Trade::Buy { .. } => "buy",
+                strategy::Trade::Sell { .. } => "sell",
+            },
+            amount: trade.amount().to_significant(18, None)?,
+            price_lossy,
+            tx_hash: execution.tx_hash,
+            base_before: execution.balances_before.base,
+            quote_before: execution.balances_before.quote,
+            base_after: execution.balances_after.base,
+            quote_after: execution.balances_after.quote,
+        })
+    }
+
+    const CSV_HEADER: &'static str =
+        "block,side,amount,price_lossy,tx_hash,base_before,quote_before,base_after,quote_after";
+
+    fn csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.block,
+            self.side,
+            self.amount,
+            self.price_lossy,
+            self.tx_hash,
+            self.base_before,
+            self.quote_before,
+            self.base_after,
+            self.quote_after,
+        )
+    }
+}
+
+enum Format {
+    Jsonl,
+    Csv,
+}
+
+pub struct TradeLog {
+    file: File,
+    format: Format,
+}
+
+impl TradeLog {
+    /// Appends to `path`, formatted by its extension: `.csv`, or `.jsonl`.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("jsonl") => Format::Jsonl,
+            _ => anyhow::bail!("Trade log {path:?} must end in .csv or .jsonl"),
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Opening trade log {path:?}"))?;
+
+        if let Format::Csv = format {
+            if file.metadata()?.len() == 0 {
+                writeln!(file, "{}", Record::CSV_HEADER)?;
+            }
+        }
+        Ok(TradeLog { file, format })
+    }
+
+    /// Writes a record, flushed so it survives the bot dying right after.
+    pub fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        let line = match self.format {
+            Format::Jsonl => serde_json::to_string(record)?,
+            Format::Csv => record.csv(),
+        };
+        writeln!(self.file, "{line}")?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+//! Executing as ERC-4337 (v0.6) UserOperations, so gas can be paid by a paymaster.
+
+use std::time::Duration;
+
+use alloy::{
+    consensus::ReceiptEnvelope,
+    primitives::{keccak256, Address, Bytes, B256, U256},
+    providers::{Provider, ProviderBuilder},
+    rpc::types::{Log, TransactionReceipt},
+    signers::{local::PrivateKeySigner, SignerSync},
+    sol_types::{SolCall, SolValue},
+};
+use anyhow::Context;
+
+use crate::execution;
+
+alloy::sol! {
+    #[sol(rpc)]
+    interface EntryPoint {
+        struct UserOperation {
+            address sender;
+            uint256 nonce;
+            bytes initCode;
+            bytes callData;
+            uint256 callGasLimit;
+            uint256 verificationGasLimit;
+            uint256 preVerificationGas;
+            uint256 maxFeePerGas;
+            uint256 maxPriorityFeePerGas;
+            bytes paymasterAndData;
+            bytes signature;
+        }
+
+        event UserOperationEvent(
+            bytes32 indexed userOpHash,
+            address indexed sender,
+            address indexed paymaster,
+            uint256 nonce,
+            bool success,
+            uint256 actualGasCost,
+            uint256 actualGasUsed
+        );
+
+        function getNonce(address sender, uint192 key) returns (uint256 nonce);
+        function handleOps(UserOperation[] ops, address beneficiary);
+    }
+
+    interface SimpleAccount {
+        function execute(address dest, uint256 value, bytes func);
+        function executeBatch(address[] dest, bytes[] func);
+    }
+}
+
+/// Placeholder signature with the right shape, used only while estimating gas.
+const DUMMY_SIGNATURE: &str = "0xfffffffffffffffffffffffffffffff0000000000000000000000000000000007aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa1c";
+
+/// Gas limits of UserOperations on a fork, where there's no bundler to estimate them.
+const FORK_CALL_GAS_LIMIT: u64 = 1_000_000;
+const FORK_VERIFICATION_GAS_LIMIT: u64 = 500_000;
+const FORK_PRE_VERIFICATION_GAS: u64 = 100_000;
+
+/// How often the bundler is asked whether a UserOperation was included.
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Bundler for the chain traded live. Forks bundle UserOperations themselves.
+    bundler_url: String,
+    entry_point: Address,
+
+    /// Smart account that executes the swap and holds the traded funds, must expose SimpleAccount-style
+    /// `execute` and `executeBatch`.
+    sender: Address,
+
+    /// Paymaster address followed by any paymaster specific data.
+    paymaster_and_data: Bytes,
+}
+
+/// A call made by the smart account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub to: Address,
+    pub value: U256,
+    pub input: Bytes,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UserOperation {
+    sender: Address,
+    nonce: U256,
+    init_code: Bytes,
+    call_data: Bytes,
+    call_gas_limit: U256,
+    verification_gas_limit: U256,
+    pre_verification_gas: U256,
+    max_fee_per_gas: U256,
+    max_priority_fee_per_gas: U256,
+    paymaster_and_data: Bytes,
+    signature: Bytes,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GasEstimate {
+    call_gas_limit: U256,
+    verification_gas_limit: U256,
+    pre_verification_gas: U256,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserOperationReceipt {
+    success: bool,
+
+    /// Emitted by this UserOperation, the bundle may contain others.
+    logs: Vec<Log>,
+
+    /// Of the bundle transaction.
+    receipt: TransactionReceipt,
+}
+
+impl UserOperationReceipt {
+    /// The bundle's receipt, with only this UserOperation's logs.
+    fn into_receipt(mut self) -> TransactionReceipt {
+        match &mut self.receipt.inner {
+            ReceiptEnvelope::Legacy(r)
+            | ReceiptEnvelope::Eip2930(r)
+            | ReceiptEnvelope::Eip1559(r)
+            | ReceiptEnvelope::Eip4844(r)
+            | ReceiptEnvelope::Eip7702(r) => r.receipt.logs = self.logs,
+        }
+        self.receipt
+    }
+}
+
+impl Config {
+    /// The smart account, which holds the funds traded.
+    pub fn sender(&self) -> Address {
+        self.sender
+    }
+
+    /// Submits `calls` from the smart account, in order, as a UserOperation to the bundler, signed
+    /// by `owner`, returning the receipt of the bundle that included it. The bundler must serve
+    /// the chain `provider` reads from.
+    pub async fn execute(
+        &self,
+        provider: &impl Provider,
+        owner: &PrivateKeySigner,
+        calls: Vec<Call>,
+        timeout: Duration,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let bundler = ProviderBuilder::new().on_http(self.bundler_url.parse()?);
+        let chain_id = provider.get_chain_id().await?;
+        let mut op = self.user_operation(provider, calls).await?;
+
+        let gas: GasEstimate = bundler
+            .raw_request(
+                "eth_estimateUserOperationGas".into(),
+                (op.clone(), self.entry_point),
+            )
+            .await?;
+        op.call_gas_limit = gas.call_gas_limit;
+        op.verification_gas_limit = gas.verification_gas_limit;
+        op.pre_verification_gas = gas.pre_verification_gas;
+
+        // Signed like `eth_sign`, as SimpleAccount expects.
+        let hash = op.hash(self.entry_point, chain_id);
+        op.signature = owner.sign_message_sync(hash.as_slice())?.as_bytes().into();
+
+        let op_hash: B256 = bundler
+            .raw_request("eth_sendUserOperation".into(), (op, self.entry_point))
+            .await?;
+        log::info!("Submitted UserOperation {op_hash} with paymaster");
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let receipt: Option<UserOperationReceipt> = bundler
+                .raw_request("eth_getUserOperationReceipt".into(), (op_hash,))
+                .await?;
+            if let Some(receipt) = receipt {
+                anyhow::ensure!(
+                    receipt.success,
+                    "UserOperation {op_hash} reverted in {}",
+                    receipt.receipt.transaction_hash
+                );
+                return Ok(receipt.into_receipt());
+            }
+            anyhow::ensure!(
+                tokio::time::Instant::now() < deadline,
+                "UserOperation {op_hash} not included in time"
+            );
+            tokio::time::sleep(RECEIPT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Executes the UserOperation on a fork, which has no bundler, by having `account` submit it to
+    /// the EntryPoint itself. `account` must own the smart account and be one of the fork's dev
+    /// accounts, as it signs with the fork's `eth_sign`.
+    pub async fn execute_on_fork(
+        &self,
+        provider: &impl Provider,
+        account: Address,
+        calls: Vec<Call>,
+        timeout: Duration,
+    ) -> anyhow::Result<TransactionReceipt> {
+        let chain_id = provider.get_chain_id().await?;
+        let mut op = self.user_operation(provider, calls).await?;
+        op.call_gas_limit = U256::from(FORK_CALL_GAS_LIMIT);
+        op.verification_gas_limit = U256::from(FORK_VERIFICATION_GAS_LIMIT);
+        op.pre_verification_gas = U256::from(FORK_PRE_VERIFICATION_GAS);
+
+        let hash = op.hash(self.entry_point, chain_id);
+        op.signature = provider
+            .raw_request("eth_sign".into(), (account, hash))
+            .await?;
+
+        let entry_point = EntryPoint::new(self.entry_point, provider);
+        let pending = entry_point
+            .handleOps(vec![op.into()], account)
+            .from(account)
+            .send()
+            .await?;
+        log::info!("Submitted UserOperation with paymaster to the fork's EntryPoint");
+        let receipt = execution::confirm(provider, pending, timeout).await?;
+
+        // The bundle succeeds even if the UserOperation reverts.
+        let event = receipt
+            .inner
+            .logs()
+            .iter()
+            .find_map(|log| log.log_decode::<EntryPoint::UserOperationEvent>().ok())
+            .context("No UserOperationEvent in the bundle")?
+            .inner
+            .data;
+        anyhow::ensure!(
+            event.success,
+            "UserOperation reverted in {}",
+            receipt.transaction_hash
+        );
+        Ok(receipt)
+    }
+
+    /// The UserOperation making `calls` from the smart account, without gas limits or a signature.
+    async fn user_operation(
+        &self,
+        provider: &impl Provider,
+        calls: Vec<Call>,
+    ) -> anyhow::Result<UserOperation> {
+        let entry_point = EntryPoint::new(self.entry_point, provider);
+        let nonce = entry_point
+            .getNonce(self.sender, Default::default())
+            .call()
+            .await?
+            .nonce;
+        let fees = provider.estimate_eip1559_fees(None).await?;
+
+        Ok(UserOperation {
+            sender: self.sender,
+            nonce,
+            init_code: Bytes::new(),
+            call_data: call_data(calls)?,
+            call_gas_limit: U256::ZERO,
+            verification_gas_limit: U256::ZERO,
+            pre_verification_gas: U256::ZERO,
+            max_fee_per_gas: U256::from(fees.max_fee_per_gas),
+            max_priority_fee_per_gas: U256::from(fees.max_priority_fee_per_gas),
+            paymaster_and_data: self.paymaster_and_data.clone(),
+            signature: DUMMY_SIGNATURE.parse()?,
+        })
+    }
+}
+
+/// Calldata for the smart account to make `calls`, batched if there are several. Batched calls
+/// can't send value, as `executeBatch` has none.
+fn call_data(mut calls: Vec<Call>) -> anyhow::Result<Bytes> {
+    if calls.len() == 1 {
+        let Call { to, value, input } = calls.remove(0);
+        return Ok(SimpleAccount::executeCall {
+            dest: to,
+            value,
+            func: input,
+        }
+        .abi_encode()
+        .into());
+    }
+    anyhow::ensure!(
+        calls.iter().all(|c| c.value.is_zero()),
+        "Can't send value in a batch of calls"
+    );
+    let (dest, func) = calls.into_iter().map(|c| (c.to, c.input)).unzip();
+    Ok(SimpleAccount::executeBatchCall { dest, func }
+        .abi_encode()
+        .into())
+}
+
+impl UserOperation {
+    fn hash(&self, entry_point: Address, chain_id: u64) -> B256 {
+        let packed = (
+            self.sender,
+            self.nonce,
+            keccak256(&self.init_code),
+            keccak256(&self.call_data),
+            self.call_gas_limit,
+            self.verification_gas_limit,
+            self.pre_verification_gas,
+            self.max_fee_per_gas,
+            self.max_priority_fee_per_gas,
+            keccak256(&self.paymaster_and_data),
+        )
+            .abi_encode();
+
+        keccak256((keccak256(packed), entry_point, U256::from(chain_id)).abi_encode())
+    }
+}
+
+impl From<UserOperation> for EntryPoint::UserOperation {
+    fn from(op: UserOperation) -> Self {
+        EntryPoint::UserOperation {
+            sender: op.sender,
+            nonce: op.nonce,
+            initCode: op.init_code,
+            callData: op.call_data,
+            callGasLimit: op.call_gas_limit,
+            verificationGasLimit: op.verification_gas_limit,
+            preVerificationGas: op.pre_verification_gas,
+            maxFeePerGas: op.max_fee_per_gas,
+            maxPriorityFeePerGas: op.max_priority_fee_per_gas,
+            paymasterAndData: op.paymaster_and_data,
+            signature: op.signature,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, b256, PrimitiveSignature};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::mock_rpc::{self, MockRpc};
+
+    const ENTRY_POINT: Address = address!("5FF137D4b0FDCD49DcA30c7CF57E578a026d2789");
+    const SENDER: Address = address!("1111111111111111111111111111111111111111");
+    const ROUTER: Address = address!("68b3465833fb72A70ecDF485E0e4C7bD8665Fc45");
+    const POOL: Address = address!("88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640");
+    const OP_HASH: B256 = b256!("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    const BUNDLE_HASH: B256 =
+        b256!("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb");
+
+    /// Answers as both the chain and its bundler.
+    fn bundler(method: &str, _: &Value) -> Result<Value, String> {
+        let op_log = mock_rpc::log(POOL, &[OP_HASH], &[]);
+        Ok(match method {
+            "eth_chainId" => json!("0x1"),
+            // The account's nonce.
+            "eth_call" => json!(B256::ZERO),
+            "eth_feeHistory" => json!({
+                "oldestBlock": "0x1",
+                "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00"],
+                "gasUsedRatio": [0.5],
+                "reward": [["0x5f5e100"]],
+            }),
+            "eth_estimateUserOperationGas" => json!({
+                "callGasLimit": "0x30d40",
+                "verificationGasLimit": "0x186a0",
+                "preVerificationGas": "0xc350",
+            }),
+            "eth_sendUserOperation" => json!(OP_HASH),
+            "eth_getUserOperationReceipt" => json!({
+                "userOpHash": OP_HASH,
+                "sender": SENDER,
+                "success": true,
+                "logs": [op_log.clone()],
+                "receipt": mock_rpc::receipt(
+                    BUNDLE_HASH,
+                    vec![op_log, mock_rpc::log(ENTRY_POINT, &[OP_HASH], &[])],
+                ),
+            }),
+            method => return Err(format!("Unexpected {method}")),
+        })
+    }
+
+    #[tokio::test]
+    async fn user_operation_receipt_comes_from_the_bundler() {
+        let rpc = MockRpc::serve(bundler).await;
+        let config = Config {
+            bundler_url: rpc.url.clone(),
+            entry_point: ENTRY_POINT,
+            sender: SENDER,
+            paymaster_and_data: "0x2222222222222222222222222222222222222222abcd"
+                .parse()
+                .unwrap(),
+        };
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+        let owner = PrivateKeySigner::random();
+        let swap = Bytes::from_static(&[1, 2, 3, 4]);
+
+        let receipt = config
+            .execute(
+                &provider,
+                &owner,
+                vec![Call {
+                    to: ROUTER,
+                    value: U256::from(5),
+                    input: swap.clone(),
+                }],
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(receipt.transaction_hash, BUNDLE_HASH);
+        // Only the UserOperation's own logs, not the rest of the bundle's.
+        let logs = receipt.inner.logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].address(), POOL);
+
+        let [sent] = &rpc.params("eth_sendUserOperation")[..] else {
+            panic!("Expected one UserOperation sent");
+        };
+        let entry_point: Address = serde_json::from_value(sent[1].clone()).unwrap();
+        assert_eq!(entry_point, ENTRY_POINT);
+
+        let op: UserOperation = serde_json::from_value(sent[0].clone()).unwrap();
+        assert_eq!(op.sender, SENDER);
+        assert_eq!(op.paymaster_and_data, config.paymaster_and_data);
+        assert_eq!(op.call_gas_limit, U256::from(200_000));
+
+        let call = SimpleAccount::executeCall::abi_decode(&op.call_data, true).unwrap();
+        assert_eq!(
+            (call.dest, call.value, call.func),
+            (ROUTER, U256::from(5), swap)
+        );
+
+        let signature = PrimitiveSignature::try_from(op.signature.as_ref()).unwrap();
+        let signer = signature
+            .recover_address_from_msg(op.hash(ENTRY_POINT, 1))
+            .unwrap();
+        assert_eq!(signer, owner.address());
+    }
+
+    #[test]
+    fn several_calls_are_batched() {
+        const TOKEN: Address = address!("A0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48");
+        let approve = Bytes::from_static(&[5, 6]);
+        let swap = Bytes::from_static(&[1, 2, 3, 4]);
+        let calls = vec![
+            Call {
+                to: TOKEN,
+                value: U256::ZERO,
+                input: approve.clone(),
+            },
+            Call {
+                to: ROUTER,
+                value: U256::ZERO,
+                input: swap.clone(),
+            },
+        ];
+
+        let data = call_data(calls.clone()).unwrap();
+        let batch = SimpleAccount::executeBatchCall::abi_decode(&data, true).unwrap();
+        assert_eq!(batch.dest, [TOKEN, ROUTER]);
+        assert_eq!(batch.func, [approve, swap]);
+
+        let mut with_value = calls;
+        with_value[1].value = U256::from(1);
+        assert!(call_data(with_value).is_err());
+    }
+}
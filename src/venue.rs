@@ -0,0 +1,111 @@
+//! Where swaps are executed, so a trade that fails on Uniswap can be retried elsewhere.
+
+use alloy::{eips::BlockId, providers::RootProvider};
+use anyhow::Context;
+use uniswap_sdk_core::prelude::*;
+use uniswap_v3_sdk::prelude::*;
+
+use crate::pool;
+
+/// A SwapRouter02 compatible router and the factory of the pools it swaps through.
+#[async_trait::async_trait(?Send)]
+pub trait Venue {
+    fn name(&self) -> String;
+
+    fn router(&self) -> anyhow::Result<Address>;
+
+    /// The venue's pool for the pair, with tick data as of `block`.
+    async fn pool(
+        &self,
+        provider: &RootProvider,
+        base: &Currency,
+        quote: &Currency,
+        fee: FeeAmount,
+        block: u64,
+    ) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>>;
+}
+
+/// The canonical Uniswap V3 deployment.
+pub struct Uniswap {
+    pub chain_id: u64,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Venue for Uniswap {
+    fn name(&self) -> String {
+        "Uniswap V3".to_string()
+    }
+
+    fn router(&self) -> anyhow::Result<Address> {
+        let chain_id = self.chain_id;
+        SWAP_ROUTER_02_ADDRESSES
+            .get(&chain_id)
+            .copied()
+            .context(format!("Unknown swap router for chain id {chain_id}"))
+    }
+
+    async fn pool(
+        &self,
+        provider: &RootProvider,
+        base: &Currency,
+        quote: &Currency,
+        fee: FeeAmount,
+        block: u64,
+    ) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+        pool::fetch(
+            self.chain_id,
+            base,
+            quote,
+            fee,
+            provider,
+            BlockId::from(block),
+        )
+        .await
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub router: Address,
+    pub factory: Address,
+}
+
+/// Another Uniswap V3 deployment, e.g. a fork of it.
+// TODO(shelbyd): Pool addresses are derived with Uniswap's init code hash, deployments using a
+// different one aren't supported.
+pub struct Deployment {
+    pub chain_id: u64,
+    pub config: Config,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Venue for Deployment {
+    fn name(&self) -> String {
+        format!("router {}", self.config.router)
+    }
+
+    fn router(&self) -> anyhow::Result<Address> {
+        Ok(self.config.router)
+    }
+
+    async fn pool(
+        &self,
+        provider: &RootProvider,
+        base: &Currency,
+        quote: &Currency,
+        fee: FeeAmount,
+        block: u64,
+    ) -> anyhow::Result<Pool<EphemeralTickMapDataProvider>> {
+        pool::fetch_from(
+            self.chain_id,
+            self.config.factory,
+            base,
+            quote,
+            fee,
+            provider,
+            BlockId::from(block),
+        )
+        .await
+    }
+}
@@ -0,0 +1,187 @@
+//! Alerting when the account's balances run low, independent of what the strategy does.
+
+use alloy::{eips::BlockId, providers::Provider, transports::http::reqwest};
+use uniswap_sdk_core::prelude::*;
+
+use crate::{
+    execution::{balance, from_human_amount, PrecisionLoss},
+    strategy::FractionInput,
+};
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Account whose balances are watched.
+    account: Address,
+
+    /// Alert when the balance drops below these, in human units.
+    base: Option<FractionInput>,
+    quote: Option<FractionInput>,
+
+    /// Also POST alerts here as `{"text": ...}`, besides logging them.
+    webhook: Option<reqwest::Url>,
+}
+
+/// Fires an alert once each time a balance crosses below its watermark.
+pub struct Watermarks {
+    config: Config,
+    client: reqwest::Client,
+
+    /// Whether the base and quote balances were below their watermarks at the last check.
+    below: [bool; 2],
+}
+
+impl Watermarks {
+    pub fn new(config: Config) -> Self {
+        Watermarks {
+            config,
+            client: reqwest::Client::new(),
+            below: [false; 2],
+        }
+    }
+
+    /// How many RPC calls a `check` makes.
+    pub fn calls(&self) -> usize {
+        [&self.config.base, &self.config.quote]
+            .iter()
+            .filter(|w| w.is_some())
+            .count()
+    }
+
+    /// Alerts when a balance at `block_id` falls below its watermark.
+    pub async fn check(
+        &mut self,
+        provider: &impl Provider,
+        base: &Currency,
+        quote: &Currency,
+        precision_loss: PrecisionLoss,
+        block_id: BlockId,
+    ) -> anyhow::Result<()> {
+        let account = self.config.account;
+        let sides = [(self.config.base, base), (self.config.quote, quote)];
+
+        for (i, (watermark, currency)) in sides.into_iter().enumerate() {
+            let Some(watermark) = watermark else {
+                continue;
+            };
+            let watermark = from_human_amount(watermark.into(), currency, precision_loss)?;
+            let balance = CurrencyAmount::from_raw_amount(
+                currency.clone(),
+                balance(provider, account, currency, block_id)
+                    .await?
+                    .to_big_int(),
+            )?;
+            let symbol = currency.symbol().map_or("???", |v| v);
+
+            let below = balance.quotient() < watermark.quotient();
+            match (self.below[i], below) {
+                (false, true) => {
+                    self.alert(&format!(
+                        "{account} has {} {symbol}, below low watermark {}",
+                        balance.to_exact(),
+                        watermark.to_exact()
+                    ))
+                    .await
+                }
+                (true, false) => log::info!(
+                    "{account} has {} {symbol}, back above low watermark {}",
+                    balance.to_exact(),
+                    watermark.to_exact()
+                ),
+                _ => {}
+            }
+            self.below[i] = below;
+        }
+
+        Ok(())
+    }
+
+    /// Logs `message`, and sends it to the webhook if there is one. Failing to reach the webhook
+    /// is logged rather than stopping trading.
+    async fn alert(&self, message: &str) {
+        log::error!("LOW BALANCE: {message}");
+
+        let Some(webhook) = &self.config.webhook else {
+            return;
+        };
+        let body = serde_json::json!({ "text": message }).to_string();
+        let sent = self
+            .client
+            .post(webhook.clone())
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = sent {
+            log::warn!("Sending low balance alert to {webhook} failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use alloy::{
+        primitives::{Bytes, U256},
+        providers::ProviderBuilder,
+        sol_types::SolValue,
+    };
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        mock_rpc::MockRpc,
+        strategy::testing::{usdc, weth},
+    };
+
+    #[tokio::test]
+    async fn crossing_the_watermark_alerts_once() {
+        // USDC balances at each check, against a watermark of 5.
+        let mut balances = [10, 4, 3, 2, 8, 1].into_iter();
+        let rpc = MockRpc::serve(move |method, _| match method {
+            "eth_call" => {
+                let raw = U256::from(balances.next().unwrap() * 1_000_000);
+                Ok(json!(Bytes::from(raw.abi_encode())))
+            }
+            method => Err(format!("Unexpected {method}")),
+        })
+        .await;
+        let alerts = Arc::new(AtomicUsize::new(0));
+        let received = alerts.clone();
+        let webhook = MockRpc::serve(move |_, _| {
+            received.fetch_add(1, Ordering::Relaxed);
+            Ok(json!(null))
+        })
+        .await;
+        let provider = ProviderBuilder::new().on_http(rpc.url.parse().unwrap());
+
+        let config = serde_json::from_value(json!({
+            "account": Address::repeat_byte(0xaa),
+            "base": 5,
+            "webhook": webhook.url,
+        }))
+        .unwrap();
+        let mut watermarks = Watermarks::new(config);
+        let mut alerts_after_each_check = Vec::new();
+        for _ in 0..6 {
+            watermarks
+                .check(
+                    &provider,
+                    &usdc(),
+                    &weth(),
+                    PrecisionLoss::Error,
+                    BlockId::latest(),
+                )
+                .await
+                .unwrap();
+            alerts_after_each_check.push(alerts.load(Ordering::Relaxed));
+        }
+        // Staying below doesn't alert again, recovering and dropping again is a new crossing.
+        assert_eq!(alerts_after_each_check, [0, 1, 1, 1, 1, 2]);
+    }
+}